@@ -2,16 +2,13 @@ use better_any::Tid;
 
 use stateman::Resources;
 
-#[cfg_attr(feature = "debug", derive(Debug))]
-#[derive(Tid)]
+#[derive(Debug, Tid)]
 struct A(u32);
 
-#[cfg_attr(feature = "debug", derive(Debug))]
-#[derive(Tid)]
+#[derive(Debug, Tid)]
 struct B(u32);
 
-#[cfg_attr(feature = "debug", derive(Debug))]
-#[derive(Tid)]
+#[derive(Debug, Tid)]
 struct C<'a>(&'a A);
 
 fn main() {
@@ -22,6 +19,8 @@ fn main() {
     resources.insert(A(1));
     resources.insert(B(2));
     resources.insert(C(&owned_a));
+    resources.register_debug::<A>();
+    resources.register_debug::<B>();
 
     // We can validly have two mutable borrows from the `Resources` map!
     let mut a = resources.borrow_mut::<A>();
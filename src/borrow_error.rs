@@ -0,0 +1,369 @@
+use std::fmt;
+
+use rt_map::{BorrowFail, Cell};
+
+#[cfg(feature = "track_borrows")]
+use crate::borrow_location::BorrowTracker;
+use crate::{label::SlotKey, BorrowLocation, Label, TypeNameLit};
+
+/// Mode in which a resource is borrowed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BorrowMode {
+    /// Immutable borrow, see [Ref][crate::Ref].
+    Shared,
+    /// Mutable borrow, see [RefMut][crate::RefMut].
+    Exclusive,
+}
+
+impl BorrowMode {
+    pub(crate) fn adverb(self) -> &'static str {
+        match self {
+            Self::Shared => "immutably",
+            Self::Exclusive => "mutably",
+        }
+    }
+}
+
+/// Current borrow of a resource, see
+/// [`Resources::borrow_state`][crate::Resources::borrow_state].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BorrowState {
+    /// The resource is not borrowed.
+    Unborrowed,
+    /// The resource is borrowed immutably by the given number of [`Ref`]s.
+    ///
+    /// Borrows of a raw cell from
+    /// [`Resources::get_raw`][crate::Resources::get_raw] are not counted.
+    ///
+    /// [`Ref`]: crate::Ref
+    Shared(usize),
+    /// The resource is borrowed mutably.
+    Exclusive,
+}
+
+/// Error when borrowing a resource fails.
+///
+/// Every variant carries the `label` of the requested resource if it is a
+/// named resource, see [`Resources::insert_named`][crate::Resources::insert_named].
+///
+/// With the `"track_borrows"` feature, conflicts also report where the
+/// conflicting borrow was taken. Without it, `held_at` is always `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BorrowError {
+    /// The requested resource does not exist.
+    NotFound {
+        type_name: TypeNameLit,
+        label: Option<Label>,
+        mode: BorrowMode,
+    },
+    /// The requested resource is already borrowed immutably.
+    ConflictShared {
+        type_name: TypeNameLit,
+        label: Option<Label>,
+        mode: BorrowMode,
+        /// Where the conflicting borrow was taken, which is only recorded
+        /// with the `"track_borrows"` feature.
+        held_at: Option<BorrowLocation>,
+    },
+    /// The requested resource is already borrowed mutably.
+    ConflictExclusive {
+        type_name: TypeNameLit,
+        label: Option<Label>,
+        mode: BorrowMode,
+        /// Where the conflicting borrow was taken, which is only recorded
+        /// with the `"track_borrows"` feature.
+        held_at: Option<BorrowLocation>,
+    },
+    /// The requested resource is derived from other resources and can't be
+    /// borrowed mutably, see
+    /// [`Resources::insert_derived`][crate::Resources::insert_derived].
+    ReadOnly {
+        type_name: TypeNameLit,
+        label: Option<Label>,
+    },
+    /// The requested resource would be derived from itself, directly or
+    /// through other derived resources, see
+    /// [`Resources::insert_derived`][crate::Resources::insert_derived].
+    DerivedCycle {
+        type_name: TypeNameLit,
+        label: Option<Label>,
+    },
+    /// Waiting for the requested resource would deadlock, because a thread
+    /// holding it waits for a resource held by the current thread, see
+    /// [`SyncResources::set_deadlock_detection`][crate::SyncResources::set_deadlock_detection].
+    Deadlock {
+        type_name: TypeNameLit,
+        label: Option<Label>,
+        mode: BorrowMode,
+    },
+    /// A panic occurred while the requested resource was borrowed mutably, so
+    /// it may be in an inconsistent state. The value can still be borrowed
+    /// with [`Resources::try_borrow_poisoned`][crate::Resources::try_borrow_poisoned].
+    Poisoned {
+        type_name: TypeNameLit,
+        label: Option<Label>,
+        mode: BorrowMode,
+    },
+    /// The value stored for the requested resource has a different type,
+    /// because it was inserted with
+    /// [`Resources::insert_raw`][crate::Resources::insert_raw] under the id of
+    /// another type.
+    TypeMismatch {
+        type_name: TypeNameLit,
+        label: Option<Label>,
+        mode: BorrowMode,
+    },
+    /// The requested resource can't be borrowed mutably within a
+    /// [Transaction][crate::Transaction], because it is not registered with
+    /// [`Resources::register_clone`][crate::Resources::register_clone].
+    NotRegistered {
+        type_name: TypeNameLit,
+        label: Option<Label>,
+    },
+}
+
+impl BorrowError {
+    /// Returns the error for a borrow in `mode` which conflicts with a
+    /// current borrow in `held_mode`.
+    pub(crate) fn conflict(
+        type_name: TypeNameLit,
+        label: Option<Label>,
+        mode: BorrowMode,
+        held_mode: BorrowMode,
+        held_at: Option<BorrowLocation>,
+    ) -> Self {
+        match held_mode {
+            BorrowMode::Shared => Self::ConflictShared {
+                type_name,
+                label,
+                mode,
+                held_at,
+            },
+            BorrowMode::Exclusive => Self::ConflictExclusive {
+                type_name,
+                label,
+                mode,
+                held_at,
+            },
+        }
+    }
+
+    /// Converts the `rt_map` failure to borrow `cell`, which holds the
+    /// resource with `key`, into an error.
+    ///
+    /// `rt_map` does not report whether a conflicting borrow is shared or
+    /// exclusive when borrowing mutably, so `cell` is probed for that.
+    pub(crate) fn from_fail<V>(
+        borrow_fail: BorrowFail,
+        cell: Option<&Cell<V>>,
+        key: SlotKey,
+        type_name: TypeNameLit,
+        mode: BorrowMode,
+        #[cfg(feature = "track_borrows")] borrows: Option<&BorrowTracker>,
+    ) -> Self {
+        let held_mode = match borrow_fail {
+            BorrowFail::ValueNotFound => {
+                return Self::NotFound {
+                    type_name,
+                    label: key.1,
+                    mode,
+                }
+            }
+            BorrowFail::BorrowConflictImm => BorrowMode::Exclusive,
+            BorrowFail::BorrowConflictMut => {
+                if matches!(cell.map(Cell::try_borrow), Some(Ok(_))) {
+                    BorrowMode::Shared
+                } else {
+                    BorrowMode::Exclusive
+                }
+            }
+        };
+
+        #[cfg(feature = "track_borrows")]
+        let held_at = borrows.and_then(|borrows| borrows.held_at(&key, held_mode));
+        #[cfg(not(feature = "track_borrows"))]
+        let held_at = None;
+
+        Self::conflict(type_name, key.1, mode, held_mode, held_at)
+    }
+
+    /// Returns the type name of the requested resource.
+    pub fn type_name(&self) -> TypeNameLit {
+        match self {
+            Self::NotFound { type_name, .. }
+            | Self::ConflictShared { type_name, .. }
+            | Self::ConflictExclusive { type_name, .. }
+            | Self::ReadOnly { type_name, .. }
+            | Self::DerivedCycle { type_name, .. }
+            | Self::Deadlock { type_name, .. }
+            | Self::Poisoned { type_name, .. }
+            | Self::TypeMismatch { type_name, .. }
+            | Self::NotRegistered { type_name, .. } => *type_name,
+        }
+    }
+
+    /// Returns the label of the requested resource, if it is a named resource.
+    pub fn label(&self) -> Option<&Label> {
+        match self {
+            Self::NotFound { label, .. }
+            | Self::ConflictShared { label, .. }
+            | Self::ConflictExclusive { label, .. }
+            | Self::ReadOnly { label, .. }
+            | Self::DerivedCycle { label, .. }
+            | Self::Deadlock { label, .. }
+            | Self::Poisoned { label, .. }
+            | Self::TypeMismatch { label, .. }
+            | Self::NotRegistered { label, .. } => label.as_ref(),
+        }
+    }
+
+    /// Returns the mode in which the resource was requested.
+    pub fn mode(&self) -> BorrowMode {
+        match self {
+            Self::NotFound { mode, .. }
+            | Self::ConflictShared { mode, .. }
+            | Self::ConflictExclusive { mode, .. }
+            | Self::Deadlock { mode, .. }
+            | Self::Poisoned { mode, .. }
+            | Self::TypeMismatch { mode, .. } => *mode,
+            Self::ReadOnly { .. } | Self::NotRegistered { .. } => BorrowMode::Exclusive,
+            Self::DerivedCycle { .. } => BorrowMode::Shared,
+        }
+    }
+
+    /// Returns where the conflicting borrow was taken, if it was recorded.
+    ///
+    /// Borrows are only recorded with the `"track_borrows"` feature, so this
+    /// always returns `None` without it.
+    pub fn held_at(&self) -> Option<&BorrowLocation> {
+        match self {
+            Self::NotFound { .. }
+            | Self::ReadOnly { .. }
+            | Self::DerivedCycle { .. }
+            | Self::Deadlock { .. }
+            | Self::Poisoned { .. }
+            | Self::TypeMismatch { .. }
+            | Self::NotRegistered { .. } => None,
+            Self::ConflictShared { held_at, .. } | Self::ConflictExclusive { held_at, .. } => {
+                held_at.as_ref()
+            }
+        }
+    }
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let type_name = ResourceName {
+            type_name: self.type_name(),
+            label: self.label(),
+        };
+        let requested = self.mode().adverb();
+        match self {
+            Self::NotFound { .. } => {
+                write!(f, "Expected to borrow `{type_name}`, but it does not exist.")?
+            }
+            Self::ConflictShared { .. } => write!(
+                f,
+                "Expected to borrow `{type_name}` {requested}, but it was already borrowed immutably."
+            )?,
+            Self::ConflictExclusive { .. } => write!(
+                f,
+                "Expected to borrow `{type_name}` {requested}, but it was already borrowed mutably."
+            )?,
+            Self::ReadOnly { .. } => write!(
+                f,
+                "Expected to borrow `{type_name}` {requested}, but it is derived from other resources."
+            )?,
+            Self::DerivedCycle { .. } => write!(
+                f,
+                "Expected to borrow `{type_name}` {requested}, but it would be derived from itself."
+            )?,
+            Self::Deadlock { .. } => write!(
+                f,
+                "Expected to borrow `{type_name}` {requested}, but waiting for it would deadlock."
+            )?,
+            Self::Poisoned { .. } => write!(
+                f,
+                "Expected to borrow `{type_name}` {requested}, but it was poisoned by a panic."
+            )?,
+            Self::TypeMismatch { .. } => write!(
+                f,
+                "Expected to borrow `{type_name}` {requested}, but a value of another type is stored under its id."
+            )?,
+            Self::NotRegistered { .. } => write!(
+                f,
+                "Expected to borrow `{type_name}` {requested}, but it is not registered with `Resources::register_clone`."
+            )?,
+        }
+        match self.held_at() {
+            Some(held_at) => write!(f, " It is currently held {held_at}."),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for BorrowError {}
+
+/// Name of a requested resource, formatted as `Type["label"]` if it is named.
+struct ResourceName<'a> {
+    type_name: TypeNameLit,
+    label: Option<&'a Label>,
+}
+
+impl fmt::Display for ResourceName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.label {
+            Some(label) => write!(f, "{}[{label:?}]", self.type_name),
+            None => write!(f, "{}", self.type_name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Label, TypeNameLit};
+
+    use super::{BorrowError, BorrowMode};
+
+    #[test]
+    fn display_names_requested_and_existing_mode() {
+        let borrow_error = BorrowError::ConflictShared {
+            type_name: TypeNameLit::of::<u32>(),
+            label: None,
+            mode: BorrowMode::Exclusive,
+            held_at: None,
+        };
+
+        assert_eq!(
+            "Expected to borrow `u32` mutably, but it was already borrowed immutably.",
+            borrow_error.to_string()
+        );
+    }
+
+    #[test]
+    fn accessors_return_variant_fields() {
+        let borrow_error = BorrowError::NotFound {
+            type_name: TypeNameLit::of::<u32>(),
+            label: None,
+            mode: BorrowMode::Shared,
+        };
+
+        assert_eq!(TypeNameLit::of::<u32>(), borrow_error.type_name());
+        assert_eq!(None, borrow_error.label());
+        assert_eq!(BorrowMode::Shared, borrow_error.mode());
+    }
+
+    #[test]
+    fn display_names_label_of_named_resource() {
+        let borrow_error = BorrowError::NotFound {
+            type_name: TypeNameLit::of::<u32>(),
+            label: Some(Label::from("left")),
+            mode: BorrowMode::Shared,
+        };
+
+        assert_eq!(
+            r#"Expected to borrow `u32["left"]`, but it does not exist."#,
+            borrow_error.to_string()
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::{backtrace::Backtrace, fmt, panic::Location, sync::Arc};
+
+use crate::BorrowMode;
+
+/// Location at which a resource is currently borrowed.
+///
+/// This is only recorded with the `"track_borrows"` feature.
+#[derive(Clone, Debug)]
+pub struct BorrowLocation {
+    mode: BorrowMode,
+    location: &'static Location<'static>,
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+impl BorrowLocation {
+    /// Returns the mode of the borrow.
+    pub fn mode(&self) -> BorrowMode {
+        self.mode
+    }
+
+    /// Returns the source location at which the borrow was taken.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Returns the backtrace of the borrow, if one was captured.
+    ///
+    /// Backtraces are captured according to [`Backtrace::capture`], i.e. only
+    /// if `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` is set.
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_deref()
+    }
+}
+
+impl fmt::Display for BorrowLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.mode.adverb(), self.location)
+    }
+}
+
+/// Only the borrow mode and source location are compared.
+impl PartialEq for BorrowLocation {
+    fn eq(&self, other: &Self) -> bool {
+        self.mode == other.mode && self.location == other.location
+    }
+}
+
+impl Eq for BorrowLocation {}
+
+#[cfg(feature = "track_borrows")]
+pub(crate) use tracker::{BorrowRecord, BorrowTracker};
+
+#[cfg(feature = "track_borrows")]
+mod tracker {
+    use std::{
+        backtrace::{Backtrace, BacktraceStatus},
+        collections::HashMap,
+        panic::Location,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use crate::{label::SlotKey, BorrowMode};
+
+    use super::BorrowLocation;
+
+    type Held = HashMap<SlotKey, Vec<(u64, BorrowLocation)>>;
+
+    /// Keeps track of where the live borrows of each resource were taken.
+    #[derive(Default)]
+    pub(crate) struct BorrowTracker {
+        next_id: AtomicU64,
+        held: Mutex<Held>,
+    }
+
+    impl BorrowTracker {
+        /// Records a borrow of the resource with `key` at the caller's
+        /// location.
+        #[track_caller]
+        pub(crate) fn record(&self, key: SlotKey, mode: BorrowMode) -> BorrowRecord<'_> {
+            self.record_at(key, mode, Location::caller())
+        }
+
+        /// Records a borrow of the resource with `key` at `location`, for
+        /// borrows which are completed later, e.g. by a future.
+        pub(crate) fn record_at(
+            &self,
+            key: SlotKey,
+            mode: BorrowMode,
+            location: &'static Location<'static>,
+        ) -> BorrowRecord<'_> {
+            let backtrace = Backtrace::capture();
+            let backtrace = match backtrace.status() {
+                BacktraceStatus::Captured => Some(Arc::new(backtrace)),
+                _ => None,
+            };
+            self.insert(
+                key,
+                BorrowLocation {
+                    mode,
+                    location,
+                    backtrace,
+                },
+            )
+        }
+
+        /// Returns the most recent live borrow of the resource with `key` in
+        /// the given `mode`.
+        pub(crate) fn held_at(&self, key: &SlotKey, mode: BorrowMode) -> Option<BorrowLocation> {
+            self.lock()
+                .get(key)?
+                .iter()
+                .rev()
+                .map(|(_, borrow_location)| borrow_location)
+                .find(|borrow_location| borrow_location.mode == mode)
+                .cloned()
+        }
+
+        fn insert(&self, key: SlotKey, borrow_location: BorrowLocation) -> BorrowRecord<'_> {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            self.lock()
+                .entry(key.clone())
+                .or_default()
+                .push((id, borrow_location));
+
+            BorrowRecord {
+                tracker: self,
+                key,
+                id,
+            }
+        }
+
+        fn lock(&self) -> std::sync::MutexGuard<'_, Held> {
+            // The map stays consistent even if a panic occurred while it was
+            // locked, so poisoning can be ignored.
+            self.held.lock().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    /// Live borrow recorded in a [BorrowTracker], removed again on drop.
+    pub(crate) struct BorrowRecord<'a> {
+        tracker: &'a BorrowTracker,
+        key: SlotKey,
+        id: u64,
+    }
+
+    impl<'a> Clone for BorrowRecord<'a> {
+        fn clone(&self) -> Self {
+            let borrow_location = self
+                .tracker
+                .lock()
+                .get(&self.key)
+                .and_then(|held| held.iter().find(|(id, _)| *id == self.id))
+                .map(|(_, borrow_location)| borrow_location.clone())
+                .expect("Expected borrow record to exist while it is alive.");
+
+            self.tracker.insert(self.key.clone(), borrow_location)
+        }
+    }
+
+    impl<'a> Drop for BorrowRecord<'a> {
+        fn drop(&mut self) {
+            let mut held = self.tracker.lock();
+            if let Some(borrows) = held.get_mut(&self.key) {
+                borrows.retain(|(id, _)| *id != self.id);
+                if borrows.is_empty() {
+                    held.remove(&self.key);
+                }
+            }
+        }
+    }
+}
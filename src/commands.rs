@@ -0,0 +1,218 @@
+use std::{any::TypeId, fmt};
+
+use crate::{Resource, Resources};
+
+type Command<'a> = Box<dyn FnOnce(&mut Resources<'a>) + Send + 'a>;
+
+/// Buffer of structural changes to [Resources], which are applied later.
+///
+/// Inserting and removing resources requires `&mut Resources`, so code that
+/// only borrows the resources records these changes in a `Commands` buffer
+/// instead. The buffer is handed to [`Resources::push_commands`] and applied
+/// in FIFO order by [`Resources::apply_commands`].
+///
+/// `Commands` is `Send`, so multiple threads can record into their own
+/// buffers, which are then merged with [`Commands::append`].
+///
+/// ## Examples
+///
+/// ```rust
+/// use better_any::Tid;
+/// use stateman::{Commands, Resources};
+///
+/// #[derive(Debug, Tid)]
+/// struct Spawned(u32);
+///
+/// #[derive(Debug, Tid)]
+/// struct Pending;
+///
+/// let mut resources = Resources::default();
+/// resources.insert(Pending);
+///
+/// let mut commands = Commands::new();
+/// commands.insert(Spawned(1));
+/// commands.remove::<Pending>();
+/// commands.push(|resources| resources.borrow_mut::<Spawned>().0 += 1);
+/// resources.push_commands(commands);
+///
+/// resources.apply_commands();
+/// assert_eq!(2, resources.borrow::<Spawned>().0);
+/// assert!(!resources.contains::<Pending>());
+/// ```
+#[derive(Default)]
+pub struct Commands<'a> {
+    commands: Vec<Command<'a>>,
+}
+
+impl<'a> Commands<'a> {
+    /// Returns an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records inserting the resource `r`.
+    pub fn insert<R>(&mut self, r: R)
+    where
+        R: Resource<'a>,
+    {
+        self.push(move |resources| {
+            resources.insert(r);
+        });
+    }
+
+    /// Records inserting an already boxed resource.
+    pub fn insert_raw(&mut self, type_id: TypeId, resource: Box<dyn Resource<'a>>) {
+        self.push(move |resources| {
+            resources.insert_raw(type_id, resource);
+        });
+    }
+
+    /// Records removing the resource of type `R`.
+    pub fn remove<R>(&mut self)
+    where
+        R: Resource<'a>,
+    {
+        self.push(|resources| {
+            resources.remove::<R>();
+        });
+    }
+
+    /// Records running `f` with mutable access to the resources.
+    pub fn push<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Resources<'a>) + Send + 'a,
+    {
+        self.commands.push(Box::new(f));
+    }
+
+    /// Moves all commands of `other` to the end of `self`, leaving `other`
+    /// empty.
+    pub fn append(&mut self, other: &mut Self) {
+        self.commands.append(&mut other.commands);
+    }
+
+    /// Returns the number of recorded commands.
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Returns true if no commands are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Applies the recorded commands to `resources` in the order in which
+    /// they were recorded.
+    pub fn apply(self, resources: &mut Resources<'a>) {
+        self.commands
+            .into_iter()
+            .for_each(|command| command(resources));
+    }
+}
+
+impl<'a> fmt::Debug for Commands<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Commands")
+            .field("len", &self.commands.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{any::TypeId, thread};
+
+    use better_any::Tid;
+
+    use crate::Resources;
+
+    use super::Commands;
+
+    #[test]
+    fn commands_are_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Commands<'static>>();
+    }
+
+    #[test]
+    fn apply_runs_commands_in_order() {
+        let mut resources = Resources::default();
+        let mut commands = Commands::new();
+        commands.insert(A(1));
+        commands.push(|resources| resources.borrow_mut::<A>().0 *= 10);
+        commands.remove::<A>();
+        commands.insert_raw(TypeId::of::<A>(), Box::new(A(2)));
+        commands.push(|resources| resources.borrow_mut::<A>().0 += 1);
+        assert_eq!(5, commands.len());
+
+        commands.apply(&mut resources);
+
+        assert_eq!(&A(3), &*resources.borrow::<A>());
+    }
+
+    #[test]
+    fn append_merges_buffers() {
+        let mut first = Commands::new();
+        first.insert(A(1));
+        let mut second = Commands::new();
+        second.push(|resources| resources.borrow_mut::<A>().0 += 1);
+
+        first.append(&mut second);
+
+        assert_eq!(2, first.len());
+        assert!(second.is_empty());
+    }
+
+    #[test]
+    fn commands_recorded_on_threads_are_applied() {
+        let mut resources = Resources::default();
+        resources.insert(A(0));
+
+        let buffers = thread::scope(|scope| {
+            let handles = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut commands = Commands::new();
+                        commands.push(|resources| resources.borrow_mut::<A>().0 += 1);
+                        commands
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        let mut commands = Commands::new();
+        buffers
+            .into_iter()
+            .for_each(|mut buffer| commands.append(&mut buffer));
+        resources.push_commands(commands);
+        assert_eq!(&A(0), &*resources.borrow::<A>());
+
+        resources.apply_commands();
+
+        assert_eq!(&A(4), &*resources.borrow::<A>());
+    }
+
+    #[test]
+    fn commands_pushed_while_applying_are_kept_for_next_apply() {
+        let mut resources = Resources::default();
+        let mut commands = Commands::new();
+        commands.push(|resources| {
+            let mut commands = Commands::new();
+            commands.insert(A(1));
+            resources.push_commands(commands);
+        });
+        resources.push_commands(commands);
+
+        resources.apply_commands();
+        assert!(!resources.contains::<A>());
+
+        resources.apply_commands();
+        assert!(resources.contains::<A>());
+    }
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct A(u32);
+}
@@ -0,0 +1,305 @@
+use std::{any::TypeId, cell::RefCell};
+
+use crate::{BorrowError, Resource, Resources};
+
+/// A function computing a resource of type `Out` from the resources `I`, see
+/// [`Resources::insert_derived`].
+///
+/// This is implemented for functions taking references to the resources of
+/// tuples of up to 8 types, e.g. `Fn(&A, &B) -> Out` for `(A, B)`.
+/// Closures need to annotate their parameter types, e.g.
+/// `|a: &A, b: &B| Out(a.0 + b.0)`.
+pub trait Derive<'a, Out, I>: Send + 'a {
+    /// Returns the ids of the input resources.
+    fn inputs() -> Vec<TypeId>;
+
+    /// Borrows the inputs from `resources` and computes the output.
+    fn derive(&self, resources: &Resources<'a>) -> Result<Out, BorrowError>;
+}
+
+macro_rules! impl_derive {
+    ($($ty:ident),+) => {
+        impl<'a, Out, Func, $($ty),+> Derive<'a, Out, ($($ty,)+)> for Func
+        where
+            Func: Fn($(&$ty),+) -> Out + Send + 'a,
+            $($ty: Resource<'a>),+
+        {
+            fn inputs() -> Vec<TypeId> {
+                vec![$($ty::id()),+]
+            }
+
+            #[allow(non_snake_case)]
+            fn derive(&self, resources: &Resources<'a>) -> Result<Out, BorrowError> {
+                $(let $ty = resources.try_borrow::<$ty>()?;)+
+                Ok(self($(&$ty),+))
+            }
+        }
+    };
+}
+
+impl_derive!(A);
+impl_derive!(A, B);
+impl_derive!(A, B, C);
+impl_derive!(A, B, C, D);
+impl_derive!(A, B, C, D, E);
+impl_derive!(A, B, C, D, E, F);
+impl_derive!(A, B, C, D, E, F, G);
+impl_derive!(A, B, C, D, E, F, G, H);
+
+type DeriveFn<'a> =
+    Box<dyn Fn(&Resources<'a>) -> Result<Box<dyn Resource<'a>>, BorrowError> + Send + 'a>;
+
+/// A resource which is recomputed when its inputs change.
+pub(crate) struct Derived<'a> {
+    inputs: Vec<TypeId>,
+    derive: DeriveFn<'a>,
+    /// Versions of the inputs at the last computation.
+    versions: RefCell<Vec<u64>>,
+}
+
+impl<'a> Derived<'a> {
+    pub(crate) fn new<Out, I, F>(f: F) -> Self
+    where
+        Out: Resource<'a>,
+        F: Derive<'a, Out, I>,
+    {
+        Self {
+            inputs: F::inputs(),
+            derive: Box::new(move |resources| {
+                f.derive(resources)
+                    .map(|out| Box::new(out) as Box<dyn Resource<'a>>)
+            }),
+            versions: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn inputs(&self) -> &[TypeId] {
+        &self.inputs
+    }
+
+    /// Returns true if `versions` differ from the versions of the last
+    /// computation.
+    pub(crate) fn is_stale(&self, versions: &[u64]) -> bool {
+        *self.versions.borrow() != versions
+    }
+
+    /// Computes the output and remembers `versions` as the versions of its
+    /// inputs.
+    pub(crate) fn derive(
+        &self,
+        resources: &Resources<'a>,
+        versions: Vec<u64>,
+    ) -> Result<Box<dyn Resource<'a>>, BorrowError> {
+        let out = (self.derive)(resources)?;
+        *self.versions.borrow_mut() = versions;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        any::TypeId,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+    };
+
+    use better_any::Tid;
+
+    use crate::{BorrowError, BorrowMode, BorrowState, Ref, Resources, TypeNameLit};
+
+    fn resources(computations: &Arc<AtomicU32>) -> Resources<'static> {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        resources.insert(B(2));
+        let computed = Arc::clone(computations);
+        resources
+            .insert_derived::<Sum, (A, B)>(move |a: &A, b: &B| {
+                computed.fetch_add(1, Ordering::SeqCst);
+                Sum(a.0 + b.0)
+            })
+            .unwrap();
+        resources
+    }
+
+    #[test]
+    fn output_is_recomputed_only_when_inputs_change() {
+        let computations = Arc::new(AtomicU32::new(0));
+        let resources = resources(&computations);
+
+        assert_eq!(&Sum(3), &*resources.borrow::<Sum>());
+        assert_eq!(&Sum(3), &*resources.borrow::<Sum>());
+        assert_eq!(1, computations.load(Ordering::SeqCst));
+
+        drop(resources.borrow_mut::<A>());
+        assert_eq!(&Sum(3), &*resources.borrow::<Sum>());
+        assert_eq!(1, computations.load(Ordering::SeqCst));
+
+        resources.borrow_mut::<A>().0 = 2;
+        resources.borrow_mut::<B>().0 = 3;
+        assert_eq!(&Sum(5), &*resources.borrow::<Sum>());
+        assert_eq!(2, computations.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn borrows_of_output_are_counted() {
+        let computations = Arc::new(AtomicU32::new(0));
+        let resources = resources(&computations);
+
+        let sum = resources.borrow::<Sum>();
+        let sum_clone = Ref::clone(&sum);
+        assert_eq!(
+            Some(BorrowState::Shared(2)),
+            resources.borrow_state::<Sum>()
+        );
+        assert_eq!(Some(BorrowState::Unborrowed), resources.borrow_state::<A>());
+        drop((sum, sum_clone));
+
+        assert_eq!(
+            Some(BorrowState::Unborrowed),
+            resources.borrow_state::<Sum>()
+        );
+    }
+
+    #[test]
+    fn output_is_read_only() {
+        let computations = Arc::new(AtomicU32::new(0));
+        let resources = resources(&computations);
+
+        assert_eq!(
+            Err(BorrowError::ReadOnly {
+                type_name: TypeNameLit::of::<Sum>(),
+                label: None,
+            }),
+            resources.try_borrow_mut::<Sum>()
+        );
+    }
+
+    #[test]
+    fn stale_output_conflicts_while_borrowed() {
+        let computations = Arc::new(AtomicU32::new(0));
+        let resources = resources(&computations);
+
+        let sum = resources.borrow::<Sum>();
+        resources.borrow_mut::<A>().0 = 2;
+        assert!(matches!(
+            resources.try_borrow::<Sum>(),
+            Err(BorrowError::ConflictShared { .. })
+        ));
+        drop(sum);
+
+        assert_eq!(&Sum(4), &*resources.borrow::<Sum>());
+    }
+
+    #[test]
+    fn output_is_not_writable_without_borrowing() {
+        let computations = Arc::new(AtomicU32::new(0));
+        let mut resources = resources(&computations);
+
+        assert!(matches!(
+            resources.try_entry::<Sum>(),
+            Err(BorrowError::ReadOnly { .. })
+        ));
+        assert!(resources.get_mut::<Sum>().is_none());
+        assert!(resources.get_resource_mut(TypeId::of::<Sum>()).is_none());
+        assert!(resources.get_raw(&TypeId::of::<Sum>()).is_none());
+    }
+
+    #[test]
+    fn missing_input_is_reported() {
+        let computations = Arc::new(AtomicU32::new(0));
+        let mut resources = resources(&computations);
+
+        resources.remove::<B>();
+
+        assert_eq!(
+            Err(BorrowError::NotFound {
+                type_name: TypeNameLit::of::<B>(),
+                label: None,
+                mode: BorrowMode::Shared,
+            }),
+            resources.try_borrow::<Sum>()
+        );
+    }
+
+    #[test]
+    fn insert_replaces_derived_output() {
+        let computations = Arc::new(AtomicU32::new(0));
+        let mut resources = resources(&computations);
+
+        resources.insert(Sum(0));
+        resources.borrow_mut::<A>().0 = 2;
+
+        assert_eq!(&Sum(0), &*resources.borrow::<Sum>());
+        resources.borrow_mut::<Sum>().0 = 1;
+        assert_eq!(1, computations.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn chained_output_is_recomputed_when_inputs_of_input_change() {
+        let computations = Arc::new(AtomicU32::new(0));
+        let mut resources = resources(&computations);
+        resources
+            .insert_derived::<Doubled, (Sum,)>(|sum: &Sum| Doubled(sum.0 * 2))
+            .unwrap();
+
+        assert_eq!(&Doubled(6), &*resources.borrow::<Doubled>());
+        resources.borrow_mut::<A>().0 = 2;
+
+        assert_eq!(&Doubled(8), &*resources.borrow::<Doubled>());
+        assert_eq!(2, computations.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let computations = Arc::new(AtomicU32::new(0));
+        let mut resources = resources(&computations);
+
+        assert_eq!(
+            Err(BorrowError::DerivedCycle {
+                type_name: TypeNameLit::of::<A>(),
+                label: None,
+            }),
+            resources.insert_derived::<A, (A,)>(|a: &A| A(a.0 + 1))
+        );
+        assert_eq!(
+            Err(BorrowError::DerivedCycle {
+                type_name: TypeNameLit::of::<A>(),
+                label: None,
+            }),
+            resources.insert_derived::<A, (Sum,)>(|sum: &Sum| A(sum.0))
+        );
+        assert_eq!(&A(1), &*resources.borrow::<A>());
+    }
+
+    #[test]
+    fn swap_rejects_cycles() {
+        let computations = Arc::new(AtomicU32::new(0));
+        let mut resources = resources(&computations);
+        let mut other = Resources::default();
+        other.insert(Sum(1));
+        other
+            .insert_derived::<A, (Sum,)>(|sum: &Sum| A(sum.0))
+            .unwrap();
+
+        assert!(matches!(
+            resources.swap::<Sum>(&mut other),
+            Err(BorrowError::DerivedCycle { .. })
+        ));
+        assert_eq!(&Sum(3), &*resources.borrow::<Sum>());
+    }
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct A(u32);
+
+    #[derive(Debug, Tid)]
+    struct B(u32);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct Sum(u32);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct Doubled(u32);
+}
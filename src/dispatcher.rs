@@ -0,0 +1,619 @@
+use std::{any::TypeId, collections::HashMap, fmt};
+
+use crate::{BorrowError, Resources, System, SystemData};
+
+/// Runs [System]s on a [Resources] map.
+///
+/// Systems are grouped into stages according to the resources they access.
+/// Systems within a stage don't conflict with each other and run in parallel,
+/// while the stages themselves run in order.
+///
+/// Use [DispatcherBuilder] to create a `Dispatcher`.
+pub struct Dispatcher<'a, 'd> {
+    systems: Vec<Box<dyn RunSystem<'a> + 'd>>,
+    stages: Vec<Vec<usize>>,
+}
+
+impl<'a, 'd> Dispatcher<'a, 'd> {
+    /// Returns a builder for a `Dispatcher`.
+    pub fn builder() -> DispatcherBuilder<'a, 'd> {
+        DispatcherBuilder::new()
+    }
+
+    /// Returns the names of the systems of each stage, in execution order.
+    pub fn stages(&self) -> Vec<Vec<&'static str>> {
+        self.stages
+            .iter()
+            .map(|stage| {
+                stage
+                    .iter()
+                    .map(|&index| self.systems[index].name())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Runs all systems on `resources`.
+    ///
+    /// See [`try_dispatch`] for a non-panicking version of this function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resources of a system can't be fetched.
+    ///
+    /// [`try_dispatch`]: Self::try_dispatch
+    pub fn dispatch(&mut self, resources: &Resources<'a>) {
+        self.try_dispatch(resources)
+            .unwrap_or_else(|borrow_error| panic!("{borrow_error}"))
+    }
+
+    /// Runs all systems on `resources`.
+    ///
+    /// The resources of all systems of a stage are fetched before any of them
+    /// is run, so if fetching fails, none of the systems of that stage and the
+    /// following stages are run.
+    pub fn try_dispatch(&mut self, resources: &Resources<'a>) -> Result<(), BorrowError> {
+        let mut systems = self.systems.iter_mut().map(Some).collect::<Vec<_>>();
+        for stage in &self.stages {
+            let mut runs = Vec::with_capacity(stage.len());
+            for &index in stage {
+                let system = systems[index]
+                    .take()
+                    .expect("Expected each system to be in a single stage.");
+                runs.push(system.try_fetch(resources)?);
+            }
+
+            if runs.len() == 1 {
+                runs.into_iter().for_each(|run| run());
+            } else {
+                std::thread::scope(|scope| {
+                    runs.into_iter().for_each(|run| {
+                        scope.spawn(run);
+                    });
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Builder for a [Dispatcher].
+///
+/// ## Examples
+///
+/// ```rust
+/// use better_any::Tid;
+/// use stateman::{DispatcherBuilder, Read, Resources, System, Write};
+///
+/// #[derive(Debug, Tid)]
+/// struct Input(u32);
+///
+/// #[derive(Debug, Tid)]
+/// struct Position(u32);
+///
+/// struct Movement;
+///
+/// impl<'a> System<'a> for Movement {
+///     type SystemData<'r> = (Read<'r, 'a, Input>, Write<'r, 'a, Position>) where 'a: 'r;
+///
+///     fn run(&mut self, (input, mut position): Self::SystemData<'_>) {
+///         position.0 += input.0;
+///     }
+/// }
+///
+/// let mut resources = Resources::default();
+/// resources.insert(Input(2));
+/// resources.insert(Position(0));
+///
+/// let mut dispatcher = DispatcherBuilder::new()
+///     .with("movement", Movement)
+///     .build()
+///     .unwrap();
+///
+/// dispatcher.dispatch(&resources);
+/// assert_eq!(2, resources.borrow::<Position>().0);
+/// ```
+#[derive(Default)]
+pub struct DispatcherBuilder<'a, 'd> {
+    systems: Vec<Box<dyn RunSystem<'a> + 'd>>,
+    constraints: Vec<(&'static str, &'static str)>,
+}
+
+impl<'a, 'd> DispatcherBuilder<'a, 'd> {
+    /// Creates an empty `DispatcherBuilder`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a system with the given unique `name`.
+    ///
+    /// Systems which conflict with each other run in the order they were
+    /// added, unless constrained otherwise.
+    pub fn with<S>(mut self, name: &'static str, system: S) -> Self
+    where
+        S: System<'a> + 'd,
+    {
+        self.systems.push(Box::new(NamedSystem { name, system }));
+        self
+    }
+
+    /// Constrains the system `name` to run before the system `other`.
+    pub fn before(mut self, name: &'static str, other: &'static str) -> Self {
+        self.constraints.push((name, other));
+        self
+    }
+
+    /// Constrains the system `name` to run after the system `other`.
+    pub fn after(mut self, name: &'static str, other: &'static str) -> Self {
+        self.constraints.push((other, name));
+        self
+    }
+
+    /// Computes the schedule and builds the [Dispatcher].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a system name is used twice, a constraint refers
+    /// to an unknown system, or the constraints contain a cycle.
+    pub fn build(self) -> Result<Dispatcher<'a, 'd>, DispatcherError> {
+        let mut indices = HashMap::with_capacity(self.systems.len());
+        for (index, system) in self.systems.iter().enumerate() {
+            if indices.insert(system.name(), index).is_some() {
+                return Err(DispatcherError::DuplicateSystem(system.name()));
+            }
+        }
+
+        let index_of = |name: &'static str| {
+            indices
+                .get(name)
+                .copied()
+                .ok_or(DispatcherError::UnknownSystem(name))
+        };
+        let mut predecessors = vec![Vec::new(); self.systems.len()];
+        for &(first, second) in &self.constraints {
+            predecessors[index_of(second)?].push(index_of(first)?);
+        }
+
+        let order = Self::order(&self.systems, &predecessors)?;
+        let stages = Self::stages(&self.systems, &predecessors, &order);
+
+        Ok(Dispatcher {
+            systems: self.systems,
+            stages,
+        })
+    }
+
+    /// Sorts the systems topologically according to the explicit
+    /// constraints, preferring the order in which they were added.
+    fn order(
+        systems: &[Box<dyn RunSystem<'a> + 'd>],
+        predecessors: &[Vec<usize>],
+    ) -> Result<Vec<usize>, DispatcherError> {
+        let mut order = Vec::with_capacity(systems.len());
+        let mut done = vec![false; systems.len()];
+
+        while order.len() < systems.len() {
+            let next = (0..systems.len()).find(|&index| {
+                !done[index] && predecessors[index].iter().all(|&other| done[other])
+            });
+
+            match next {
+                Some(index) => {
+                    done[index] = true;
+                    order.push(index);
+                }
+                None => {
+                    let cycle = Self::cycle(predecessors, &done)
+                        .into_iter()
+                        .map(|index| systems[index].name())
+                        .collect();
+                    return Err(DispatcherError::Cycle(cycle));
+                }
+            }
+        }
+
+        Ok(order)
+    }
+
+    /// Returns the systems of the first cycle among the systems which are not
+    /// `done`, i.e. their first strongly connected component which contains a
+    /// cycle.
+    ///
+    /// Systems which merely depend on the cycle are not included.
+    fn cycle(predecessors: &[Vec<usize>], done: &[bool]) -> Vec<usize> {
+        let mut successors = vec![Vec::new(); predecessors.len()];
+        for (index, predecessors) in predecessors.iter().enumerate() {
+            for &predecessor in predecessors {
+                successors[predecessor].push(index);
+            }
+        }
+
+        (0..predecessors.len())
+            .filter(|&index| !done[index])
+            .find_map(|start| {
+                let ancestors = Self::reachable(start, predecessors, done);
+                if !ancestors[start] {
+                    return None;
+                }
+
+                let descendants = Self::reachable(start, &successors, done);
+                Some(
+                    (0..predecessors.len())
+                        .filter(|&index| ancestors[index] && descendants[index])
+                        .collect(),
+                )
+            })
+            .expect("Expected the unfinished systems to contain a cycle.")
+    }
+
+    /// Returns which systems are reachable from `start` in at least one step
+    /// along `edges`, skipping systems which are `done`.
+    fn reachable(start: usize, edges: &[Vec<usize>], done: &[bool]) -> Vec<bool> {
+        let mut reached = vec![false; edges.len()];
+        let mut pending = vec![start];
+        while let Some(index) = pending.pop() {
+            for &next in &edges[index] {
+                if !done[next] && !reached[next] {
+                    reached[next] = true;
+                    pending.push(next);
+                }
+            }
+        }
+
+        reached
+    }
+
+    /// Assigns each system to the first stage after all of its explicit
+    /// predecessors and all earlier systems it conflicts with.
+    fn stages(
+        systems: &[Box<dyn RunSystem<'a> + 'd>],
+        predecessors: &[Vec<usize>],
+        order: &[usize],
+    ) -> Vec<Vec<usize>> {
+        let accesses: Vec<_> = systems.iter().map(|system| system.accesses()).collect();
+        let mut stage_of = vec![0; systems.len()];
+        let mut stages: Vec<Vec<usize>> = Vec::new();
+
+        for (position, &index) in order.iter().enumerate() {
+            let after_predecessors = predecessors[index].iter().map(|&other| stage_of[other] + 1);
+            let after_conflicts = order[..position]
+                .iter()
+                .filter(|&&other| accesses[index].conflicts_with(&accesses[other]))
+                .map(|&other| stage_of[other] + 1);
+            let stage = after_predecessors.chain(after_conflicts).max().unwrap_or(0);
+
+            stage_of[index] = stage;
+            if stages.len() <= stage {
+                stages.resize_with(stage + 1, Vec::new);
+            }
+            stages[stage].push(index);
+        }
+
+        stages
+    }
+}
+
+/// Error when building a [Dispatcher] fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DispatcherError {
+    /// Two systems were added with the same name.
+    DuplicateSystem(&'static str),
+    /// A constraint refers to a system which was not added.
+    UnknownSystem(&'static str),
+    /// The constraints between these systems contain a cycle.
+    Cycle(Vec<&'static str>),
+}
+
+impl fmt::Display for DispatcherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateSystem(name) => write!(f, "System `{name}` was added twice."),
+            Self::UnknownSystem(name) => write!(f, "System `{name}` does not exist."),
+            Self::Cycle(names) => write!(
+                f,
+                "The ordering constraints of the systems `{}` contain a cycle.",
+                names.join("`, `")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DispatcherError {}
+
+/// Resources read and written by a system.
+struct Accesses {
+    reads: Vec<TypeId>,
+    writes: Vec<TypeId>,
+}
+
+impl Accesses {
+    fn conflicts_with(&self, other: &Self) -> bool {
+        self.writes
+            .iter()
+            .any(|id| other.reads.contains(id) || other.writes.contains(id))
+            || other.writes.iter().any(|id| self.reads.contains(id))
+    }
+}
+
+/// Object safe version of [System].
+trait RunSystem<'a>: Send {
+    fn name(&self) -> &'static str;
+
+    fn accesses(&self) -> Accesses;
+
+    /// Fetches the resources of the system and returns a closure which runs
+    /// it on them.
+    fn try_fetch<'r>(
+        &'r mut self,
+        resources: &'r Resources<'a>,
+    ) -> Result<Box<dyn FnOnce() + Send + 'r>, BorrowError>;
+}
+
+struct NamedSystem<S> {
+    name: &'static str,
+    system: S,
+}
+
+impl<'a, S> RunSystem<'a> for NamedSystem<S>
+where
+    S: System<'a>,
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn accesses(&self) -> Accesses {
+        fn accesses<'r, 'a: 'r, S: System<'a>>() -> Accesses {
+            Accesses {
+                reads: S::SystemData::<'r>::reads(),
+                writes: S::SystemData::<'r>::writes(),
+            }
+        }
+
+        accesses::<S>()
+    }
+
+    fn try_fetch<'r>(
+        &'r mut self,
+        resources: &'r Resources<'a>,
+    ) -> Result<Box<dyn FnOnce() + Send + 'r>, BorrowError> {
+        let data = resources.try_fetch::<S::SystemData<'r>>()?;
+        let system = &mut self.system;
+
+        Ok(Box::new(move || system.run(data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+
+    use better_any::Tid;
+
+    use crate::{Read, Resources, System, Write};
+
+    use super::{DispatcherBuilder, DispatcherError};
+
+    #[test]
+    fn systems_with_disjoint_writes_share_a_stage() {
+        let dispatcher = DispatcherBuilder::new()
+            .with("write_a", WriteA)
+            .with("write_b", WriteB)
+            .with("read_a", ReadA)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            vec![vec!["write_a", "write_b"], vec!["read_a"]],
+            dispatcher.stages()
+        );
+    }
+
+    #[test]
+    fn readers_share_a_stage() {
+        let dispatcher = DispatcherBuilder::new()
+            .with("read_a_0", ReadA)
+            .with("read_a_1", ReadA)
+            .build()
+            .unwrap();
+
+        assert_eq!(vec![vec!["read_a_0", "read_a_1"]], dispatcher.stages());
+    }
+
+    #[test]
+    fn explicit_constraints_order_systems() {
+        let dispatcher = DispatcherBuilder::new()
+            .with("write_a", WriteA)
+            .with("write_b", WriteB)
+            .with("read_a", ReadA)
+            .before("write_b", "write_a")
+            .after("read_a", "write_b")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            vec![vec!["write_b"], vec!["write_a"], vec!["read_a"]],
+            dispatcher.stages()
+        );
+    }
+
+    #[test]
+    fn build_reports_cycle() {
+        let result = DispatcherBuilder::new()
+            .with("write_a", WriteA)
+            .with("write_b", WriteB)
+            .with("read_a", ReadA)
+            .before("write_a", "write_b")
+            .before("write_b", "write_a")
+            .after("read_a", "write_b")
+            .build();
+
+        assert_eq!(
+            Some(DispatcherError::Cycle(vec!["write_a", "write_b"])),
+            result.err()
+        );
+    }
+
+    #[test]
+    fn build_reports_only_systems_of_cycle() {
+        let result = DispatcherBuilder::new()
+            .with("read_a", ReadA)
+            .with("write_a", WriteA)
+            .with("write_b", WriteB)
+            .with("copy_a_to_b", CopyAToB)
+            .before("write_b", "copy_a_to_b")
+            .before("copy_a_to_b", "write_b")
+            .after("read_a", "copy_a_to_b")
+            .after("write_a", "read_a")
+            .build();
+
+        assert_eq!(
+            Some(DispatcherError::Cycle(vec!["write_b", "copy_a_to_b"])),
+            result.err()
+        );
+    }
+
+    #[test]
+    fn build_reports_unknown_and_duplicate_systems() {
+        let unknown = DispatcherBuilder::new()
+            .with("write_a", WriteA)
+            .after("write_a", "write_b")
+            .build();
+        let duplicate = DispatcherBuilder::new()
+            .with("write_a", WriteA)
+            .with("write_a", WriteA)
+            .build();
+
+        assert_eq!(
+            Some(DispatcherError::UnknownSystem("write_b")),
+            unknown.err()
+        );
+        assert_eq!(
+            Some(DispatcherError::DuplicateSystem("write_a")),
+            duplicate.err()
+        );
+    }
+
+    #[test]
+    fn dispatch_runs_systems_in_stage_order() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        resources.insert(B(2));
+
+        let mut dispatcher = DispatcherBuilder::new()
+            .with("write_a", WriteA)
+            .with("write_b", WriteB)
+            .with("copy_a_to_b", CopyAToB)
+            .build()
+            .unwrap();
+        dispatcher.dispatch(&resources);
+
+        assert_eq!(A(2), *resources.borrow::<A>());
+        assert_eq!(B(2), *resources.borrow::<B>());
+    }
+
+    #[test]
+    fn dispatch_runs_stage_in_parallel() {
+        let mut resources = Resources::default();
+        resources.insert(A(0));
+        resources.insert(B(0));
+
+        // Both systems wait for each other, which only finishes if they run
+        // concurrently.
+        let barrier = Arc::new(Barrier::new(2));
+        let mut dispatcher = DispatcherBuilder::new()
+            .with("wait_a", Wait(barrier.clone(), WriteA))
+            .with("wait_b", Wait(barrier, WriteB))
+            .build()
+            .unwrap();
+        dispatcher.dispatch(&resources);
+
+        assert_eq!(A(1), *resources.borrow::<A>());
+        assert_eq!(B(1), *resources.borrow::<B>());
+    }
+
+    #[test]
+    fn try_dispatch_returns_err_if_resource_is_missing() {
+        let resources = Resources::default();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with("write_a", WriteA)
+            .build()
+            .unwrap();
+
+        assert!(dispatcher.try_dispatch(&resources).is_err());
+    }
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct A(usize);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct B(usize);
+
+    struct ReadA;
+
+    impl<'a> System<'a> for ReadA {
+        type SystemData<'r>
+            = Read<'r, 'a, A>
+        where
+            'a: 'r;
+
+        fn run(&mut self, _: Self::SystemData<'_>) {}
+    }
+
+    struct WriteA;
+
+    impl<'a> System<'a> for WriteA {
+        type SystemData<'r>
+            = Write<'r, 'a, A>
+        where
+            'a: 'r;
+
+        fn run(&mut self, mut a: Self::SystemData<'_>) {
+            a.0 += 1;
+        }
+    }
+
+    struct WriteB;
+
+    impl<'a> System<'a> for WriteB {
+        type SystemData<'r>
+            = Write<'r, 'a, B>
+        where
+            'a: 'r;
+
+        fn run(&mut self, mut b: Self::SystemData<'_>) {
+            b.0 += 1;
+        }
+    }
+
+    struct CopyAToB;
+
+    impl<'a> System<'a> for CopyAToB {
+        type SystemData<'r>
+            = (Read<'r, 'a, A>, Write<'r, 'a, B>)
+        where
+            'a: 'r;
+
+        fn run(&mut self, (a, mut b): Self::SystemData<'_>) {
+            b.0 = a.0;
+        }
+    }
+
+    struct Wait<S>(Arc<Barrier>, S);
+
+    impl<'a, S> System<'a> for Wait<S>
+    where
+        S: System<'a>,
+    {
+        type SystemData<'r>
+            = S::SystemData<'r>
+        where
+            'a: 'r;
+
+        fn run(&mut self, data: Self::SystemData<'_>) {
+            self.0.wait();
+            self.1.run(data);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::{any::TypeId, collections::HashMap};
+
+use crate::{resource, Resource, TypeNameLit};
+
+type CastRef<'a, T> = Box<dyn for<'r> Fn(&'r dyn Resource<'a>) -> &'r T + Send + 'a>;
+type CastMut<'a, T> = Box<dyn for<'r> Fn(&'r mut dyn Resource<'a>) -> &'r mut T + Send + 'a>;
+
+/// Casts of resource types to trait objects, keyed by the id of the trait
+/// object, see [`Resources::register_trait`][crate::Resources::register_trait].
+#[derive(Default)]
+pub(crate) struct TraitCasts<'a> {
+    casts: HashMap<TypeId, Box<dyn ErasedCasts<'a> + 'a>>,
+}
+
+impl<'a> TraitCasts<'a> {
+    /// Adds the casts of `R` to the trait object `T`, replacing earlier ones.
+    pub(crate) fn insert<R, T>(&mut self, cast_ref: fn(&R) -> &T, cast_mut: fn(&mut R) -> &mut T)
+    where
+        R: Resource<'a>,
+        T: ?Sized + 'static,
+    {
+        let casts = self
+            .casts
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(DynCasts::<T> { casts: Vec::new() }));
+        debug_assert_eq!(TypeId::of::<T>(), casts.trait_id());
+        // SAFETY: Only `DynCasts<'a, T>` is stored under the id of `T`, and
+        // `'a` is the lifetime of `self`, so the pointer has the type of the
+        // value.
+        let casts =
+            unsafe { &mut *(&mut **casts as *mut dyn ErasedCasts<'a> as *mut DynCasts<'a, T>) };
+        casts.insert(cast_ref, cast_mut);
+    }
+
+    /// Returns the casts to the trait object `T` in the order of their
+    /// registration.
+    pub(crate) fn iter<T>(&self) -> impl Iterator<Item = &DynCast<'a, T>>
+    where
+        T: ?Sized + 'static,
+    {
+        let casts = self.casts.get(&TypeId::of::<T>()).map(|casts| {
+            debug_assert_eq!(TypeId::of::<T>(), casts.trait_id());
+            // SAFETY: See `insert`.
+            unsafe { &*(&**casts as *const dyn ErasedCasts<'a> as *const DynCasts<'a, T>) }
+        });
+
+        casts.into_iter().flat_map(|casts| casts.casts.iter())
+    }
+}
+
+/// [DynCasts] with the type of the trait object erased.
+trait ErasedCasts<'a>: Send {
+    /// Returns the id of the trait object the casts are for.
+    fn trait_id(&self) -> TypeId;
+}
+
+impl<'a, T> ErasedCasts<'a> for DynCasts<'a, T>
+where
+    T: ?Sized + 'static,
+{
+    fn trait_id(&self) -> TypeId {
+        TypeId::of::<T>()
+    }
+}
+
+/// Casts of resource types to the trait object `T`.
+struct DynCasts<'a, T: ?Sized> {
+    casts: Vec<DynCast<'a, T>>,
+}
+
+/// Cast of a single resource type to the trait object `T`.
+pub(crate) struct DynCast<'a, T: ?Sized> {
+    pub(crate) type_id: TypeId,
+    pub(crate) type_name: TypeNameLit,
+    pub(crate) cast_ref: CastRef<'a, T>,
+    pub(crate) cast_mut: CastMut<'a, T>,
+}
+
+impl<'a, T> DynCasts<'a, T>
+where
+    T: ?Sized + 'static,
+{
+    /// Adds the casts of `R`, replacing earlier ones.
+    fn insert<R>(&mut self, cast_ref: fn(&R) -> &T, cast_mut: fn(&mut R) -> &mut T)
+    where
+        R: Resource<'a>,
+    {
+        self.casts.retain(|cast| cast.type_id != R::id());
+        self.casts.push(DynCast {
+            type_id: R::id(),
+            type_name: TypeNameLit::of::<R>(),
+            cast_ref: Box::new(move |resource| cast_ref(resource::downcast_ref(resource))),
+            cast_mut: Box::new(move |resource| cast_mut(resource::downcast_mut(resource))),
+        });
+    }
+}
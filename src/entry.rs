@@ -1,57 +1,353 @@
-use std::{any::TypeId, marker::PhantomData};
+use std::{
+    any::TypeId,
+    collections::{hash_map, HashMap},
+    marker::PhantomData,
+};
 
-use crate::{RefMut, Resource};
+use rt_map::Cell;
 
-pub struct Entry<'a, 'b, R> {
-    inner: rt_map::Entry<'a, TypeId, Box<dyn Resource<'b>>>,
-    marker: PhantomData<R>,
-}
+#[cfg(feature = "track_borrows")]
+use crate::{borrow_location::BorrowTracker, BorrowMode};
+use crate::{
+    derived::Derived,
+    label::SlotKey,
+    observers::{ChangeHook, Observers},
+    resource,
+    ticks::{TickRef, Ticks},
+    Ref, RefMut, Resource, TypeNameLit,
+};
+
+type ResourceCell<'b> = Cell<Box<dyn Resource<'b>>>;
 
-/// An entry to a resource container.
+/// An entry to a resource of type `R` in [Resources][crate::Resources], which
+/// is either occupied or vacant.
 ///
 /// This is similar to the Entry API found in the standard library.
 ///
 /// ## Examples
 ///
 /// ```rust
-/// use rt_map::RtMap;
+/// use better_any::Tid;
+/// use stateman::Resources;
 ///
-/// #[derive(Debug)]
-/// struct Res(i32);
+/// #[derive(Debug, Default, Tid)]
+/// struct Counter(u32);
 ///
-/// let mut rt_map = RtMap::<u32, Res>::default();
+/// let mut resources = Resources::default();
 ///
-/// let value = rt_map.entry(0).or_insert(Res(4));
-/// println!("{:?}", value.0 * 2);
+/// resources.entry::<Counter>().or_insert(Counter(4));
+/// resources
+///     .entry::<Counter>()
+///     .and_modify(|counter| counter.0 += 1)
+///     .or_default();
+///
+/// assert_eq!(5, resources.borrow::<Counter>().0);
 /// ```
+pub enum Entry<'a, 'b, R> {
+    /// The resource exists.
+    Occupied(OccupiedEntry<'a, 'b, R>),
+    /// The resource doesn't exist.
+    Vacant(VacantEntry<'a, 'b, R>),
+}
+
+/// An entry to an existing resource, see [Entry].
+pub struct OccupiedEntry<'a, 'b, R> {
+    inner: hash_map::OccupiedEntry<'a, TypeId, ResourceCell<'b>>,
+    tracking: Tracking<'a, 'b>,
+    marker: PhantomData<R>,
+}
+
+/// An entry to a missing resource, see [Entry].
+pub struct VacantEntry<'a, 'b, R> {
+    inner: hash_map::VacantEntry<'a, TypeId, ResourceCell<'b>>,
+    tracking: Tracking<'a, 'b>,
+    marker: PhantomData<R>,
+}
+
+/// Bookkeeping of [Resources][crate::Resources] which is updated when the
+/// entry is changed.
+pub(crate) struct Tracking<'a, 'b> {
+    pub(crate) ticks: &'a mut HashMap<SlotKey, Ticks>,
+    pub(crate) tick: u64,
+    pub(crate) derived: &'a mut HashMap<TypeId, Derived<'b>>,
+    pub(crate) observers: &'a Observers<'b>,
+    #[cfg(feature = "track_borrows")]
+    pub(crate) borrows: &'a BorrowTracker,
+}
+
+impl<'a, 'b> Tracking<'a, 'b> {
+    /// Resets the ticks of `R`, which is added at the current tick.
+    fn insert<R>(&mut self)
+    where
+        R: Resource<'b>,
+    {
+        self.derived.remove(&R::id());
+        self.ticks.insert(
+            (R::id(), None),
+            Ticks::new(self.tick, TypeNameLit::of::<R>()),
+        );
+    }
+
+    fn remove<R>(&mut self)
+    where
+        R: Resource<'b>,
+    {
+        self.derived.remove(&R::id());
+        self.ticks.remove(&(R::id(), None));
+    }
+
+    fn as_ref(&self) -> TrackingRef<'_, 'b> {
+        TrackingRef {
+            ticks: self.ticks,
+            tick: self.tick,
+            observers: self.observers,
+            #[cfg(feature = "track_borrows")]
+            borrows: self.borrows,
+        }
+    }
+
+    fn into_ref(self) -> TrackingRef<'a, 'b> {
+        TrackingRef {
+            ticks: self.ticks,
+            tick: self.tick,
+            observers: self.observers,
+            #[cfg(feature = "track_borrows")]
+            borrows: self.borrows,
+        }
+    }
+}
+
+/// Bookkeeping of [Resources][crate::Resources] which is attached to
+/// references returned by an entry.
+struct TrackingRef<'a, 'b> {
+    ticks: &'a HashMap<SlotKey, Ticks>,
+    tick: u64,
+    observers: &'a Observers<'b>,
+    #[cfg(feature = "track_borrows")]
+    borrows: &'a BorrowTracker,
+}
+
+impl<'a, 'b> TrackingRef<'a, 'b> {
+    /// Borrows `cell` of `R` mutably like
+    /// [`Resources::try_borrow_mut`][crate::Resources::try_borrow_mut], which
+    /// attaches its ticks, poison flag and change observers, and records the
+    /// borrow.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn borrow_mut<R>(self, cell: &'a ResourceCell<'b>) -> RefMut<'a, 'b, R>
+    where
+        R: Resource<'b>,
+    {
+        // The entry borrows the map mutably, so the cell can't be borrowed.
+        let inner = cell
+            .try_borrow_mut()
+            .unwrap_or_else(|_| unreachable!("Expected cell of entry to be unborrowed."));
+        let mut ref_mut = RefMut::<R>::new(rt_map::RefMut::new(inner));
+        if let Some(ticks) = self.ticks.get(&(R::id(), None)) {
+            ref_mut = ref_mut.with_ticks(TickRef {
+                ticks,
+                tick: self.tick,
+            });
+        }
+
+        let changes = self.observers.changes();
+        if changes.observes(R::id()) {
+            ref_mut = ref_mut.with_change_hook(ChangeHook::new(R::id(), cell, changes));
+        }
+
+        #[cfg(feature = "track_borrows")]
+        let ref_mut =
+            ref_mut.with_record(self.borrows.record((R::id(), None), BorrowMode::Exclusive));
+
+        ref_mut
+    }
+}
+
 impl<'a, 'b: 'a, R> Entry<'a, 'b, R>
 where
     R: Resource<'b>,
 {
-    /// Create new entry.
-    pub fn new(inner: rt_map::Entry<'a, TypeId, Box<dyn Resource<'b>>>) -> Self {
-        Self {
-            inner,
-            marker: PhantomData,
+    pub(crate) fn new(
+        inner: hash_map::Entry<'a, TypeId, ResourceCell<'b>>,
+        tracking: Tracking<'a, 'b>,
+    ) -> Self {
+        match inner {
+            hash_map::Entry::Occupied(inner) => Self::Occupied(OccupiedEntry {
+                inner,
+                tracking,
+                marker: PhantomData,
+            }),
+            hash_map::Entry::Vacant(inner) => Self::Vacant(VacantEntry {
+                inner,
+                tracking,
+                marker: PhantomData,
+            }),
         }
     }
 
+    /// Returns the type name of the resource of this entry.
+    pub fn key(&self) -> TypeNameLit {
+        TypeNameLit::of::<R>()
+    }
+
     /// Returns this entry's value, inserts and returns `v` otherwise.
     ///
     /// Please note that you should use `or_insert_with` in case the creation of
     /// the value is expensive.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
     pub fn or_insert(self, v: R) -> RefMut<'a, 'b, R> {
         self.or_insert_with(move || v)
     }
 
     /// Returns this entry's value, inserts and returns the return value of `f`
     /// otherwise.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
     pub fn or_insert_with<F>(self, f: F) -> RefMut<'a, 'b, R>
     where
         F: FnOnce() -> R,
     {
-        let inner = self.inner.or_insert_with(move || Box::new(f()));
+        self.or_insert_with_key(|_| f())
+    }
+
+    /// Returns this entry's value, inserts and returns the return value of `f`
+    /// otherwise, which is called with the type name of the resource.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn or_insert_with_key<F>(self, f: F) -> RefMut<'a, 'b, R>
+    where
+        F: FnOnce(TypeNameLit) -> R,
+    {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => {
+                let value = f(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// Returns this entry's value, inserts and returns the default value
+    /// otherwise.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn or_default(self) -> RefMut<'a, 'b, R>
+    where
+        R: Default,
+    {
+        self.or_insert_with(R::default)
+    }
+
+    /// Calls `f` with the value if the entry is occupied, which marks it as
+    /// changed and notifies its change observers.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut R),
+    {
+        if let Self::Occupied(entry) = &mut self {
+            f(&mut entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, 'b: 'a, R> OccupiedEntry<'a, 'b, R>
+where
+    R: Resource<'b>,
+{
+    /// Returns the type name of the resource of this entry.
+    pub fn key(&self) -> TypeNameLit {
+        TypeNameLit::of::<R>()
+    }
+
+    /// Returns a reference to the value.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn get(&self) -> Ref<'_, 'b, R> {
+        // The entry borrows the map mutably, so the cell can't be borrowed.
+        let inner = self
+            .inner
+            .get()
+            .try_borrow()
+            .unwrap_or_else(|_| unreachable!("Expected cell of entry to be unborrowed."));
+        let r#ref = Ref::<R>::new(rt_map::Ref::new(inner));
+        let r#ref = match self.tracking.ticks.get(&(R::id(), None)) {
+            Some(ticks) => r#ref.with_ticks(ticks),
+            None => r#ref,
+        };
+
+        #[cfg(feature = "track_borrows")]
+        let r#ref = r#ref.with_record(
+            self.tracking
+                .borrows
+                .record((R::id(), None), BorrowMode::Shared),
+        );
+
+        r#ref
+    }
+
+    /// Returns a mutable reference to the value like
+    /// [`Resources::borrow_mut`][crate::Resources::borrow_mut], which marks it
+    /// as changed when dereferenced mutably.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn get_mut(&mut self) -> RefMut<'_, 'b, R> {
+        self.tracking.as_ref().borrow_mut(self.inner.get())
+    }
+
+    /// Converts the entry into a reference to the value, see [`get_mut`].
+    ///
+    /// [`get_mut`]: Self::get_mut
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn into_mut(self) -> RefMut<'a, 'b, R> {
+        let cell: &'a ResourceCell<'b> = self.inner.into_mut();
+
+        self.tracking.into_ref().borrow_mut(cell)
+    }
+
+    /// Replaces the value and returns the old one.
+    pub fn insert(&mut self, value: R) -> R {
+        // `Resources::try_entry` checked that the old value is an `R`, so it
+        // can be downcast after it is replaced.
+        let new: Box<dyn Resource<'b>> = Box::new(value);
+        let old = std::mem::replace(self.inner.get_mut().get_mut(), new);
+        self.tracking.insert::<R>();
+        self.tracking
+            .observers
+            .replaced(R::id(), &*old, &**self.inner.get_mut().get_mut());
+
+        *resource::downcast_box(old)
+    }
+
+    /// Removes the value from the map and returns it.
+    pub fn remove(self) -> R {
+        self.remove_entry().1
+    }
+
+    /// Removes the value from the map and returns it together with the type
+    /// name of the resource.
+    pub fn remove_entry(mut self) -> (TypeNameLit, R) {
+        let resource = self.inner.remove().into_inner();
+        self.tracking.remove::<R>();
+        self.tracking.observers.removed(R::id(), &*resource);
+
+        (TypeNameLit::of::<R>(), *resource::downcast_box(resource))
+    }
+}
+
+impl<'a, 'b: 'a, R> VacantEntry<'a, 'b, R>
+where
+    R: Resource<'b>,
+{
+    /// Returns the type name of the resource of this entry.
+    pub fn key(&self) -> TypeNameLit {
+        TypeNameLit::of::<R>()
+    }
+
+    /// Inserts the value and returns a reference to it.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn insert(mut self, value: R) -> RefMut<'a, 'b, R> {
+        self.tracking.insert::<R>();
+        let cell: &'a ResourceCell<'b> = self.inner.insert(Cell::new(Box::new(value)));
+        if let Ok(resource) = cell.try_borrow() {
+            self.tracking.observers.inserted(R::id(), &**resource);
+        }
 
-        RefMut::new(inner)
+        self.tracking.into_ref().borrow_mut(cell)
     }
 }
@@ -0,0 +1,40 @@
+use std::{any::TypeId, borrow::Cow, fmt};
+
+/// Label which distinguishes multiple resources of the same type, see
+/// [`Resources::insert_named`][crate::Resources::insert_named].
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label(Cow<'static, str>);
+
+impl Label {
+    /// Returns the label as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&'static str> for Label {
+    fn from(label: &'static str) -> Self {
+        Self(Cow::Borrowed(label))
+    }
+}
+
+impl From<String> for Label {
+    fn from(label: String) -> Self {
+        Self(Cow::Owned(label))
+    }
+}
+
+impl fmt::Debug for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Identifies a resource by its type and optional label.
+pub(crate) type SlotKey = (TypeId, Option<Label>);
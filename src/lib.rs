@@ -1,11 +1,54 @@
 pub use crate::{
-    entry::Entry, r#ref::Ref, ref_mut::RefMut, resource::Resource, resources::Resources,
+    borrow_error::{BorrowError, BorrowMode, BorrowState},
+    borrow_location::BorrowLocation,
+    commands::Commands,
+    derived::Derive,
+    dispatcher::{Dispatcher, DispatcherBuilder, DispatcherError},
+    entry::{Entry, OccupiedEntry, VacantEntry},
+    label::Label,
+    local_resources::{LocalEntry, LocalOccupiedEntry, LocalResources, LocalVacantEntry},
+    observers::ObserverId,
+    poison::{PoisonError, PoisonResult},
+    r#ref::Ref,
+    ref_mut::RefMut,
+    resource::{LocalResource, Resource, TypeNameLit},
+    resources::{Resources, ResourcesDrain, ResourcesIter},
+    scoped_resources::ScopedResources,
+    static_resource::Static,
+    sync_entry::{SyncEntry, SyncOccupiedEntry, SyncVacantEntry},
+    sync_resources::SyncResources,
+    system::System,
+    system_data::{Read, SystemData, Write},
+    transaction::Transaction,
 };
 
-pub use rt_map::BorrowFail;
+#[cfg(feature = "serde")]
+pub use crate::resource_registry::{ResourceRegistry, UnregisteredPolicy};
 
+mod borrow_error;
+mod borrow_location;
+mod commands;
+mod derived;
+mod dispatcher;
+mod dyn_cast;
 mod entry;
+mod label;
+mod local_resources;
+mod observers;
+mod poison;
 mod r#ref;
 mod ref_mut;
 mod resource;
+#[cfg(feature = "serde")]
+mod resource_registry;
 mod resources;
+mod scoped_resources;
+mod slot;
+mod static_resource;
+mod sync_entry;
+mod sync_resources;
+mod system;
+mod system_data;
+mod ticks;
+mod transaction;
+mod wait_graph;
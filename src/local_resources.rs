@@ -0,0 +1,926 @@
+use std::{
+    any::TypeId,
+    collections::{hash_map, HashMap},
+    fmt,
+    marker::PhantomData,
+};
+
+use better_any::TidExt;
+use rt_map::{BorrowFail, Cell, RtMap};
+
+#[cfg(feature = "track_borrows")]
+use crate::borrow_location::BorrowTracker;
+use crate::{
+    poison::{Poison, PoisonResult},
+    BorrowError, BorrowMode, LocalResource, Ref, RefMut, Resource, Resources, TypeNameLit,
+};
+
+/// A resource stored in [LocalResources], which remembers whether it is
+/// `Send`.
+pub(crate) enum Stored<'a> {
+    Send(Box<dyn Resource<'a>>),
+    Local(Box<dyn LocalResource<'a>>),
+}
+
+impl<'a> Stored<'a> {
+    pub(crate) fn type_name(&self) -> TypeNameLit {
+        match self {
+            Self::Send(resource) => resource.type_name(),
+            Self::Local(resource) => resource.type_name(),
+        }
+    }
+
+    pub(crate) fn downcast_ref<R>(&self) -> Option<&R>
+    where
+        R: LocalResource<'a>,
+    {
+        match self {
+            Self::Send(resource) => (**resource).downcast_ref(),
+            Self::Local(resource) => (**resource).downcast_ref(),
+        }
+    }
+
+    pub(crate) fn downcast_mut<R>(&mut self) -> Option<&mut R>
+    where
+        R: LocalResource<'a>,
+    {
+        match self {
+            Self::Send(resource) => (**resource).downcast_mut(),
+            Self::Local(resource) => (**resource).downcast_mut(),
+        }
+    }
+
+    fn downcast_box<R>(self) -> Option<Box<R>>
+    where
+        R: LocalResource<'a>,
+    {
+        match self {
+            Self::Send(resource) => resource.downcast_box().ok(),
+            Self::Local(resource) => resource.downcast_box().ok(),
+        }
+    }
+}
+
+/// A set of types (resources) like [Resources], which may contain resources
+/// that are not `Send`.
+#[derive(Default)]
+pub struct LocalResources<'a> {
+    map: RtMap<TypeId, Stored<'a>>,
+    poisons: HashMap<TypeId, Poison>,
+    #[cfg(feature = "track_borrows")]
+    borrows: BorrowTracker,
+}
+
+/// A [LocalResource] container, which provides the borrowing API of
+/// [Resources] for types which are not `Send`, e.g. ones containing an `Rc`.
+///
+/// Consequently, `LocalResources` itself is not `Send` and has to stay on the
+/// thread it was created on.
+///
+/// ## Examples
+///
+/// ```rust
+/// use std::rc::Rc;
+///
+/// use better_any::Tid;
+/// use stateman::LocalResources;
+///
+/// #[derive(Debug, Tid)]
+/// struct Shared(Rc<u32>);
+///
+/// let mut resources = LocalResources::default();
+/// resources.insert(Shared(Rc::new(3)));
+///
+/// assert_eq!(3, *resources.borrow::<Shared>().0);
+/// ```
+///
+/// ## Converting into Resources
+///
+/// The map only knows that a resource is `Send` if it was inserted with
+/// [`insert_send`]. [`insert`] and [`entry`] treat every resource as not
+/// `Send`, even if its type is, because that can't be detected without
+/// specialization.
+///
+/// Therefore, the map can only be converted into [Resources] with
+/// [`TryFrom`] if **every** resource was inserted with [`insert_send`]:
+///
+/// ```rust
+/// use better_any::Tid;
+/// use stateman::{LocalResources, Resources};
+///
+/// #[derive(Debug, Tid)]
+/// struct Counter(u32);
+///
+/// let mut resources = LocalResources::default();
+/// resources.insert(Counter(0));
+/// let mut resources = Resources::try_from(resources).unwrap_err();
+///
+/// resources.insert_send(Counter(0));
+/// assert!(Resources::try_from(resources).is_ok());
+/// ```
+///
+/// [`insert_send`]: Self::insert_send
+/// [`insert`]: Self::insert
+/// [`entry`]: Self::entry
+impl<'a> LocalResources<'a> {
+    /// Creates an empty `LocalResources` map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty `LocalResources` map with the specified capacity.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            map: RtMap::with_capacity(capacity),
+            poisons: HashMap::with_capacity(capacity),
+            #[cfg(feature = "track_borrows")]
+            borrows: BorrowTracker::default(),
+        }
+    }
+
+    /// Returns the number of elements the map can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    /// Returns an entry for the resource with type `R`.
+    ///
+    /// See [`try_entry`] for a non-panicking version of this function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is poisoned, see [`is_poisoned`].
+    ///
+    /// [`is_poisoned`]: Self::is_poisoned
+    /// [`try_entry`]: Self::try_entry
+    pub fn entry<'b, R>(&'b mut self) -> LocalEntry<'b, 'a, R>
+    where
+        R: LocalResource<'a>,
+    {
+        self.try_entry::<R>().unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Returns an entry for the resource with type `R`, or an error if `R`
+    /// is poisoned or the value stored for it has a different type.
+    pub fn try_entry<'b, R>(&'b mut self) -> Result<LocalEntry<'b, 'a, R>, BorrowError>
+    where
+        R: LocalResource<'a>,
+    {
+        if self.is_poisoned::<R>() {
+            return Err(BorrowError::Poisoned {
+                type_name: TypeNameLit::of::<R>(),
+                label: None,
+                mode: BorrowMode::Exclusive,
+            });
+        }
+        if let Some(resource) = self.map.get_resource_mut(&R::id()) {
+            if resource.downcast_mut::<R>().is_none() {
+                return Err(BorrowError::TypeMismatch {
+                    type_name: TypeNameLit::of::<R>(),
+                    label: None,
+                    mode: BorrowMode::Exclusive,
+                });
+            }
+        }
+
+        Ok(LocalEntry::new(
+            (*self.map).entry(R::id()),
+            LocalTracking {
+                poisons: &mut self.poisons,
+                #[cfg(feature = "track_borrows")]
+                borrows: &self.borrows,
+            },
+        ))
+    }
+
+    /// Inserts a resource into the map. If the resource existed before,
+    /// it will be overwritten.
+    ///
+    /// The resource is treated as not `Send`, so the map can't be converted
+    /// into [Resources] anymore, see [`insert_send`].
+    ///
+    /// [`insert_send`]: Self::insert_send
+    pub fn insert<R>(&mut self, r: R)
+    where
+        R: LocalResource<'a>,
+    {
+        self.poisons.insert(R::id(), Poison::default());
+        self.map.insert(R::id(), Stored::Local(Box::new(r)));
+    }
+
+    /// Inserts a resource which is `Send` into the map, which keeps the map
+    /// convertible into [Resources].
+    pub fn insert_send<R>(&mut self, r: R)
+    where
+        R: Resource<'a>,
+    {
+        self.poisons.insert(R::id(), Poison::default());
+        self.map.insert(R::id(), Stored::Send(Box::new(r)));
+    }
+
+    /// Removes a resource of type `R` from this container and returns its
+    /// ownership to the caller.
+    pub fn remove<R>(&mut self) -> Option<R>
+    where
+        R: LocalResource<'a>,
+    {
+        self.poisons.remove(&R::id());
+        self.map
+            .remove(&R::id())
+            .and_then(Stored::downcast_box::<R>)
+            .map(|x| *x)
+    }
+
+    /// Returns true if the specified resource type `R` exists in `self`.
+    pub fn contains<R>(&self) -> bool
+    where
+        R: LocalResource<'a>,
+    {
+        self.map.contains_key(&R::id())
+    }
+
+    /// Returns the `R` resource in the resource map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist.
+    /// Panics if the resource is being accessed mutably.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow<R>(&self) -> Ref<'_, 'a, R>
+    where
+        R: LocalResource<'a>,
+    {
+        self.try_borrow::<R>().unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Returns an immutable reference to `R` if it exists, an error
+    /// otherwise.
+    ///
+    /// Returns [`BorrowError::Poisoned`] if a panic occurred while the
+    /// resource was borrowed mutably, see [`try_borrow_poisoned`].
+    ///
+    /// [`try_borrow_poisoned`]: Self::try_borrow_poisoned
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow<R>(&self) -> Result<Ref<'_, 'a, R>, BorrowError>
+    where
+        R: LocalResource<'a>,
+    {
+        self.try_borrow_poisoned::<R>()?.map_err(BorrowError::from)
+    }
+
+    /// Returns an immutable reference to `R` like [`try_borrow`], which is
+    /// wrapped in a [PoisonError][crate::PoisonError] if the resource is
+    /// poisoned.
+    ///
+    /// [`try_borrow`]: Self::try_borrow
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_poisoned<R>(&self) -> Result<PoisonResult<Ref<'_, 'a, R>>, BorrowError>
+    where
+        R: LocalResource<'a>,
+    {
+        let r#ref = self
+            .map
+            .try_borrow(&R::id())
+            .map(Ref::<R>::from_local)
+            .map_err(|borrow_fail| self.borrow_error::<R>(borrow_fail, BorrowMode::Shared))?;
+
+        #[cfg(feature = "track_borrows")]
+        let r#ref = r#ref.with_record(self.borrows.record((R::id(), None), BorrowMode::Shared));
+
+        Ok(self.check_poison::<R, _>(r#ref, BorrowMode::Shared))
+    }
+
+    /// Returns a mutable reference to `R`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist.
+    /// Panics if the resource is already accessed.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_mut<R>(&self) -> RefMut<'_, 'a, R>
+    where
+        R: LocalResource<'a>,
+    {
+        self.try_borrow_mut::<R>()
+            .unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Returns a mutable reference to `R` if it exists, an error otherwise.
+    ///
+    /// Returns [`BorrowError::Poisoned`] if a panic occurred while the
+    /// resource was borrowed mutably, see [`try_borrow_mut_poisoned`].
+    ///
+    /// [`try_borrow_mut_poisoned`]: Self::try_borrow_mut_poisoned
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_mut<R>(&self) -> Result<RefMut<'_, 'a, R>, BorrowError>
+    where
+        R: LocalResource<'a>,
+    {
+        self.try_borrow_mut_poisoned::<R>()?
+            .map_err(BorrowError::from)
+    }
+
+    /// Returns a mutable reference to `R` like [`try_borrow_mut`], which is
+    /// wrapped in a [PoisonError][crate::PoisonError] if the resource is
+    /// poisoned.
+    ///
+    /// The resource stays poisoned until [`clear_poison`] is called.
+    ///
+    /// [`try_borrow_mut`]: Self::try_borrow_mut
+    /// [`clear_poison`]: Self::clear_poison
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_mut_poisoned<R>(&self) -> Result<PoisonResult<RefMut<'_, 'a, R>>, BorrowError>
+    where
+        R: LocalResource<'a>,
+    {
+        let ref_mut = self
+            .map
+            .try_borrow_mut(&R::id())
+            .map(RefMut::<R>::from_local)
+            .map_err(|borrow_fail| self.borrow_error::<R>(borrow_fail, BorrowMode::Exclusive))?;
+        let ref_mut = match self.poisons.get(&R::id()) {
+            Some(poison) => ref_mut.with_poison(poison),
+            None => ref_mut,
+        };
+
+        #[cfg(feature = "track_borrows")]
+        let ref_mut =
+            ref_mut.with_record(self.borrows.record((R::id(), None), BorrowMode::Exclusive));
+
+        Ok(self.check_poison::<R, _>(ref_mut, BorrowMode::Exclusive))
+    }
+
+    /// Returns true if a panic occurred while `R` was borrowed mutably.
+    ///
+    /// Inserting a new value clears the poison.
+    pub fn is_poisoned<R>(&self) -> bool
+    where
+        R: LocalResource<'a>,
+    {
+        matches!(self.poisons.get(&R::id()), Some(poison) if poison.is_poisoned())
+    }
+
+    /// Clears the poison of `R`, so it can be borrowed normally again.
+    pub fn clear_poison<R>(&self)
+    where
+        R: LocalResource<'a>,
+    {
+        if let Some(poison) = self.poisons.get(&R::id()) {
+            poison.clear();
+        }
+    }
+
+    /// Retrieves a resource without fetching, which is cheaper, but only
+    /// available with `&mut self`.
+    pub fn get_mut<R>(&mut self) -> Option<&mut R>
+    where
+        R: LocalResource<'a>,
+    {
+        self.map
+            .get_resource_mut(&R::id())
+            .and_then(Stored::downcast_mut)
+    }
+
+    /// Returns `guard` to `R`, or an error carrying it if the resource is
+    /// poisoned.
+    fn check_poison<R, G>(&self, guard: G, mode: BorrowMode) -> PoisonResult<G>
+    where
+        R: LocalResource<'a>,
+    {
+        match self.poisons.get(&R::id()) {
+            Some(poison) => poison.check(guard, TypeNameLit::of::<R>(), None, mode),
+            None => Ok(guard),
+        }
+    }
+
+    /// Converts the `rt_map` failure into a [BorrowError] for `R`.
+    fn borrow_error<R>(&self, borrow_fail: BorrowFail, mode: BorrowMode) -> BorrowError
+    where
+        R: LocalResource<'a>,
+    {
+        BorrowError::from_fail(
+            borrow_fail,
+            self.map.get_raw(&R::id()),
+            (R::id(), None),
+            TypeNameLit::of::<R>(),
+            mode,
+            #[cfg(feature = "track_borrows")]
+            Some(&self.borrows),
+        )
+    }
+
+    fn borrow_panic<Ret>(borrow_error: BorrowError) -> Ret {
+        panic!("{borrow_error}")
+    }
+}
+
+/// Converts a map whose resources were all inserted with
+/// [`insert_send`][LocalResources::insert_send] into [Resources].
+///
+/// Returns the map unchanged if it contains any resource which is not known to
+/// be `Send`.
+impl<'a> TryFrom<LocalResources<'a>> for Resources<'a> {
+    type Error = LocalResources<'a>;
+
+    fn try_from(local: LocalResources<'a>) -> Result<Self, Self::Error> {
+        let all_send = local
+            .map
+            .values()
+            .all(|cell| matches!(&*cell.borrow(), Stored::Send(_)));
+        if !all_send {
+            return Err(local);
+        }
+
+        let mut resources = Resources::with_capacity(local.map.len());
+        for (type_id, cell) in local.map.into_inner() {
+            if let Stored::Send(resource) = cell.into_inner() {
+                resources.insert_raw(type_id, resource);
+                if matches!(local.poisons.get(&type_id), Some(poison) if poison.is_poisoned()) {
+                    resources.poison_raw(type_id);
+                }
+            }
+        }
+
+        Ok(resources)
+    }
+}
+
+impl<'a> fmt::Debug for LocalResources<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug_map = f.debug_map();
+
+        self.map.values().for_each(|cell| match cell.try_borrow() {
+            Ok(resource) => {
+                debug_map.entry(&resource.type_name(), &"..");
+            }
+            Err(_) => {
+                debug_map.entry(&"<borrowed>", &"..");
+            }
+        });
+
+        debug_map.finish()
+    }
+}
+
+type StoredCell<'b> = Cell<Stored<'b>>;
+
+/// An entry to a resource of type `R` in [LocalResources], which is either
+/// occupied or vacant.
+///
+/// This is similar to the Entry API found in the standard library, and
+/// [Entry][crate::Entry] of [Resources]. Values inserted through the entry
+/// are treated as not `Send`, see [`LocalResources::insert`].
+///
+/// ## Examples
+///
+/// ```rust
+/// use better_any::Tid;
+/// use stateman::LocalResources;
+///
+/// #[derive(Debug, Default, Tid)]
+/// struct Res(i32);
+///
+/// let mut resources = LocalResources::default();
+///
+/// let value = resources.entry::<Res>().or_insert(Res(4));
+/// println!("{:?}", value.0 * 2);
+/// drop(value);
+///
+/// resources
+///     .entry::<Res>()
+///     .and_modify(|res| res.0 += 1)
+///     .or_default();
+/// assert_eq!(5, resources.borrow::<Res>().0);
+/// ```
+pub enum LocalEntry<'a, 'b, R> {
+    /// The resource exists.
+    Occupied(LocalOccupiedEntry<'a, 'b, R>),
+    /// The resource doesn't exist.
+    Vacant(LocalVacantEntry<'a, 'b, R>),
+}
+
+/// An entry to an existing resource, see [LocalEntry].
+pub struct LocalOccupiedEntry<'a, 'b, R> {
+    inner: hash_map::OccupiedEntry<'a, TypeId, StoredCell<'b>>,
+    tracking: LocalTracking<'a>,
+    marker: PhantomData<R>,
+}
+
+/// An entry to a missing resource, see [LocalEntry].
+pub struct LocalVacantEntry<'a, 'b, R> {
+    inner: hash_map::VacantEntry<'a, TypeId, StoredCell<'b>>,
+    tracking: LocalTracking<'a>,
+    marker: PhantomData<R>,
+}
+
+/// Bookkeeping of [LocalResources] which is updated when the entry is
+/// changed.
+struct LocalTracking<'a> {
+    poisons: &'a mut HashMap<TypeId, Poison>,
+    #[cfg(feature = "track_borrows")]
+    borrows: &'a BorrowTracker,
+}
+
+impl<'a> LocalTracking<'a> {
+    /// Borrows `cell` of `R` immutably, and records the borrow.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn borrow<'r, 'b, R>(&'r self, cell: &'r StoredCell<'b>) -> Ref<'r, 'b, R>
+    where
+        R: LocalResource<'b>,
+    {
+        // The entry borrows the map mutably, so the cell can't be borrowed.
+        let inner = cell
+            .try_borrow()
+            .unwrap_or_else(|_| unreachable!("Expected cell of entry to be unborrowed."));
+        let r#ref = Ref::<R>::from_local(rt_map::Ref::new(inner));
+
+        #[cfg(feature = "track_borrows")]
+        let r#ref = r#ref.with_record(self.borrows.record((R::id(), None), BorrowMode::Shared));
+
+        r#ref
+    }
+
+    /// Borrows `cell` of `R` mutably, which is poisoned on panic, and records
+    /// the borrow.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn borrow_mut<'r, 'b, R>(
+        poisons: &'r HashMap<TypeId, Poison>,
+        #[cfg(feature = "track_borrows")] borrows: &'r BorrowTracker,
+        cell: &'r StoredCell<'b>,
+    ) -> RefMut<'r, 'b, R>
+    where
+        R: LocalResource<'b>,
+    {
+        let inner = cell
+            .try_borrow_mut()
+            .unwrap_or_else(|_| unreachable!("Expected cell of entry to be unborrowed."));
+        let mut ref_mut = RefMut::<R>::from_local(rt_map::RefMut::new(inner));
+        if let Some(poison) = poisons.get(&R::id()) {
+            ref_mut = ref_mut.with_poison(poison);
+        }
+
+        #[cfg(feature = "track_borrows")]
+        let ref_mut = ref_mut.with_record(borrows.record((R::id(), None), BorrowMode::Exclusive));
+
+        ref_mut
+    }
+}
+
+impl<'a, 'b: 'a, R> LocalEntry<'a, 'b, R>
+where
+    R: LocalResource<'b>,
+{
+    fn new(
+        inner: hash_map::Entry<'a, TypeId, StoredCell<'b>>,
+        tracking: LocalTracking<'a>,
+    ) -> Self {
+        match inner {
+            hash_map::Entry::Occupied(inner) => Self::Occupied(LocalOccupiedEntry {
+                inner,
+                tracking,
+                marker: PhantomData,
+            }),
+            hash_map::Entry::Vacant(inner) => Self::Vacant(LocalVacantEntry {
+                inner,
+                tracking,
+                marker: PhantomData,
+            }),
+        }
+    }
+
+    /// Returns the type name of the resource of this entry.
+    pub fn key(&self) -> TypeNameLit {
+        TypeNameLit::of::<R>()
+    }
+
+    /// Returns this entry's value, inserts and returns `v` otherwise.
+    ///
+    /// Please note that you should use `or_insert_with` in case the creation of
+    /// the value is expensive.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn or_insert(self, v: R) -> RefMut<'a, 'b, R> {
+        self.or_insert_with(move || v)
+    }
+
+    /// Returns this entry's value, inserts and returns the return value of `f`
+    /// otherwise.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn or_insert_with<F>(self, f: F) -> RefMut<'a, 'b, R>
+    where
+        F: FnOnce() -> R,
+    {
+        self.or_insert_with_key(|_| f())
+    }
+
+    /// Returns this entry's value, inserts and returns the return value of `f`
+    /// otherwise, which is called with the type name of the resource.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn or_insert_with_key<F>(self, f: F) -> RefMut<'a, 'b, R>
+    where
+        F: FnOnce(TypeNameLit) -> R,
+    {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => {
+                let value = f(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// Returns this entry's value, inserts and returns the default value
+    /// otherwise.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn or_default(self) -> RefMut<'a, 'b, R>
+    where
+        R: Default,
+    {
+        self.or_insert_with(R::default)
+    }
+
+    /// Calls `f` with the value if the entry is occupied.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut R),
+    {
+        if let Self::Occupied(entry) = &mut self {
+            f(&mut entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, 'b: 'a, R> LocalOccupiedEntry<'a, 'b, R>
+where
+    R: LocalResource<'b>,
+{
+    /// Returns the type name of the resource of this entry.
+    pub fn key(&self) -> TypeNameLit {
+        TypeNameLit::of::<R>()
+    }
+
+    /// Returns a reference to the value.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn get(&self) -> Ref<'_, 'b, R> {
+        self.tracking.borrow(self.inner.get())
+    }
+
+    /// Returns a mutable reference to the value like
+    /// [`LocalResources::borrow_mut`].
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn get_mut(&mut self) -> RefMut<'_, 'b, R> {
+        LocalTracking::borrow_mut(
+            self.tracking.poisons,
+            #[cfg(feature = "track_borrows")]
+            self.tracking.borrows,
+            self.inner.get(),
+        )
+    }
+
+    /// Converts the entry into a reference to the value, see [`get_mut`].
+    ///
+    /// [`get_mut`]: Self::get_mut
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn into_mut(self) -> RefMut<'a, 'b, R> {
+        LocalTracking::borrow_mut(
+            self.tracking.poisons,
+            #[cfg(feature = "track_borrows")]
+            self.tracking.borrows,
+            self.inner.into_mut(),
+        )
+    }
+
+    /// Replaces the value and returns the old one.
+    ///
+    /// The new value is treated as not `Send`, see
+    /// [`LocalResources::insert`].
+    pub fn insert(&mut self, value: R) -> R {
+        // `LocalResources::try_entry` checked that the old value is an `R`,
+        // so it can be downcast after it is replaced.
+        let old = std::mem::replace(
+            self.inner.get_mut().get_mut(),
+            Stored::Local(Box::new(value)),
+        );
+        self.tracking.poisons.insert(R::id(), Poison::default());
+
+        *old.downcast_box::<R>()
+            .unwrap_or_else(|| unreachable!("Expected value of entry to be an `R`."))
+    }
+
+    /// Removes the value from the map and returns it.
+    pub fn remove(self) -> R {
+        self.remove_entry().1
+    }
+
+    /// Removes the value from the map and returns it together with the type
+    /// name of the resource.
+    pub fn remove_entry(self) -> (TypeNameLit, R) {
+        let resource = self.inner.remove().into_inner();
+        self.tracking.poisons.remove(&R::id());
+        let resource = resource
+            .downcast_box::<R>()
+            .unwrap_or_else(|| unreachable!("Expected value of entry to be an `R`."));
+
+        (TypeNameLit::of::<R>(), *resource)
+    }
+}
+
+impl<'a, 'b: 'a, R> LocalVacantEntry<'a, 'b, R>
+where
+    R: LocalResource<'b>,
+{
+    /// Returns the type name of the resource of this entry.
+    pub fn key(&self) -> TypeNameLit {
+        TypeNameLit::of::<R>()
+    }
+
+    /// Inserts the value and returns a reference to it.
+    ///
+    /// The value is treated as not `Send`, see [`LocalResources::insert`].
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn insert(self, value: R) -> RefMut<'a, 'b, R> {
+        self.tracking.poisons.insert(R::id(), Poison::default());
+        let cell = self.inner.insert(Cell::new(Stored::Local(Box::new(value))));
+
+        LocalTracking::borrow_mut(
+            self.tracking.poisons,
+            #[cfg(feature = "track_borrows")]
+            self.tracking.borrows,
+            cell,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        rc::Rc,
+    };
+
+    use better_any::Tid;
+
+    use crate::{BorrowError, BorrowMode, Resources, TypeNameLit};
+
+    use super::{LocalEntry, LocalResources};
+
+    #[test]
+    fn non_send_resource_can_be_borrowed() {
+        let mut resources = LocalResources::new();
+        resources.insert(Shared(Rc::new(1)));
+
+        *resources.borrow_mut::<Shared>() = Shared(Rc::new(2));
+
+        assert_eq!(2, *resources.borrow::<Shared>().0);
+        assert_eq!(2, *resources.remove::<Shared>().unwrap().0);
+        assert!(!resources.contains::<Shared>());
+    }
+
+    #[test]
+    fn ref_mut_dropped_during_panic_poisons_resource() {
+        let mut resources = LocalResources::new();
+        resources.insert(Shared(Rc::new(1)));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _shared = resources.borrow_mut::<Shared>();
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+
+        assert!(resources.is_poisoned::<Shared>());
+        assert!(matches!(
+            resources.try_borrow::<Shared>(),
+            Err(BorrowError::Poisoned {
+                mode: BorrowMode::Shared,
+                ..
+            })
+        ));
+        assert_eq!(
+            1,
+            *resources
+                .try_borrow_mut_poisoned::<Shared>()
+                .unwrap()
+                .unwrap_err()
+                .into_inner()
+                .0
+        );
+
+        resources.clear_poison::<Shared>();
+        assert!(resources.try_borrow::<Shared>().is_ok());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _shared = resources.borrow_mut::<Shared>();
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+        resources.insert(Shared(Rc::new(2)));
+        assert!(!resources.is_poisoned::<Shared>());
+    }
+
+    #[test]
+    fn entry_or_insert_inserts_value() {
+        let mut resources = LocalResources::new();
+        resources.entry::<Shared>().or_insert(Shared(Rc::new(1)));
+        let shared = resources.entry::<Shared>().or_insert(Shared(Rc::new(2)));
+
+        assert_eq!(1, *shared.0);
+    }
+
+    #[test]
+    fn occupied_entry_replaces_and_removes_value() {
+        let mut resources = LocalResources::new();
+        resources.insert(Shared(Rc::new(1)));
+
+        let LocalEntry::Occupied(mut entry) = resources.entry::<Shared>() else {
+            panic!("Expected entry of Shared to be occupied.");
+        };
+        assert_eq!(1, *entry.get().0);
+        assert_eq!(1, *entry.insert(Shared(Rc::new(2))).0);
+        assert_eq!(2, *entry.remove().0);
+
+        assert!(!resources.contains::<Shared>());
+        let shared = resources
+            .entry::<Shared>()
+            .and_modify(|shared| shared.0 = Rc::new(3))
+            .or_insert_with(|| Shared(Rc::new(4)));
+        assert_eq!(4, *shared.0);
+    }
+
+    #[test]
+    fn entry_of_poisoned_resource_is_reported() {
+        let mut resources = LocalResources::new();
+        resources.insert(Shared(Rc::new(1)));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let LocalEntry::Occupied(mut entry) = resources.entry::<Shared>() else {
+                panic!("Expected entry of Shared to be occupied.");
+            };
+            let _shared = entry.get_mut();
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+
+        assert!(matches!(
+            resources.try_entry::<Shared>(),
+            Err(BorrowError::Poisoned {
+                mode: BorrowMode::Exclusive,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn conflicting_borrow_is_reported() {
+        let mut resources = LocalResources::new();
+        resources.insert(Shared(Rc::new(1)));
+
+        let _shared = resources.borrow_mut::<Shared>();
+
+        assert!(matches!(
+            resources.try_borrow::<Shared>(),
+            Err(BorrowError::ConflictExclusive {
+                mode: BorrowMode::Shared,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn send_resources_convert_into_resources() {
+        let mut resources = LocalResources::new();
+        resources.insert_send(A(1));
+
+        let resources = Resources::try_from(resources).unwrap();
+
+        assert_eq!(&A(1), &*resources.borrow::<A>());
+    }
+
+    #[test]
+    fn local_resources_do_not_convert_into_resources() {
+        let mut resources = LocalResources::new();
+        resources.insert_send(A(1));
+        resources.insert(Shared(Rc::new(1)));
+
+        let resources = Resources::try_from(resources).unwrap_err();
+
+        assert_eq!(&A(1), &*resources.borrow::<A>());
+        assert_eq!(
+            Err(BorrowError::NotFound {
+                type_name: TypeNameLit::of::<B>(),
+                label: None,
+                mode: BorrowMode::Shared,
+            }),
+            resources.try_borrow::<B>().map(|_| ())
+        );
+    }
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct A(u32);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct B(u32);
+
+    #[derive(Debug, Tid)]
+    struct Shared(Rc<u32>);
+}
@@ -0,0 +1,400 @@
+use std::{
+    any::TypeId,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use better_any::TidExt;
+use rt_map::Cell;
+
+use crate::{Resource, TypeNameLit};
+
+/// Handle of an observer registered with [Resources][crate::Resources], which
+/// is used to unregister it again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+type InsertFn<'a> = Box<dyn Fn(TypeNameLit, &dyn Resource<'a>) + Send + 'a>;
+type ReplaceFn<'a> = Box<dyn Fn(TypeNameLit, &dyn Resource<'a>, &dyn Resource<'a>) + Send + 'a>;
+type ChangeFn<'a> = Box<dyn Fn(&dyn Resource<'a>) + Send + Sync + 'a>;
+
+/// Callback of an observer, called with the type name and the value(s).
+pub(crate) enum Observer<'a> {
+    Insert(InsertFn<'a>),
+    Remove(InsertFn<'a>),
+    Replace(ReplaceFn<'a>),
+}
+
+impl<'a> Observer<'a> {
+    pub(crate) fn insert<R, F>(f: F) -> Self
+    where
+        R: Resource<'a>,
+        F: Fn(TypeNameLit, &R) + Send + 'a,
+    {
+        Self::Insert(Box::new(typed(f)))
+    }
+
+    pub(crate) fn remove<R, F>(f: F) -> Self
+    where
+        R: Resource<'a>,
+        F: Fn(TypeNameLit, &R) + Send + 'a,
+    {
+        Self::Remove(Box::new(typed(f)))
+    }
+
+    pub(crate) fn replace<R, F>(f: F) -> Self
+    where
+        R: Resource<'a>,
+        F: Fn(TypeNameLit, &R, &R) + Send + 'a,
+    {
+        Self::Replace(Box::new(move |type_name, old, new| {
+            if let (Some(old), Some(new)) = (old.downcast_ref::<R>(), new.downcast_ref::<R>()) {
+                f(type_name, old, new);
+            }
+        }))
+    }
+}
+
+/// Wraps `f` to skip resources that are not of type `R`, which may be
+/// inserted with a mismatching `TypeId` through `insert_raw`.
+fn typed<'a, R, F>(f: F) -> impl Fn(TypeNameLit, &dyn Resource<'a>) + Send + 'a
+where
+    R: Resource<'a>,
+    F: Fn(TypeNameLit, &R) + Send + 'a,
+{
+    move |type_name, resource| {
+        if let Some(resource) = resource.downcast_ref::<R>() {
+            f(type_name, resource);
+        }
+    }
+}
+
+/// Observers of a [Resources][crate::Resources] map, called in the order in
+/// which they were registered.
+#[derive(Default)]
+pub(crate) struct Observers<'a> {
+    next_id: u64,
+    observers: Vec<(ObserverId, Option<TypeId>, Observer<'a>)>,
+    changes: ChangeObservers<'a>,
+}
+
+/// Observers of content changes, which are called from
+/// [RefMut][crate::RefMut]s on any thread.
+#[derive(Default)]
+pub(crate) struct ChangeObservers<'a> {
+    observers: Vec<(ObserverId, TypeId, ChangeFn<'a>)>,
+}
+
+impl<'a> ChangeObservers<'a> {
+    pub(crate) fn observes(&self, type_id: TypeId) -> bool {
+        self.observers
+            .iter()
+            .any(|(_, observed, _)| *observed == type_id)
+    }
+
+    fn changed(&self, type_id: TypeId, resource: &dyn Resource<'a>) {
+        self.observers
+            .iter()
+            .filter(|(_, observed, _)| *observed == type_id)
+            .for_each(|(_, _, f)| f(resource));
+    }
+}
+
+/// Notifies [ChangeObservers] when a mutably borrowed resource was changed.
+pub(crate) struct ChangeHook<'a, 'b> {
+    type_id: TypeId,
+    cell: &'a Cell<Box<dyn Resource<'b>>>,
+    observers: &'a ChangeObservers<'b>,
+    changed: AtomicBool,
+}
+
+// SAFETY: The cell is only borrowed immutably to pass the resource to the
+// observers, which are `Sync` and only registered for `Sync` resources.
+unsafe impl<'a, 'b> Send for ChangeHook<'a, 'b> {}
+
+impl<'a, 'b> ChangeHook<'a, 'b> {
+    pub(crate) fn new(
+        type_id: TypeId,
+        cell: &'a Cell<Box<dyn Resource<'b>>>,
+        observers: &'a ChangeObservers<'b>,
+    ) -> Self {
+        Self {
+            type_id,
+            cell,
+            observers,
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn set_changed(&self) {
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Calls the observers if the resource was changed. The mutable borrow
+    /// must be released before, so the resource can be borrowed immutably.
+    pub(crate) fn notify(&self) {
+        if !self.changed.load(Ordering::Relaxed) {
+            return;
+        }
+
+        // Another thread may have borrowed the resource mutably in the
+        // meantime, in which case its guard notifies the observers.
+        if let Ok(resource) = self.cell.try_borrow() {
+            self.observers.changed(self.type_id, &**resource);
+        }
+    }
+}
+
+impl<'a> Observers<'a> {
+    /// Registers `observer` for resources of `type_id`, or all resources if
+    /// `None`.
+    pub(crate) fn add(&mut self, type_id: Option<TypeId>, observer: Observer<'a>) -> ObserverId {
+        let id = ObserverId(self.next_id);
+        self.next_id += 1;
+        self.observers.push((id, type_id, observer));
+        id
+    }
+
+    /// Registers `f` to be called with resources of type `R` after they were
+    /// changed through a [RefMut][crate::RefMut].
+    pub(crate) fn add_change<R, F>(&mut self, f: F) -> ObserverId
+    where
+        R: Resource<'a> + Sync,
+        F: Fn(&R) + Send + Sync + 'a,
+    {
+        let id = ObserverId(self.next_id);
+        self.next_id += 1;
+        self.changes.observers.push((
+            id,
+            R::id(),
+            Box::new(move |resource| {
+                if let Some(resource) = resource.downcast_ref::<R>() {
+                    f(resource);
+                }
+            }),
+        ));
+        id
+    }
+
+    /// Calls the observers of content changes of `type_id` with `resource`,
+    /// which was changed without a [ChangeHook].
+    pub(crate) fn changed(&self, type_id: TypeId, resource: &dyn Resource<'a>) {
+        self.changes.changed(type_id, resource);
+    }
+
+    pub(crate) fn changes(&self) -> &ChangeObservers<'a> {
+        &self.changes
+    }
+
+    pub(crate) fn remove(&mut self, id: ObserverId) -> bool {
+        let len = self.observers.len() + self.changes.observers.len();
+        self.observers
+            .retain(|(observer_id, ..)| *observer_id != id);
+        self.changes
+            .observers
+            .retain(|(observer_id, ..)| *observer_id != id);
+        self.observers.len() + self.changes.observers.len() != len
+    }
+
+    pub(crate) fn inserted(&self, type_id: TypeId, resource: &dyn Resource<'a>) {
+        self.observers_of(type_id).for_each(|observer| {
+            if let Observer::Insert(f) = observer {
+                f(resource.type_name(), resource);
+            }
+        });
+    }
+
+    pub(crate) fn removed(&self, type_id: TypeId, resource: &dyn Resource<'a>) {
+        self.observers_of(type_id).for_each(|observer| {
+            if let Observer::Remove(f) = observer {
+                f(resource.type_name(), resource);
+            }
+        });
+    }
+
+    pub(crate) fn replaced(&self, type_id: TypeId, old: &dyn Resource<'a>, new: &dyn Resource<'a>) {
+        self.observers_of(type_id).for_each(|observer| {
+            if let Observer::Replace(f) = observer {
+                f(new.type_name(), old, new);
+            }
+        });
+    }
+
+    fn observers_of(&self, type_id: TypeId) -> impl Iterator<Item = &Observer<'a>> {
+        self.observers
+            .iter()
+            .filter(move |(_, observed, _)| observed.is_none() || *observed == Some(type_id))
+            .map(|(_, _, observer)| observer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        any::TypeId,
+        sync::{Arc, Mutex},
+    };
+
+    use better_any::Tid;
+
+    use crate::{RefMut, Resources, TypeNameLit};
+
+    type Events = Arc<Mutex<Vec<String>>>;
+
+    fn record(events: &Events, event: String) {
+        events.lock().unwrap().push(event);
+    }
+
+    fn observed() -> (Resources<'static>, Events) {
+        let events = Events::default();
+        let mut resources = Resources::default();
+
+        let observed = Arc::clone(&events);
+        resources.on_insert::<A>(move |_, a| record(&observed, format!("insert A({})", a.0)));
+        let observed = Arc::clone(&events);
+        resources.on_remove::<A>(move |_, a| record(&observed, format!("remove A({})", a.0)));
+        let observed = Arc::clone(&events);
+        resources.on_replace::<A>(move |_, old, new| {
+            record(&observed, format!("replace A({}) A({})", old.0, new.0))
+        });
+
+        (resources, events)
+    }
+
+    #[test]
+    fn observers_are_called_on_insert_replace_and_remove() {
+        let (mut resources, events) = observed();
+
+        resources.insert(A(1));
+        resources.insert_raw(TypeId::of::<A>(), Box::new(A(2)));
+        resources.remove::<A>();
+        resources.entry::<A>().or_insert(A(3));
+        resources.entry::<A>().or_insert(A(4));
+        resources.insert(B);
+
+        assert_eq!(
+            vec![
+                "insert A(1)",
+                "replace A(1) A(2)",
+                "remove A(2)",
+                "insert A(3)"
+            ],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn global_observers_receive_type_name() {
+        let events = Events::default();
+        let mut resources = Resources::default();
+        let observed = Arc::clone(&events);
+        resources.on_insert_any(move |type_name, _| record(&observed, type_name.to_string()));
+        let observed = Arc::clone(&events);
+        resources.on_remove_any(move |type_name, _| {
+            assert_eq!(TypeNameLit::of::<B>(), type_name);
+            record(&observed, String::from("removed"))
+        });
+
+        resources.insert(A(1));
+        resources.insert(B);
+        resources.remove::<B>();
+
+        assert_eq!(
+            vec![
+                TypeNameLit::of::<A>().to_string(),
+                TypeNameLit::of::<B>().to_string(),
+                String::from("removed")
+            ],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn removed_observer_is_not_called() {
+        let events = Events::default();
+        let mut resources = Resources::default();
+        let observed = Arc::clone(&events);
+        let id = resources.on_insert::<A>(move |_, a| record(&observed, format!("A({})", a.0)));
+
+        resources.insert(A(1));
+        assert!(resources.remove_observer(id));
+        assert!(!resources.remove_observer(id));
+        resources.remove::<A>();
+        resources.insert(A(2));
+
+        assert_eq!(vec!["A(1)"], *events.lock().unwrap());
+    }
+
+    #[test]
+    fn change_observer_is_called_after_borrow_is_released() {
+        let events = Events::default();
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        let observed = Arc::clone(&events);
+        resources.on_change::<A>(move |a| record(&observed, format!("A({})", a.0)));
+
+        let mut a = resources.borrow_mut::<A>();
+        a.0 = 2;
+        assert!(events.lock().unwrap().is_empty());
+        drop(a);
+
+        assert_eq!(vec!["A(2)"], *events.lock().unwrap());
+    }
+
+    #[test]
+    fn change_observer_is_called_for_entry_references() {
+        let events = Events::default();
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        let observed = Arc::clone(&events);
+        resources.on_change::<A>(move |a| record(&observed, format!("A({})", a.0)));
+
+        resources.entry::<A>().and_modify(|a| a.0 = 2);
+        resources.entry::<A>().or_insert(A(0)).0 = 3;
+
+        assert_eq!(vec!["A(2)", "A(3)"], *events.lock().unwrap());
+    }
+
+    #[test]
+    fn change_observer_is_not_called_without_deref_mut() {
+        let events = Events::default();
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        let observed = Arc::clone(&events);
+        let id = resources.on_change::<A>(move |a| record(&observed, format!("A({})", a.0)));
+
+        assert_eq!(1, resources.borrow_mut::<A>().0);
+        RefMut::bypass_change_detection(&mut resources.borrow_mut::<A>()).0 = 2;
+        assert!(events.lock().unwrap().is_empty());
+
+        assert!(resources.remove_observer(id));
+        resources.borrow_mut::<A>().0 = 3;
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn change_observer_is_called_once_for_split_references() {
+        let events = Events::default();
+        let mut resources = Resources::default();
+        resources.insert(C(1, 2));
+        let observed = Arc::clone(&events);
+        resources.on_change::<C>(move |c| record(&observed, format!("C({}, {})", c.0, c.1)));
+
+        let (mut first, second) =
+            RefMut::map_split(resources.borrow_mut::<C>(), |c| (&mut c.0, &mut c.1));
+        *first = 3;
+        drop(first);
+        assert!(events.lock().unwrap().is_empty());
+        drop(second);
+
+        assert_eq!(vec!["C(3, 2)"], *events.lock().unwrap());
+    }
+
+    #[derive(Debug, Tid)]
+    struct A(u32);
+
+    #[derive(Debug, Tid)]
+    struct C(u32, u32);
+
+    #[derive(Debug, Tid)]
+    struct B;
+}
@@ -0,0 +1,108 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{BorrowError, BorrowMode, Label, TypeNameLit};
+
+/// Flag of a resource which is set when a [RefMut][crate::RefMut] to it is
+/// dropped during a panic.
+#[derive(Debug, Default)]
+pub(crate) struct Poison(AtomicBool);
+
+impl Poison {
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Marks the resource as poisoned by a panic while it was borrowed
+    /// mutably.
+    pub(crate) fn poison(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns `guard` to the resource with `type_name` and `label` which was
+    /// borrowed in `mode`, or an error carrying it if the resource is
+    /// poisoned.
+    pub(crate) fn check<G>(
+        &self,
+        guard: G,
+        type_name: TypeNameLit,
+        label: Option<&Label>,
+        mode: BorrowMode,
+    ) -> PoisonResult<G> {
+        if !self.is_poisoned() {
+            return Ok(guard);
+        }
+
+        Err(PoisonError {
+            guard,
+            borrow_error: BorrowError::Poisoned {
+                type_name,
+                label: label.cloned(),
+                mode,
+            },
+        })
+    }
+}
+
+/// Result of borrowing a resource which may be poisoned, see
+/// [`Resources::try_borrow_poisoned`][crate::Resources::try_borrow_poisoned].
+pub type PoisonResult<G> = Result<G, PoisonError<G>>;
+
+/// Error carrying the reference to a poisoned resource, like
+/// [`std::sync::PoisonError`].
+///
+/// A panic occurred while the resource was borrowed mutably, so it may be in
+/// an inconsistent state. The reference can still be accessed with
+/// [`PoisonError::into_inner`].
+pub struct PoisonError<G> {
+    guard: G,
+    borrow_error: BorrowError,
+}
+
+impl<G> PoisonError<G> {
+    /// Returns the reference to the poisoned resource.
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+
+    /// Returns an immutable reference to the reference to the poisoned
+    /// resource.
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+
+    /// Returns a mutable reference to the reference to the poisoned resource.
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+impl<G> From<PoisonError<G>> for BorrowError {
+    /// Returns the [`BorrowError::Poisoned`] of `poison_error`, which releases
+    /// the resource.
+    fn from(poison_error: PoisonError<G>) -> Self {
+        poison_error.borrow_error
+    }
+}
+
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError")
+            .field("borrow_error", &self.borrow_error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<G> fmt::Display for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.borrow_error, f)
+    }
+}
+
+impl<G> std::error::Error for PoisonError<G> {}
@@ -1,20 +1,243 @@
-use std::{fmt, marker::PhantomData, ops::Deref};
+use std::{fmt, marker::PhantomData, ops::Deref, ptr::NonNull};
 
 use better_any::TidExt;
 
-use crate::Resource;
+#[cfg(feature = "track_borrows")]
+use crate::borrow_location::BorrowRecord;
+use crate::{
+    local_resources::Stored,
+    slot::SlotBorrow,
+    ticks::{SharedCount, Ticks},
+    LocalResource, Resource,
+};
 
 /// Reference to a resource.
-#[derive(Clone)]
-pub struct Ref<'a, 'b, R: 'a> {
-    inner: rt_map::Ref<'a, Box<dyn Resource<'b>>>,
+///
+/// The reference may be narrowed down to a part of the resource with
+/// [`Ref::map`] and [`Ref::filter_map`], which keeps the resource borrowed.
+///
+/// References into [Resources][crate::Resources] also report when the
+/// resource was added and last changed, see [`Ref::is_changed`].
+pub struct Ref<'a, 'b, R: ?Sized + 'a> {
+    borrow: Borrow<'a, 'b>,
+    ticks: Option<&'a Ticks>,
+    value: NonNull<R>,
     phantom: PhantomData<&'a R>,
 }
 
-impl<'a, 'b, R> Ref<'a, 'b, R> {
+// SAFETY: `Ref` behaves like `&R`. The guard never accesses the value, and the
+// borrow state it clones and releases is atomic: `rt_map::CellRef` increments
+// and decrements the `AtomicUsize` flag of its `rt_map::Cell` in `Clone` and
+// `Drop`, and `SlotBorrow` updates its slot under a `Mutex`. So the guard may be
+// cloned and dropped on any thread, including the `!Send` guard of a
+// `LocalResources` cell.
+unsafe impl<'a, 'b, R> Send for Ref<'a, 'b, R> where R: ?Sized + Sync {}
+
+// SAFETY: See above.
+unsafe impl<'a, 'b, R> Sync for Ref<'a, 'b, R> where R: ?Sized + Sync {}
+
+/// Keeps the underlying resource borrowed.
+#[derive(Clone)]
+struct Borrow<'a, 'b> {
+    // Only held to release the borrow on drop.
+    #[allow(dead_code)]
+    inner: CellGuard<'a, 'b>,
+    #[cfg(feature = "track_borrows")]
+    record: Option<BorrowRecord<'a>>,
+    // Only held to count the borrow in the ticks of the resource.
+    #[allow(dead_code)]
+    shared: Option<SharedCount<'a>>,
+}
+
+/// Guard of a [Resources][crate::Resources] or
+/// [LocalResources][crate::LocalResources] cell, or a
+/// [SyncResources][crate::SyncResources] slot.
+#[allow(dead_code)]
+#[derive(Clone)]
+enum CellGuard<'a, 'b> {
+    Cell(rt_map::Ref<'a, Box<dyn Resource<'b>>>),
+    Local(rt_map::Ref<'a, Stored<'b>>),
+    Slot(SlotBorrow<'a, 'b>),
+}
+
+impl<'a, 'b, R> Ref<'a, 'b, R>
+where
+    R: LocalResource<'b>,
+{
     pub fn new(inner: rt_map::Ref<'a, Box<dyn Resource<'b>>>) -> Self {
+        let value = Self::downcast(&**inner);
+
+        Self::from_guard(CellGuard::Cell(inner), value)
+    }
+
+    pub(crate) fn from_local(inner: rt_map::Ref<'a, Stored<'b>>) -> Self {
+        let value = inner
+            .downcast_ref::<R>()
+            .map(NonNull::from)
+            .unwrap_or_else(|| panic!("Failed to downcast to {}", std::any::type_name::<R>()));
+
+        Self::from_guard(CellGuard::Local(inner), value)
+    }
+
+    fn downcast(resource: &dyn Resource<'b>) -> NonNull<R> {
+        resource
+            .downcast_ref::<R>()
+            .map(NonNull::from)
+            .unwrap_or_else(|| panic!("Failed to downcast to {}", std::any::type_name::<R>()))
+    }
+}
+
+impl<'a, 'b> Ref<'a, 'b, dyn Resource<'b>> {
+    /// Borrows the resource of `inner` without downcasting it.
+    pub(crate) fn erased(inner: rt_map::Ref<'a, Box<dyn Resource<'b>>>) -> Self {
+        let value = NonNull::from(&**inner);
+
+        Self::from_guard(CellGuard::Cell(inner), value)
+    }
+
+    /// Borrows the resource of `borrow` without downcasting it.
+    pub(crate) fn from_slot(borrow: SlotBorrow<'a, 'b>) -> Self {
+        let value = NonNull::from(borrow.value() as &dyn Resource<'b>);
+
+        Self::from_guard(CellGuard::Slot(borrow), value)
+    }
+}
+
+impl<'a, 'b, R> Ref<'a, 'b, R>
+where
+    R: ?Sized,
+{
+    fn from_guard(inner: CellGuard<'a, 'b>, value: NonNull<R>) -> Self {
+        Self {
+            borrow: Borrow {
+                inner,
+                #[cfg(feature = "track_borrows")]
+                record: None,
+                shared: None,
+            },
+            ticks: None,
+            value,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Keeps `record` alive for as long as this borrow.
+    #[cfg(feature = "track_borrows")]
+    pub(crate) fn with_record(mut self, record: BorrowRecord<'a>) -> Self {
+        self.borrow.record = Some(record);
+        self
+    }
+
+    /// Keeps track of the change ticks of the borrowed resource, and counts
+    /// the borrow in them.
+    pub(crate) fn with_ticks(mut self, ticks: &'a Ticks) -> Self {
+        self.ticks = Some(ticks);
+        self.borrow.shared = Some(ticks.count_shared());
+        self
+    }
+
+    /// Returns the tick at which the resource was added, or `None` if the
+    /// container doesn't track changes.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `Ref::added_tick(...)`, so it does not interfere with methods of `R`.
+    pub fn added_tick(orig: &Self) -> Option<u64> {
+        orig.ticks.map(Ticks::added)
+    }
+
+    /// Returns the tick at which the resource was last changed, or `None` if
+    /// the container doesn't track changes.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `Ref::changed_tick(...)`, so it does not interfere with methods of `R`.
+    pub fn changed_tick(orig: &Self) -> Option<u64> {
+        orig.ticks.map(Ticks::changed)
+    }
+
+    /// Returns true if the resource was added after tick `since`.
+    ///
+    /// See [`Resources::is_added`][crate::Resources::is_added].
+    pub fn is_added(orig: &Self, since: u64) -> bool {
+        matches!(Self::added_tick(orig), Some(added) if added > since)
+    }
+
+    /// Returns true if the resource was added or changed after tick `since`.
+    ///
+    /// See [`Resources::is_changed`][crate::Resources::is_changed].
+    pub fn is_changed(orig: &Self, since: u64) -> bool {
+        matches!(Self::changed_tick(orig), Some(changed) if changed > since)
+    }
+
+    /// Makes a new `Ref` for a component of the borrowed resource.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `Ref::map(...)`, so it does not interfere with methods of `R`.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::{Ref, Resources};
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Position(u32, u32);
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Position(1, 2));
+    ///
+    /// let x = Ref::map(resources.borrow::<Position>(), |position| &position.0);
+    /// assert_eq!(1, *x);
+    /// ```
+    pub fn map<U, F>(orig: Self, f: F) -> Ref<'a, 'b, U>
+    where
+        U: ?Sized,
+        F: FnOnce(&R) -> &U,
+    {
+        // SAFETY: `value` points into the resource, which stays borrowed for
+        // as long as `borrow` is alive.
+        let value = NonNull::from(f(unsafe { orig.value.as_ref() }));
+
+        Ref {
+            borrow: orig.borrow,
+            ticks: orig.ticks,
+            value,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Makes a new `Ref` for an optional component of the borrowed resource.
+    /// The original guard is returned as `Err(..)` if the closure returns
+    /// `None`.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `Ref::filter_map(...)`, so it does not interfere with methods of `R`.
+    pub fn filter_map<U, F>(orig: Self, f: F) -> Result<Ref<'a, 'b, U>, Self>
+    where
+        U: ?Sized,
+        F: FnOnce(&R) -> Option<&U>,
+    {
+        // SAFETY: See `Ref::map`.
+        match f(unsafe { orig.value.as_ref() }).map(NonNull::from) {
+            Some(value) => Ok(Ref {
+                borrow: orig.borrow,
+                ticks: orig.ticks,
+                value,
+                phantom: PhantomData,
+            }),
+            None => Err(orig),
+        }
+    }
+}
+
+impl<'a, 'b, R> Clone for Ref<'a, 'b, R>
+where
+    R: ?Sized,
+{
+    fn clone(&self) -> Self {
         Self {
-            inner,
+            borrow: self.borrow.clone(),
+            ticks: self.ticks,
+            value: self.value,
             phantom: PhantomData,
         }
     }
@@ -22,30 +245,29 @@ impl<'a, 'b, R> Ref<'a, 'b, R> {
 
 impl<'a, 'b, R> Deref for Ref<'a, 'b, R>
 where
-    R: Resource<'b>,
+    R: ?Sized,
 {
     type Target = R;
 
     fn deref(&self) -> &R {
-        (*self.inner)
-            .downcast_ref::<R>()
-            .unwrap_or_else(|| panic!("Failed to downcast to {}", std::any::type_name::<R>()))
+        // SAFETY: See `Ref::map`.
+        unsafe { self.value.as_ref() }
     }
 }
 
 impl<'a, 'b, R> fmt::Debug for Ref<'a, 'b, R>
 where
-    R: Resource<'b> + fmt::Debug + 'a,
+    R: ?Sized + fmt::Debug + 'a,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let inner: &R = self;
-        f.debug_struct("Ref").field("inner", inner).finish()
+        f.debug_struct("Ref").field("inner", &inner).finish()
     }
 }
 
 impl<'a, 'b, R> PartialEq for Ref<'a, 'b, R>
 where
-    R: Resource<'b> + PartialEq + 'a,
+    R: ?Sized + PartialEq + 'a,
 {
     fn eq(&self, other: &Self) -> bool {
         let r_self: &R = self;
@@ -94,6 +316,55 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn map_projects_to_field() -> fmt::Result {
+        let value: Box<dyn Resource> = Box::new(B(1, 2));
+        let cell = Cell::new(value);
+        let r#ref = Ref::<B>::new(rt_map::Ref::new(cell.borrow()));
+        let field = Ref::map(r#ref, |b| &b.1);
+
+        assert_eq!(2, *field);
+        assert!(cell.try_borrow_mut().is_err());
+
+        let mut debug_string = String::with_capacity(64);
+        write!(&mut debug_string, "{:?}", field)?;
+        assert_eq!("Ref { inner: 2 }", debug_string.as_str());
+
+        drop(field);
+        assert!(cell.try_borrow_mut().is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_map_returns_original_on_none() {
+        let value: Box<dyn Resource> = Box::new(B(1, 2));
+        let cell = Cell::new(value);
+        let r#ref = Ref::<B>::new(rt_map::Ref::new(cell.borrow()));
+
+        let r#ref = Ref::filter_map(r#ref, |_| None::<&usize>).unwrap_err();
+        assert_eq!(&B(1, 2), &*r#ref);
+
+        let field = Ref::filter_map(r#ref, |b| Some(&b.0)).unwrap();
+        assert_eq!(1, *field);
+    }
+
+    #[test]
+    fn clone_keeps_resource_borrowed() {
+        let value: Box<dyn Resource> = Box::new(A(1));
+        let cell = Cell::new(value);
+        let r#ref = Ref::<A>::new(rt_map::Ref::new(cell.borrow()));
+        let field = Ref::map(r#ref, |a| &a.0);
+        let field_clone = field.clone();
+
+        drop(field);
+        assert!(cell.try_borrow_mut().is_err());
+        assert_eq!(1, *field_clone);
+    }
+
     #[derive(Debug, Clone, PartialEq, Tid)]
     struct A(usize);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct B(usize, usize);
 }
@@ -3,31 +3,141 @@ use std::{
     fmt,
     marker::PhantomData,
     ops::{Deref, DerefMut},
+    ptr::NonNull,
+    sync::Arc,
+    thread,
 };
 
 use better_any::TidExt;
 
+#[cfg(feature = "track_borrows")]
+use crate::borrow_location::BorrowRecord;
 pub use crate::Resource;
+use crate::{
+    local_resources::Stored, observers::ChangeHook, poison::Poison, slot::SlotBorrow,
+    ticks::TickRef, LocalResource,
+};
 
 /// Mutable reference to a resource.
-pub struct RefMut<'a, 'b, R: 'a> {
-    inner: rt_map::RefMut<'a, Box<dyn Resource<'b>>>,
-    phantom: PhantomData<&'a R>,
+///
+/// The reference may be narrowed down to parts of the resource with
+/// [`RefMut::map`], [`RefMut::filter_map`] and [`RefMut::map_split`], which
+/// keeps the resource borrowed.
+///
+/// Mutably dereferencing a reference into [Resources][crate::Resources] marks
+/// the resource as changed, unless [`RefMut::bypass_change_detection`] is
+/// used. Observers registered with
+/// [`Resources::on_change`][crate::Resources::on_change] are called once the
+/// reference is dropped.
+pub struct RefMut<'a, 'b, R: ?Sized + 'a> {
+    guard: Guard<'a, 'b>,
+    ticks: Option<TickRef<'a>>,
+    value: NonNull<R>,
+    phantom: PhantomData<&'a mut R>,
+}
+
+// SAFETY: `RefMut` behaves like `&mut R`. The guard is only used to release
+// the borrow when it is dropped, which is fine on any thread.
+unsafe impl<'a, 'b, R> Send for RefMut<'a, 'b, R> where R: ?Sized + Send {}
+
+/// Keeps the underlying resource borrowed.
+///
+/// The guard is shared between the references returned by
+/// [`RefMut::map_split`].
+enum Guard<'a, 'b> {
+    Unique(Borrow<'a, 'b>),
+    Shared(Arc<Borrow<'a, 'b>>),
+}
+
+impl<'a, 'b> Guard<'a, 'b> {
+    fn share(self) -> Arc<Borrow<'a, 'b>> {
+        match self {
+            Self::Unique(borrow) => Arc::new(borrow),
+            Self::Shared(borrow) => borrow,
+        }
+    }
+
+    fn borrow(&self) -> &Borrow<'a, 'b> {
+        match self {
+            Self::Unique(borrow) => borrow,
+            Self::Shared(borrow) => borrow,
+        }
+    }
+}
+
+struct Borrow<'a, 'b> {
+    // Only held to release the borrow on drop.
+    #[allow(dead_code)]
+    inner: Option<CellGuard<'a, 'b>>,
+    #[cfg(feature = "track_borrows")]
+    record: Option<BorrowRecord<'a>>,
+    hook: Option<ChangeHook<'a, 'b>>,
+    /// Poison flag of the resource, which is set on panic.
+    poison: Option<&'a Poison>,
+    /// Whether the thread was already panicking when the poison flag was
+    /// attached, like the poison flag of [`std::sync::Mutex`], so only a new
+    /// panic poisons the resource.
+    was_panicking: bool,
 }
 
+impl<'a, 'b> Drop for Borrow<'a, 'b> {
+    fn drop(&mut self) {
+        if !self.was_panicking && thread::panicking() {
+            if let Some(poison) = self.poison {
+                poison.poison();
+            }
+        }
+
+        // Release the borrow first, so the observers can borrow the resource.
+        drop(self.inner.take());
+        #[cfg(feature = "track_borrows")]
+        drop(self.record.take());
+
+        if let Some(hook) = &self.hook {
+            hook.notify();
+        }
+    }
+}
+
+/// Guard of a [Resources][crate::Resources] or
+/// [LocalResources][crate::LocalResources] cell, or a
+/// [SyncResources][crate::SyncResources] slot.
+#[allow(dead_code)]
+enum CellGuard<'a, 'b> {
+    Cell(rt_map::RefMut<'a, Box<dyn Resource<'b>>>),
+    Local(rt_map::RefMut<'a, Stored<'b>>),
+    Slot(SlotBorrow<'a, 'b>),
+}
+
+// SAFETY: The guards never access the value, which is only reachable through
+// `RefMut::value` and thus covered by the `R: Send` bound of `RefMut`. Dropping
+// them only releases the borrow, which is atomic for every variant:
+// `rt_map::RefMut` wraps `rt_map::CellRefMut`, whose `Drop` resets the
+// `AtomicUsize` flag of the `rt_map::Cell` with `Ordering::Release`, and
+// `SlotBorrow` releases its slot under a `Mutex`. This also holds for
+// `CellGuard::Local`, which is only `!Send` because the `Stored` value may be,
+// so the borrow of a `LocalResources` cell may be released on another thread
+// while the map stays on its own.
+unsafe impl<'a, 'b> Send for Borrow<'a, 'b> {}
+
+// SAFETY: `Borrow` is only accessed through `&self` to flag a change, which
+// is atomic, so sharing it between the halves of `RefMut::map_split` on
+// different threads is fine.
+unsafe impl<'a, 'b> Sync for Borrow<'a, 'b> {}
+
 impl<'a, 'b, R> fmt::Debug for RefMut<'a, 'b, R>
 where
-    R: Resource<'b> + fmt::Debug + 'a,
+    R: ?Sized + fmt::Debug + 'a,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let inner: &R = self;
-        f.debug_struct("RefMut").field("inner", inner).finish()
+        f.debug_struct("RefMut").field("inner", &inner).finish()
     }
 }
 
 impl<'a, 'b, R> PartialEq for RefMut<'a, 'b, R>
 where
-    R: Resource<'b> + PartialEq + 'a,
+    R: ?Sized + PartialEq + 'a,
 {
     fn eq(&self, other: &Self) -> bool {
         let r_self: &R = self;
@@ -36,36 +146,247 @@ where
     }
 }
 
-impl<'a, 'b, R> RefMut<'a, 'b, R> {
-    pub fn new(inner: rt_map::RefMut<'a, Box<dyn Resource<'b>>>) -> Self {
+impl<'a, 'b, R> RefMut<'a, 'b, R>
+where
+    R: LocalResource<'b>,
+{
+    pub fn new(mut inner: rt_map::RefMut<'a, Box<dyn Resource<'b>>>) -> Self {
+        let value = Self::downcast(&mut **inner);
+
+        Self::from_guard(CellGuard::Cell(inner), value)
+    }
+
+    pub(crate) fn from_local(mut inner: rt_map::RefMut<'a, Stored<'b>>) -> Self {
+        let value = inner
+            .downcast_mut::<R>()
+            .map(NonNull::from)
+            .unwrap_or_else(|| panic!("Failed to downcast to {}", std::any::type_name::<R>()));
+
+        Self::from_guard(CellGuard::Local(inner), value)
+    }
+
+    fn downcast(resource: &mut dyn Resource<'b>) -> NonNull<R> {
+        resource
+            .downcast_mut::<R>()
+            .map(NonNull::from)
+            .unwrap_or_else(|| panic!("Failed to downcast to {}", std::any::type_name::<R>()))
+    }
+}
+
+impl<'a, 'b> RefMut<'a, 'b, dyn Resource<'b>> {
+    /// Borrows the resource of `inner` without downcasting it.
+    pub(crate) fn erased(mut inner: rt_map::RefMut<'a, Box<dyn Resource<'b>>>) -> Self {
+        let value = NonNull::from(&mut **inner);
+
+        Self::from_guard(CellGuard::Cell(inner), value)
+    }
+
+    /// Borrows the resource of `borrow` mutably without downcasting it, which
+    /// is poisoned on panic.
+    pub(crate) fn from_slot(mut borrow: SlotBorrow<'a, 'b>) -> Self {
+        let value = NonNull::from(borrow.value_mut() as &mut dyn Resource<'b>);
+        let poison = borrow.poison();
+
+        Self::from_guard(CellGuard::Slot(borrow), value).with_poison(poison)
+    }
+}
+
+impl<'a, 'b, R> RefMut<'a, 'b, R>
+where
+    R: ?Sized,
+{
+    fn from_guard(inner: CellGuard<'a, 'b>, value: NonNull<R>) -> Self {
         Self {
-            inner,
+            guard: Guard::Unique(Borrow {
+                inner: Some(inner),
+                #[cfg(feature = "track_borrows")]
+                record: None,
+                hook: None,
+                poison: None,
+                was_panicking: false,
+            }),
+            ticks: None,
+            value,
             phantom: PhantomData,
         }
     }
+
+    /// Keeps `record` alive for as long as this borrow.
+    #[cfg(feature = "track_borrows")]
+    pub(crate) fn with_record(mut self, record: BorrowRecord<'a>) -> Self {
+        match &mut self.guard {
+            Guard::Unique(borrow) => borrow.record = Some(record),
+            Guard::Shared(_) => unreachable!("Borrow records are attached before splitting."),
+        }
+        self
+    }
+
+    /// Notifies `hook` on drop if the resource was changed.
+    pub(crate) fn with_change_hook(mut self, hook: ChangeHook<'a, 'b>) -> Self {
+        match &mut self.guard {
+            Guard::Unique(borrow) => borrow.hook = Some(hook),
+            Guard::Shared(_) => unreachable!("Change hooks are attached before splitting."),
+        }
+        self
+    }
+
+    /// Marks the resource as changed on [`DerefMut`], and as poisoned if the
+    /// reference is dropped during a panic.
+    pub(crate) fn with_ticks(mut self, ticks: TickRef<'a>) -> Self {
+        self = self.with_poison(ticks.ticks.poison());
+        self.ticks = Some(ticks);
+        self
+    }
+
+    /// Marks the resource as poisoned if the reference is dropped during a
+    /// panic.
+    pub(crate) fn with_poison(mut self, poison: &'a Poison) -> Self {
+        match &mut self.guard {
+            Guard::Unique(borrow) => {
+                borrow.poison = Some(poison);
+                borrow.was_panicking = thread::panicking();
+            }
+            Guard::Shared(_) => unreachable!("Poison flags are attached before splitting."),
+        }
+        self
+    }
+
+    /// Returns a mutable reference to the resource without marking it as
+    /// changed.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `RefMut::bypass_change_detection(...)`, so it does not interfere with
+    /// methods of `R`.
+    pub fn bypass_change_detection(orig: &mut Self) -> &mut R {
+        // SAFETY: See `RefMut::map`.
+        unsafe { orig.value.as_mut() }
+    }
+
+    /// Makes a new `RefMut` for a component of the borrowed resource.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `RefMut::map(...)`, so it does not interfere with methods of `R`.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::{RefMut, Resources};
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Position(u32, u32);
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Position(1, 2));
+    ///
+    /// let mut x = RefMut::map(resources.borrow_mut::<Position>(), |position| &mut position.0);
+    /// *x = 3;
+    /// ```
+    pub fn map<U, F>(orig: Self, f: F) -> RefMut<'a, 'b, U>
+    where
+        U: ?Sized,
+        F: FnOnce(&mut R) -> &mut U,
+    {
+        // SAFETY: `value` points into the resource, which stays exclusively
+        // borrowed for as long as `guard` is alive.
+        let value = NonNull::from(f(unsafe { &mut *orig.value.as_ptr() }));
+
+        RefMut {
+            guard: orig.guard,
+            ticks: orig.ticks,
+            value,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Makes a new `RefMut` for an optional component of the borrowed
+    /// resource. The original guard is returned as `Err(..)` if the closure
+    /// returns `None`.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `RefMut::filter_map(...)`, so it does not interfere with methods of
+    /// `R`.
+    #[allow(clippy::result_large_err)]
+    pub fn filter_map<U, F>(orig: Self, f: F) -> Result<RefMut<'a, 'b, U>, Self>
+    where
+        U: ?Sized,
+        F: FnOnce(&mut R) -> Option<&mut U>,
+    {
+        // SAFETY: See `RefMut::map`.
+        match f(unsafe { &mut *orig.value.as_ptr() }).map(NonNull::from) {
+            Some(value) => Ok(RefMut {
+                guard: orig.guard,
+                ticks: orig.ticks,
+                value,
+                phantom: PhantomData,
+            }),
+            None => Err(orig),
+        }
+    }
+
+    /// Splits a `RefMut` into multiple `RefMut`s for different components of
+    /// the borrowed resource.
+    ///
+    /// The resource stays borrowed until both returned references are
+    /// dropped.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `RefMut::map_split(...)`, so it does not interfere with methods of
+    /// `R`.
+    pub fn map_split<U, V, F>(orig: Self, f: F) -> (RefMut<'a, 'b, U>, RefMut<'a, 'b, V>)
+    where
+        U: ?Sized,
+        V: ?Sized,
+        F: FnOnce(&mut R) -> (&mut U, &mut V),
+    {
+        // SAFETY: See `RefMut::map`. The closure guarantees that `u` and `v`
+        // don't alias.
+        let (u, v) = f(unsafe { &mut *orig.value.as_ptr() });
+        let (u, v) = (NonNull::from(u), NonNull::from(v));
+        let guard = orig.guard.share();
+
+        (
+            RefMut {
+                guard: Guard::Shared(guard.clone()),
+                ticks: orig.ticks,
+                value: u,
+                phantom: PhantomData,
+            },
+            RefMut {
+                guard: Guard::Shared(guard),
+                ticks: orig.ticks,
+                value: v,
+                phantom: PhantomData,
+            },
+        )
+    }
 }
 
 impl<'a, 'b, R> Deref for RefMut<'a, 'b, R>
 where
-    R: Resource<'b>,
+    R: ?Sized,
 {
     type Target = R;
 
     fn deref(&self) -> &R {
-        self.inner
-            .downcast_ref::<R>()
-            .unwrap_or_else(|| panic!("Failed to downcast to {}", std::any::type_name::<R>()))
+        // SAFETY: See `RefMut::map`.
+        unsafe { self.value.as_ref() }
     }
 }
 
 impl<'a, 'b, R> DerefMut for RefMut<'a, 'b, R>
 where
-    R: Resource<'b>,
+    R: ?Sized,
 {
     fn deref_mut(&mut self) -> &mut R {
-        self.inner
-            .downcast_mut::<R>()
-            .unwrap_or_else(|| panic!("Failed to downcast to {}", std::any::type_name::<R>()))
+        if let Some(ticks) = self.ticks {
+            ticks.set_changed();
+        }
+        if let Some(hook) = &self.guard.borrow().hook {
+            hook.set_changed();
+        }
+
+        Self::bypass_change_detection(self)
     }
 }
 
@@ -73,7 +394,7 @@ where
 mod tests {
     use std::fmt::{self, Write};
 
-    use better_any::Tid;
+    use better_any::{Tid, TidExt};
     use rt_map::Cell;
 
     use crate::Resource;
@@ -122,6 +443,59 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn map_projects_to_field() -> fmt::Result {
+        let value: Box<dyn Resource> = Box::new(B(1, 2));
+        let cell = Cell::new(value);
+        let ref_mut = RefMut::<B>::new(rt_map::RefMut::new(cell.borrow_mut()));
+        let mut field = RefMut::map(ref_mut, |b| &mut b.1);
+
+        *field = 3;
+
+        let mut debug_string = String::with_capacity(64);
+        write!(&mut debug_string, "{:?}", field)?;
+        assert_eq!("RefMut { inner: 3 }", debug_string.as_str());
+        assert!(cell.try_borrow().is_err());
+
+        drop(field);
+        assert_eq!(&B(1, 3), cell.borrow().downcast_ref::<B>().unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_map_returns_original_on_none() {
+        let value: Box<dyn Resource> = Box::new(B(1, 2));
+        let cell = Cell::new(value);
+        let ref_mut = RefMut::<B>::new(rt_map::RefMut::new(cell.borrow_mut()));
+
+        let ref_mut = RefMut::filter_map(ref_mut, |_| None::<&mut usize>).unwrap_err();
+        assert_eq!(&B(1, 2), &*ref_mut);
+
+        let field = RefMut::filter_map(ref_mut, |b| Some(&mut b.0)).unwrap();
+        assert_eq!(1, *field);
+    }
+
+    #[test]
+    fn map_split_keeps_resource_borrowed_until_both_are_dropped() {
+        let value: Box<dyn Resource> = Box::new(B(1, 2));
+        let cell = Cell::new(value);
+        let ref_mut = RefMut::<B>::new(rt_map::RefMut::new(cell.borrow_mut()));
+
+        let (mut first, mut second) = RefMut::map_split(ref_mut, |b| (&mut b.0, &mut b.1));
+        *first += 10;
+        *second += 20;
+
+        drop(first);
+        assert!(cell.try_borrow().is_err());
+
+        drop(second);
+        assert_eq!(&B(11, 22), cell.borrow().downcast_ref::<B>().unwrap());
+    }
+
     #[derive(Debug, Clone, PartialEq, Tid)]
     struct A(usize);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct B(usize, usize);
 }
@@ -1,51 +1,115 @@
 use std::fmt;
 
-use better_any::Tid;
+use better_any::{Tid, TidExt};
 
 /// Trait to represent any type that is `Send + 'a`.
 ///
 /// A resource is a data slot which lives in the [Resources][crate::Resources] can only be accessed
 /// according to Rust's typical borrowing model (one writer xor multiple
 /// readers).
-#[cfg(not(feature = "debug"))]
-pub trait Resource<'a>: Tid<'a> + Send {
+pub trait Resource<'a>: LocalResource<'a> + Send {}
+
+impl<'a, T> Resource<'a> for T where T: Tid<'a> + Send {}
+
+/// Trait to represent any type that is `'a`, which may not be `Send`.
+///
+/// Local resources can only be stored in
+/// [LocalResources][crate::LocalResources], which is not `Send` itself.
+pub trait LocalResource<'a>: Tid<'a> {
     fn type_name(&self) -> TypeNameLit;
 }
 
-#[cfg(not(feature = "debug"))]
-impl<'a, T> Resource<'a> for T
+impl<'a, T> LocalResource<'a> for T
 where
-    T: Tid<'a> + Send,
+    T: Tid<'a>,
 {
     fn type_name(&self) -> TypeNameLit {
         TypeNameLit(std::any::type_name::<T>())
     }
 }
 
-/// Trait to represent any type that is `Send + 'a`.
-///
-/// A resource is a data slot which lives in the [Resources][crate::Resources] can only be accessed
-/// according to Rust's typical borrowing model (one writer xor multiple
-/// readers).
-#[cfg(feature = "debug")]
-pub trait Resource<'a>: Tid<'a> + Send + std::fmt::Debug {
-    fn type_name(&self) -> TypeNameLit;
+/// Downcasts a type erased resource, which is expected to be an `R`.
+pub(crate) fn downcast_ref<'r, 'a, R>(resource: &'r (dyn Resource<'a> + '_)) -> &'r R
+where
+    R: Resource<'a>,
+{
+    resource
+        .downcast_ref::<R>()
+        .unwrap_or_else(|| panic!("Failed to downcast to {}", std::any::type_name::<R>()))
 }
 
-#[cfg(feature = "debug")]
-impl<'a, T> Resource<'a> for T
+/// Downcasts a type erased resource mutably, which is expected to be an `R`.
+pub(crate) fn downcast_mut<'r, 'a, R>(resource: &'r mut (dyn Resource<'a> + '_)) -> &'r mut R
 where
-    T: Tid<'a> + std::fmt::Debug + Send + 'a,
+    R: Resource<'a>,
 {
-    fn type_name(&self) -> TypeNameLit {
-        TypeNameLit(std::any::type_name::<T>())
+    resource
+        .downcast_mut::<R>()
+        .unwrap_or_else(|| panic!("Failed to downcast to {}", std::any::type_name::<R>()))
+}
+
+/// Downcasts a boxed type erased resource, which is expected to be an `R`.
+pub(crate) fn downcast_box<'a, R>(resource: Box<dyn Resource<'a>>) -> Box<R>
+where
+    R: Resource<'a>,
+{
+    resource
+        .downcast_box::<R>()
+        .unwrap_or_else(|_| panic!("Failed to downcast to {}", std::any::type_name::<R>()))
+}
+
+/// Formats a type erased resource, see
+/// [`Resources::register_debug`][crate::Resources::register_debug].
+pub(crate) type DebugFn<'a> = fn(&dyn Resource<'a>, &mut fmt::Formatter) -> fmt::Result;
+
+pub(crate) fn debug_resource<'a, R>(
+    resource: &dyn Resource<'a>,
+    f: &mut fmt::Formatter,
+) -> fmt::Result
+where
+    R: Resource<'a> + fmt::Debug,
+{
+    match resource.downcast_ref::<R>() {
+        Some(resource) => fmt::Debug::fmt(resource, f),
+        None => fmt::Debug::fmt(&"..", f),
     }
 }
 
+/// Formats a resource with its registered [DebugFn], or as `".."` if there
+/// is none.
+pub(crate) struct DebugResource<'r, 'a> {
+    pub(crate) resource: &'r dyn Resource<'a>,
+    pub(crate) debug_fn: Option<DebugFn<'a>>,
+}
+
+impl<'r, 'a> fmt::Debug for DebugResource<'r, 'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.debug_fn {
+            Some(debug_fn) => debug_fn(self.resource, f),
+            // At runtime, we are unable to determine if the resource is `Debug`.
+            None => fmt::Debug::fmt(&"..", f),
+        }
+    }
+}
+
+/// Name of a resource type, used for debugging and error messages.
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct TypeNameLit(&'static str);
 
+impl TypeNameLit {
+    pub(crate) fn of<T: ?Sized>() -> Self {
+        Self(std::any::type_name::<T>())
+    }
+}
+
 impl fmt::Debug for TypeNameLit {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
+
+impl fmt::Display for TypeNameLit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
@@ -0,0 +1,499 @@
+use std::{any::TypeId, collections::HashMap, fmt};
+
+use better_any::TidExt;
+use rt_map::Cell;
+use serde::{
+    de::{self, DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess, Visitor},
+    ser::{self, SerializeMap},
+    Deserializer, Serialize, Serializer,
+};
+
+use crate::{Label, Resource, TypeNameLit};
+
+/// What to do with resources whose type is not registered in a
+/// [ResourceRegistry].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnregisteredPolicy {
+    /// Leave the resource out of the snapshot, or ignore it when restoring.
+    #[default]
+    Skip,
+    /// Fail with an error naming the resource.
+    Error,
+}
+
+/// Records how to serialize and deserialize resource types, each under a
+/// stable name.
+///
+/// The registry is used by [`Resources::snapshot`] and
+/// [`Resources::restore`], which save and load the resources as a map from
+/// the registered names to the values.
+///
+/// ## Examples
+///
+/// ```rust
+/// use better_any::Tid;
+/// use serde::{Deserialize, Serialize};
+/// use stateman::{ResourceRegistry, Resources};
+///
+/// #[derive(Debug, Tid, Serialize, Deserialize)]
+/// struct Score(u32);
+///
+/// let mut registry = ResourceRegistry::new();
+/// registry.register::<Score>("score");
+///
+/// let mut resources = Resources::default();
+/// resources.insert(Score(3));
+///
+/// let mut json = Vec::new();
+/// resources
+///     .snapshot(&registry, &mut serde_json::Serializer::new(&mut json))
+///     .unwrap();
+/// assert_eq!(r#"{"score":3}"#, String::from_utf8(json).unwrap());
+/// ```
+#[derive(Default)]
+pub struct ResourceRegistry<'a> {
+    registrations: HashMap<&'static str, Registration<'a>>,
+    names: HashMap<TypeId, &'static str>,
+    policy: UnregisteredPolicy,
+}
+
+/// Type erased (de)serialization functions of a registered type.
+struct Registration<'a> {
+    type_id: TypeId,
+    serialize: for<'r> fn(&'r dyn Resource<'a>) -> Option<&'r dyn erased_serde::Serialize>,
+    deserialize: DeserializeFn<'a>,
+}
+
+/// Resources deserialized from a snapshot, keyed by type.
+type Restored<'a> = Vec<(TypeId, Box<dyn Resource<'a> + 'a>)>;
+
+type DeserializeFn<'a> = for<'de> fn(
+    &mut dyn erased_serde::Deserializer<'de>,
+) -> Result<Box<dyn Resource<'a>>, erased_serde::Error>;
+
+impl<'a> ResourceRegistry<'a> {
+    /// Returns an empty registry, which skips unregistered resources.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the policy for unregistered resources.
+    pub fn policy(&self) -> UnregisteredPolicy {
+        self.policy
+    }
+
+    /// Sets the policy for unregistered resources.
+    pub fn set_policy(&mut self, policy: UnregisteredPolicy) -> &mut Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Registers the resource type `R` under `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` or `R` is already registered.
+    pub fn register<R>(&mut self, name: &'static str) -> &mut Self
+    where
+        R: Resource<'a> + Serialize + DeserializeOwned,
+    {
+        if let Some(existing) = self.names.get(&R::id()) {
+            panic!(
+                "Expected to register `{}` as `{name}`, but it is already registered as `{existing}`.",
+                std::any::type_name::<R>()
+            );
+        }
+        if self.registrations.contains_key(name) {
+            panic!(
+                "Expected to register `{}` as `{name}`, but the name is already taken.",
+                std::any::type_name::<R>()
+            );
+        }
+
+        self.names.insert(R::id(), name);
+        self.registrations.insert(
+            name,
+            Registration {
+                type_id: R::id(),
+                serialize: serialize_resource::<R>,
+                deserialize: deserialize_resource::<R>,
+            },
+        );
+        self
+    }
+
+    /// Returns the name under which the resource type `R` is registered.
+    pub fn name_of<R>(&self) -> Option<&'static str>
+    where
+        R: Resource<'a>,
+    {
+        self.names.get(&R::id()).copied()
+    }
+}
+
+impl<'a> fmt::Debug for ResourceRegistry<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = self.registrations.keys().collect::<Vec<_>>();
+        names.sort();
+
+        f.debug_struct("ResourceRegistry")
+            .field("names", &names)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+/// Returns `resource` as a serializable `R`, or `None` if it has another type.
+fn serialize_resource<'r, 'a, R>(
+    resource: &'r dyn Resource<'a>,
+) -> Option<&'r dyn erased_serde::Serialize>
+where
+    R: Resource<'a> + Serialize,
+{
+    resource
+        .downcast_ref::<R>()
+        .map(|resource| resource as &dyn erased_serde::Serialize)
+}
+
+fn deserialize_resource<'a, R>(
+    deserializer: &mut dyn erased_serde::Deserializer<'_>,
+) -> Result<Box<dyn Resource<'a>>, erased_serde::Error>
+where
+    R: Resource<'a> + DeserializeOwned,
+{
+    erased_serde::deserialize::<R>(deserializer).map(|r| Box::new(r) as Box<dyn Resource<'a>>)
+}
+
+/// Serializes the resources in `cells` as a map, sorted by name.
+///
+/// Named resources are not supported, so the ones in `named` are reported
+/// with [`UnregisteredPolicy::Error`].
+pub(crate) fn snapshot<'c, 'a: 'c, S>(
+    cells: impl Iterator<Item = (&'c TypeId, &'c Cell<Box<dyn Resource<'a>>>)>,
+    mut named: impl Iterator<Item = (TypeNameLit, &'c Label)>,
+    registry: &ResourceRegistry<'a>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if registry.policy == UnregisteredPolicy::Error {
+        if let Some((type_name, label)) = named.next() {
+            return Err(ser::Error::custom(format!(
+                "Expected to snapshot `{type_name}[{label:?}]`, but named resources are not supported."
+            )));
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (type_id, cell) in cells {
+        match registry.names.get(type_id) {
+            Some(name) => {
+                let resource = cell.try_borrow().map_err(|_| {
+                    ser::Error::custom(format!(
+                        "Expected to snapshot `{name}`, but it was already borrowed mutably."
+                    ))
+                })?;
+                entries.push((*name, &registry.registrations[name], resource));
+            }
+            None => match registry.policy {
+                UnregisteredPolicy::Skip => {}
+                UnregisteredPolicy::Error => {
+                    let type_name = cell
+                        .try_borrow()
+                        .map(|resource| resource.type_name().to_string())
+                        .unwrap_or_else(|_| format!("{type_id:?}"));
+                    return Err(ser::Error::custom(format!(
+                        "Expected to snapshot `{type_name}`, but it is not registered."
+                    )));
+                }
+            },
+        }
+    }
+    entries.sort_by_key(|(name, ..)| *name);
+
+    let mut map = serializer.serialize_map(Some(entries.len()))?;
+    for (name, registration, resource) in &entries {
+        let resource = (registration.serialize)(&***resource).ok_or_else(|| {
+            ser::Error::custom(format!(
+                "Expected to snapshot `{name}`, but a value of another type is stored under its id."
+            ))
+        })?;
+        map.serialize_entry(name, resource)?;
+    }
+    map.end()
+}
+
+/// Deserializes the resources in a snapshot, without inserting them.
+pub(crate) fn restore<'de, 'a, D>(
+    registry: &ResourceRegistry<'a>,
+    deserializer: D,
+) -> Result<Restored<'a>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_map(SnapshotVisitor { registry })
+}
+
+struct SnapshotVisitor<'r, 'a> {
+    registry: &'r ResourceRegistry<'a>,
+}
+
+impl<'de, 'r, 'a> Visitor<'de> for SnapshotVisitor<'r, 'a> {
+    type Value = Restored<'a>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map from resource names to resources")
+    }
+
+    fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut resources = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(name) = map.next_key::<String>()? {
+            match self.registry.registrations.get(name.as_str()) {
+                Some(registration) => {
+                    let resource = map.next_value_seed(ResourceSeed { registration })?;
+                    resources.push((registration.type_id, resource));
+                }
+                None => match self.registry.policy {
+                    UnregisteredPolicy::Skip => {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                    UnregisteredPolicy::Error => {
+                        return Err(de::Error::custom(format!(
+                            "Expected to restore `{name}`, but it is not registered."
+                        )))
+                    }
+                },
+            }
+        }
+
+        Ok(resources)
+    }
+}
+
+struct ResourceSeed<'r, 'a> {
+    registration: &'r Registration<'a>,
+}
+
+impl<'de, 'r, 'a> DeserializeSeed<'de> for ResourceSeed<'r, 'a> {
+    type Value = Box<dyn Resource<'a>>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.registration.deserialize)(&mut deserializer).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use better_any::Tid;
+    use serde::{Deserialize, Serialize};
+
+    use crate::Resources;
+
+    use super::{ResourceRegistry, UnregisteredPolicy};
+
+    fn registry() -> ResourceRegistry<'static> {
+        let mut registry = ResourceRegistry::new();
+        registry.register::<A>("a").register::<B>("b");
+        registry
+    }
+
+    fn resources() -> Resources<'static> {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        resources.insert(B {
+            name: String::from("b"),
+            values: vec![2, 3],
+        });
+        resources
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_json() {
+        let registry = registry();
+        let mut json = Vec::new();
+        resources()
+            .snapshot(&registry, &mut serde_json::Serializer::new(&mut json))
+            .unwrap();
+
+        assert_eq!(
+            r#"{"a":1,"b":{"name":"b","values":[2,3]}}"#,
+            std::str::from_utf8(&json).unwrap()
+        );
+
+        let mut restored = Resources::default();
+        restored
+            .restore(&registry, &mut serde_json::Deserializer::from_slice(&json))
+            .unwrap();
+
+        assert_eq!(&A(1), &*restored.borrow::<A>());
+        assert_eq!(&vec![2, 3], &restored.borrow::<B>().values);
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_bincode() {
+        let registry = registry();
+        let mut bytes = Vec::new();
+        resources()
+            .snapshot(
+                &registry,
+                &mut bincode::Serializer::new(&mut bytes, bincode::options()),
+            )
+            .unwrap();
+
+        let mut restored = Resources::default();
+        restored
+            .restore(
+                &registry,
+                &mut bincode::Deserializer::from_slice(&bytes, bincode::options()),
+            )
+            .unwrap();
+
+        assert_eq!(&A(1), &*restored.borrow::<A>());
+        assert_eq!("b", restored.borrow::<B>().name);
+    }
+
+    #[test]
+    fn unregistered_resources_are_skipped() {
+        let mut registry = ResourceRegistry::new();
+        registry.register::<A>("a");
+
+        let json = serde_json::to_string(&Snapshot(&resources(), &registry)).unwrap();
+        assert_eq!(r#"{"a":1}"#, json);
+
+        let mut restored = Resources::default();
+        restored
+            .restore(
+                &registry,
+                &mut serde_json::Deserializer::from_str(r#"{"a":1,"c":[true]}"#),
+            )
+            .unwrap();
+        assert!(restored.contains::<A>());
+    }
+
+    #[test]
+    fn unregistered_resources_are_reported_with_error_policy() {
+        let mut registry = ResourceRegistry::new();
+        registry
+            .register::<A>("a")
+            .set_policy(UnregisteredPolicy::Error);
+
+        let error = serde_json::to_string(&Snapshot(&resources(), &registry)).unwrap_err();
+        assert!(error.to_string().contains("is not registered"), "{error}");
+
+        let mut restored = Resources::default();
+        let error = restored
+            .restore(
+                &registry,
+                &mut serde_json::Deserializer::from_str(r#"{"a":1,"c":[true]}"#),
+            )
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Expected to restore `c`, but it is not registered."),
+            "{error}"
+        );
+        assert!(!restored.contains::<A>());
+    }
+
+    #[test]
+    fn snapshot_of_mutably_borrowed_resource_fails() {
+        let resources = resources();
+        let _a = resources.borrow_mut::<A>();
+
+        let error = serde_json::to_string(&Snapshot(&resources, &registry())).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Expected to snapshot `a`, but it was already borrowed mutably."),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn derived_resources_are_left_out() {
+        let mut resources = resources();
+        resources
+            .insert_derived::<C, (A,)>(|a: &A| C(a.0 * 2))
+            .unwrap();
+        let mut registry = registry();
+        registry
+            .register::<C>("c")
+            .set_policy(UnregisteredPolicy::Error);
+
+        let json = serde_json::to_string(&Snapshot(&resources, &registry)).unwrap();
+        assert_eq!(r#"{"a":1,"b":{"name":"b","values":[2,3]}}"#, json);
+    }
+
+    #[test]
+    fn named_resources_are_reported_with_error_policy() {
+        let mut resources = resources();
+        resources.insert_named("left", A(2));
+        let mut registry = registry();
+
+        let json = serde_json::to_string(&Snapshot(&resources, &registry)).unwrap();
+        assert_eq!(r#"{"a":1,"b":{"name":"b","values":[2,3]}}"#, json);
+
+        registry.set_policy(UnregisteredPolicy::Error);
+        let error = serde_json::to_string(&Snapshot(&resources, &registry)).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("but named resources are not supported."),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn mismatched_insert_raw_is_reported() {
+        let mut resources = resources();
+        resources.insert_raw(A::id(), Box::new(C(1)));
+
+        let error = serde_json::to_string(&Snapshot(&resources, &registry())).unwrap_err();
+        assert!(
+            error.to_string().contains(
+                "Expected to snapshot `a`, but a value of another type is stored under its id."
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "but the name is already taken")]
+    fn register_duplicate_name_panics() {
+        let mut registry = ResourceRegistry::new();
+        registry.register::<A>("a").register::<B>("a");
+    }
+
+    /// Serializes a snapshot with `serde_json::to_string`.
+    struct Snapshot<'r>(&'r Resources<'static>, &'r ResourceRegistry<'static>);
+
+    impl<'r> Serialize for Snapshot<'r> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            self.0.snapshot(self.1, serializer)
+        }
+    }
+
+    #[derive(Debug, PartialEq, Tid, Serialize, Deserialize)]
+    struct A(u32);
+
+    #[derive(Debug, PartialEq, Tid, Serialize, Deserialize)]
+    struct B {
+        name: String,
+        values: Vec<u32>,
+    }
+
+    #[derive(Debug, PartialEq, Tid, Serialize, Deserialize)]
+    struct C(u32);
+}
@@ -1,17 +1,51 @@
+#[cfg(feature = "track_borrows")]
+use std::panic::Location;
 use std::{
     any::TypeId,
+    collections::{hash_map, HashMap},
     fmt,
-    ops::{Deref, DerefMut},
+    sync::{self, Mutex},
 };
 
 use better_any::TidExt;
 use rt_map::{BorrowFail, Cell, RtMap};
-
-use crate::{Entry, Ref, RefMut, Resource};
+#[cfg(feature = "serde")]
+use serde::{Deserializer, Serializer};
+
+#[cfg(feature = "track_borrows")]
+use crate::borrow_location::BorrowTracker;
+use crate::{
+    derived::Derived,
+    dyn_cast::TraitCasts,
+    entry::Tracking,
+    label::SlotKey,
+    observers::{ChangeHook, Observer, ObserverId, Observers},
+    poison::PoisonResult,
+    resource::{self, DebugFn, DebugResource},
+    ticks::{TickRef, Ticks},
+    transaction::{self, CloneFn, Transaction},
+    BorrowError, BorrowMode, BorrowState, Commands, Derive, Entry, Label, Ref, RefMut, Resource,
+    ScopedResources, SystemData, TypeNameLit,
+};
+#[cfg(feature = "serde")]
+use crate::{resource_registry, ResourceRegistry};
 
 /// A set of types (resources), or map from `TypeId` to type.
 #[derive(Default)]
-pub struct Resources<'a>(RtMap<TypeId, Box<dyn Resource<'a>>>);
+pub struct Resources<'a> {
+    map: RtMap<TypeId, Box<dyn Resource<'a>>>,
+    named: RtMap<(TypeId, Label), Box<dyn Resource<'a>>>,
+    ticks: HashMap<SlotKey, Ticks>,
+    tick: u64,
+    clones: HashMap<TypeId, CloneFn<'a>>,
+    debugs: HashMap<TypeId, DebugFn<'a>>,
+    derived: HashMap<TypeId, Derived<'a>>,
+    traits: TraitCasts<'a>,
+    commands: Mutex<Commands<'a>>,
+    observers: Observers<'a>,
+    #[cfg(feature = "track_borrows")]
+    borrows: BorrowTracker,
+}
 
 /// A [Resource] container, which provides methods to insert, access and manage
 /// the contained resources.
@@ -24,6 +58,16 @@ pub struct Resources<'a>(RtMap<TypeId, Box<dyn Resource<'a>>>);
 /// # Resource Ids
 ///
 /// Resources are identified by `TypeId`s, which consist of a `TypeId`.
+///
+/// # Change Detection
+///
+/// `Resources` keeps a world tick, which is advanced with
+/// [`increment_tick`]. Each resource records the tick at which it was added
+/// and the tick at which it was last changed, i.e. mutably dereferenced
+/// through a [RefMut] or accessed with [`get_mut`].
+///
+/// [`increment_tick`]: Self::increment_tick
+/// [`get_mut`]: Self::get_mut
 impl<'a> Resources<'a> {
     /// Creates an empty `Resources` map.
     ///
@@ -52,7 +96,20 @@ impl<'a> Resources<'a> {
     /// let resources: Resources = Resources::with_capacity(10);
     /// ```
     pub fn with_capacity(capacity: usize) -> Self {
-        Self(RtMap::with_capacity(capacity))
+        Self {
+            map: RtMap::with_capacity(capacity),
+            named: RtMap::with_capacity(capacity),
+            ticks: HashMap::with_capacity(capacity),
+            tick: 0,
+            clones: HashMap::new(),
+            debugs: HashMap::new(),
+            derived: HashMap::new(),
+            traits: TraitCasts::default(),
+            commands: Mutex::default(),
+            observers: Observers::default(),
+            #[cfg(feature = "track_borrows")]
+            borrows: BorrowTracker::default(),
+        }
     }
 
     /// Returns the number of elements the map can hold without reallocating.
@@ -68,15 +125,73 @@ impl<'a> Resources<'a> {
     /// assert!(resources.capacity() >= 100);
     /// ```
     pub fn capacity(&self) -> usize {
-        self.0.capacity()
+        self.map.capacity()
     }
 
     /// Returns an entry for the resource with type `R`.
+    ///
+    /// References returned by the entry behave like the ones of
+    /// [`borrow_mut`], so changes are tracked and observed, and borrows are
+    /// recorded with the `"track_borrows"` feature.
+    ///
+    /// See [`try_entry`] for a non-panicking version of this function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is derived from other resources, see
+    /// [`insert_derived`].
+    /// Panics if `R` is poisoned, see [`is_poisoned`].
+    ///
+    /// [`borrow_mut`]: Self::borrow_mut
+    /// [`insert_derived`]: Self::insert_derived
+    /// [`is_poisoned`]: Self::is_poisoned
+    /// [`try_entry`]: Self::try_entry
     pub fn entry<'b, R>(&'b mut self) -> Entry<'b, 'a, R>
     where
         R: Resource<'a>,
     {
-        Entry::new(self.0.entry(R::id()))
+        self.try_entry::<R>().unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Returns an entry for the resource with type `R`, or an error if `R`
+    /// can't be borrowed mutably.
+    ///
+    /// Fails if `R` is derived from other resources, poisoned, or the value
+    /// stored for it has a different type.
+    pub fn try_entry<'b, R>(&'b mut self) -> Result<Entry<'b, 'a, R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        if self.derived.contains_key(&R::id()) {
+            return Err(BorrowError::ReadOnly {
+                type_name: TypeNameLit::of::<R>(),
+                label: None,
+            });
+        }
+        if self.is_poisoned::<R>() {
+            return Err(BorrowError::Poisoned {
+                type_name: TypeNameLit::of::<R>(),
+                label: None,
+                mode: BorrowMode::Exclusive,
+            });
+        }
+        if let Some(resource) = self.map.get_resource_mut(&R::id()) {
+            if (**resource).self_id() != R::id() {
+                return Err(Self::type_mismatch::<R>(BorrowMode::Exclusive));
+            }
+        }
+
+        Ok(Entry::new(
+            (*self.map).entry(R::id()),
+            Tracking {
+                ticks: &mut self.ticks,
+                tick: self.tick,
+                derived: &mut self.derived,
+                observers: &self.observers,
+                #[cfg(feature = "track_borrows")]
+                borrows: &self.borrows,
+            },
+        ))
     }
 
     /// Inserts a resource into the map. If the resource existed before,
@@ -92,7 +207,8 @@ impl<'a> Resources<'a> {
     /// struct MyRes(i32);
     /// ```
     ///
-    /// When you have a resource, simply insert it like this:
+    /// When you have a resource, simply insert it like this, which returns the
+    /// previous value of `R`:
     ///
     /// ```rust
     /// use better_any::Tid;
@@ -102,18 +218,171 @@ impl<'a> Resources<'a> {
     /// use stateman::Resources;
     ///
     /// let mut resources = Resources::default();
-    /// resources.insert(MyRes(5));
+    /// assert_eq!(None, resources.insert(MyRes(5)).map(|old| old.0));
+    /// assert_eq!(Some(5), resources.insert(MyRes(6)).map(|old| old.0));
+    /// ```
+    ///
+    /// See [`try_insert`] for a non-panicking version of this function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the previous value has a different type, see
+    /// [`BorrowError::TypeMismatch`].
+    ///
+    /// [`try_insert`]: Self::try_insert
+    pub fn insert<R>(&mut self, r: R) -> Option<R>
+    where
+        R: Resource<'a>,
+    {
+        self.try_insert(r).unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Inserts a resource into the map like [`insert`], and returns the
+    /// previous value of `R`.
+    ///
+    /// Returns [`BorrowError::TypeMismatch`] and keeps the previous value if
+    /// it has a different type, so it can still be removed with
+    /// [`insert_raw`].
+    ///
+    /// [`insert`]: Self::insert
+    /// [`insert_raw`]: Self::insert_raw
+    pub fn try_insert<R>(&mut self, r: R) -> Result<Option<R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        if let Err(error @ BorrowError::TypeMismatch { .. }) =
+            self.check_type::<R>(BorrowMode::Exclusive)
+        {
+            return Err(error);
+        }
+
+        let old = self.insert_raw(R::id(), Box::new(r)).map(|old| {
+            old.downcast_box()
+                .unwrap_or_else(|_| unreachable!("Expected previous value to be an `R`."))
+        });
+
+        Ok(old.map(|old: Box<R>| *old))
+    }
+
+    /// Inserts an already boxed resource into the map and returns the previous
+    /// one.
+    pub fn insert_raw(
+        &mut self,
+        type_id: TypeId,
+        resource: Box<dyn Resource<'a>>,
+    ) -> Option<Box<dyn Resource<'a>>> {
+        self.derived.remove(&type_id);
+        let type_name = resource.type_name();
+        let old = self.map.insert(type_id, resource);
+        self.ticks
+            .insert((type_id, None), Ticks::new(self.tick, type_name));
+
+        let new = self
+            .map
+            .get_resource_mut(&type_id)
+            .expect("Expected resource to exist after insertion.");
+        match &old {
+            Some(old) => self.observers.replaced(type_id, &**old, &**new),
+            None => self.observers.inserted(type_id, &**new),
+        }
+
+        old
+    }
+
+    /// Replaces the value of `R` and returns the previous one.
+    ///
+    /// Returns an error and leaves the map unchanged if `R` doesn't exist or
+    /// the stored value has a different type.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::{BorrowError, Resources};
+    ///
+    /// #[derive(Debug, Default, PartialEq, Tid)]
+    /// struct Score(u32);
+    ///
+    /// let mut resources = Resources::default();
+    /// assert!(matches!(
+    ///     resources.replace(Score(1)),
+    ///     Err(BorrowError::NotFound { .. })
+    /// ));
+    ///
+    /// resources.insert(Score(2));
+    /// assert_eq!(Ok(Score(2)), resources.replace(Score(3)));
+    /// assert_eq!(Ok(Score(3)), resources.take::<Score>());
+    /// assert_eq!(&Score(0), &*resources.borrow::<Score>());
     /// ```
-    pub fn insert<R>(&mut self, r: R)
+    pub fn replace<R>(&mut self, r: R) -> Result<R, BorrowError>
     where
         R: Resource<'a>,
     {
-        self.0.insert(R::id(), Box::new(r));
+        self.check_type::<R>(BorrowMode::Exclusive)?;
+        self.insert(r).ok_or(BorrowError::NotFound {
+            type_name: TypeNameLit::of::<R>(),
+            label: None,
+            mode: BorrowMode::Exclusive,
+        })
+    }
+
+    /// Replaces the value of `R` with its default value and returns the
+    /// previous one, see [`replace`].
+    ///
+    /// [`replace`]: Self::replace
+    pub fn take<R>(&mut self) -> Result<R, BorrowError>
+    where
+        R: Resource<'a> + Default,
+    {
+        self.replace(R::default())
     }
 
-    /// Inserts an already boxed resource into the map.
-    pub fn insert_raw(&mut self, type_id: TypeId, resource: Box<dyn Resource<'a>>) {
-        self.0.insert(type_id, resource);
+    /// Exchanges the values of `R` in `self` and `other`. If only one of them
+    /// contains `R`, it is moved to the other one.
+    ///
+    /// Each value keeps its ticks, poison and [derived][Self::insert_derived]
+    /// registration. The replace observers of both maps are called, or the
+    /// insert and remove observers if the value is moved.
+    ///
+    /// Returns an error and leaves both maps unchanged if a value stored for
+    /// `R` has a different type, or if a derived `R` would be derived from
+    /// itself in the other map, see [`BorrowError::DerivedCycle`].
+    pub fn swap<R>(&mut self, other: &mut Resources<'a>) -> Result<(), BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        for resources in [&*self, &*other] {
+            if let Err(error @ BorrowError::TypeMismatch { .. }) =
+                resources.check_type::<R>(BorrowMode::Exclusive)
+            {
+                return Err(error);
+            }
+        }
+
+        let type_id = R::id();
+        for (from, to) in [(&*self, &*other), (&*other, &*self)] {
+            if let Some(derived) = from.derived.get(&type_id) {
+                if to.derives_from(derived.inputs(), type_id) {
+                    return Err(BorrowError::DerivedCycle {
+                        type_name: TypeNameLit::of::<R>(),
+                        label: None,
+                    });
+                }
+            }
+        }
+
+        let ours = self.detach(type_id);
+        let theirs = other.detach(type_id);
+        if let Some(theirs) = theirs {
+            self.attach(type_id, theirs);
+        }
+        if let Some(ours) = ours {
+            other.attach(type_id, ours);
+        }
+        self.swapped(type_id, other);
+        other.swapped(type_id, self);
+
+        Ok(())
     }
 
     /// Removes a resource of type `R` from this container and returns its
@@ -124,15 +393,17 @@ impl<'a> Resources<'a> {
     /// this resource still exists. Thus, only use this if you're sure no
     /// system will try to access this resource after you removed it (or else
     /// you will get a panic).
+    ///
+    /// If the value stored for `R` has a different type, it is kept and
+    /// `None` is returned.
     pub fn remove<R>(&mut self) -> Option<R>
     where
         R: Resource<'a>,
     {
-        self.0
-            .remove(&R::id())
-            .map(|x: Box<dyn Resource>| x.downcast_box())
-            .map(|x: Result<Box<R>, _>| x.ok().unwrap())
-            .map(|x| *x)
+        self.check_type::<R>(BorrowMode::Exclusive).ok()?;
+        self.remove_raw(R::id())
+            .and_then(|x| x.downcast_box().ok())
+            .map(|x: Box<R>| *x)
     }
 
     /// Returns true if the specified resource type `R` exists in `self`.
@@ -140,7 +411,112 @@ impl<'a> Resources<'a> {
     where
         R: Resource<'a>,
     {
-        self.0.contains_key(&R::id())
+        self.map.contains_key(&R::id())
+    }
+
+    /// Returns the id, type name and borrow state of each resource.
+    ///
+    /// The borrow states are probed lazily while iterating. Named resources
+    /// are not included, see [`iter_named`].
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::{BorrowState, Resources};
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Score(u32);
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Score(0));
+    ///
+    /// let score = resources.borrow::<Score>();
+    /// for (_, type_name, borrow_state) in resources.iter() {
+    ///     assert!(type_name.to_string().ends_with("Score"));
+    ///     assert_eq!(BorrowState::Shared(1), borrow_state);
+    /// }
+    /// ```
+    ///
+    /// [`iter_named`]: Self::iter_named
+    pub fn iter(&self) -> ResourcesIter<'_, 'a> {
+        ResourcesIter {
+            resources: self,
+            type_ids: self.map.keys(),
+        }
+    }
+
+    /// Returns the number of resources, including named ones.
+    pub fn len(&self) -> usize {
+        self.map.len() + self.named.len()
+    }
+
+    /// Returns true if `self` contains no resources, including named ones.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the type names of the resources.
+    ///
+    /// Named resources are not included.
+    pub fn type_names(&self) -> impl Iterator<Item = TypeNameLit> + '_ {
+        self.ticks
+            .iter()
+            .filter(|((_, label), _)| label.is_none())
+            .map(|(_, ticks)| ticks.type_name())
+    }
+
+    /// Returns how the resource `R` is currently borrowed, or `None` if it
+    /// doesn't exist.
+    pub fn borrow_state<R>(&self) -> Option<BorrowState>
+    where
+        R: Resource<'a>,
+    {
+        self.borrow_state_of(R::id())
+    }
+
+    /// Removes the resources for which `f` returns `false` and returns them.
+    ///
+    /// Named resources are kept.
+    pub fn retain<F>(&mut self, mut f: F) -> Vec<Box<dyn Resource<'a>>>
+    where
+        F: FnMut((TypeId, TypeNameLit, BorrowState)) -> bool,
+    {
+        let removed = self
+            .iter()
+            .filter(|meta| !f(*meta))
+            .map(|(type_id, _, _)| type_id)
+            .collect::<Vec<_>>();
+
+        removed
+            .into_iter()
+            .filter_map(|type_id| self.remove_raw(type_id))
+            .collect()
+    }
+
+    /// Removes all resources, including named ones, and returns an iterator
+    /// over them.
+    ///
+    /// The resources are handed out one by one while iterating. Resources
+    /// which are left when the iterator is dropped are removed as well.
+    pub fn drain(&mut self) -> ResourcesDrain<'_, 'a> {
+        self.ticks.clear();
+        self.derived.clear();
+
+        ResourcesDrain {
+            resources: self.map.drain(),
+            named: self.named.drain(),
+            observers: &self.observers,
+        }
+    }
+
+    /// Removes all resources, including named ones, and returns them.
+    ///
+    /// Use [`retain`] to remove only the unnamed resources.
+    ///
+    /// [`retain`]: Self::retain
+    pub fn clear(&mut self) -> Vec<Box<dyn Resource<'a>>> {
+        self.drain().collect()
     }
 
     /// Returns the `R` resource in the resource map.
@@ -153,20 +529,80 @@ impl<'a> Resources<'a> {
     /// Panics if the resource is being accessed mutably.
     ///
     /// [`try_borrow`]: Self::try_borrow
+    #[cfg_attr(feature = "track_borrows", track_caller)]
     pub fn borrow<R>(&self) -> Ref<'_, 'a, R>
     where
         R: Resource<'a>,
     {
-        self.try_borrow::<R>()
-            .unwrap_or_else(Self::borrow_panic::<R, _>)
+        self.try_borrow::<R>().unwrap_or_else(Self::borrow_panic)
     }
 
     /// Returns an immutable reference to `R` if it exists, `None` otherwise.
-    pub fn try_borrow<R>(&self) -> Result<Ref<'_, 'a, R>, BorrowFail>
+    ///
+    /// Returns [`BorrowError::Poisoned`] if a panic occurred while `R` was
+    /// borrowed mutably, see [`try_borrow_poisoned`].
+    ///
+    /// [`try_borrow_poisoned`]: Self::try_borrow_poisoned
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow<R>(&self) -> Result<Ref<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        self.try_borrow_poisoned::<R>()?.map_err(BorrowError::from)
+    }
+
+    /// Returns an immutable reference to `R` like [`try_borrow`], which is
+    /// wrapped in a [PoisonError][crate::PoisonError] if `R` is
+    /// poisoned.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use std::panic::{self, AssertUnwindSafe};
+    ///
+    /// use better_any::Tid;
+    /// use stateman::{PoisonError, Resources};
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Balance(i32);
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Balance(10));
+    ///
+    /// let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+    ///     let mut balance = resources.borrow_mut::<Balance>();
+    ///     balance.0 -= 20;
+    ///     panic!("Insufficient funds.");
+    /// }));
+    ///
+    /// let balance = resources
+    ///     .try_borrow_poisoned::<Balance>()
+    ///     .unwrap()
+    ///     .unwrap_or_else(PoisonError::into_inner);
+    /// assert_eq!(-10, balance.0);
+    /// ```
+    ///
+    /// [`try_borrow`]: Self::try_borrow
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_poisoned<R>(&self) -> Result<PoisonResult<Ref<'_, 'a, R>>, BorrowError>
     where
         R: Resource<'a>,
     {
-        self.0.try_borrow(&R::id()).map(Ref::new)
+        let r#ref = self.try_borrow_erased(
+            R::id(),
+            TypeNameLit::of::<R>(),
+            #[cfg(feature = "track_borrows")]
+            Location::caller(),
+        )?;
+        let r#ref = Ref::filter_map(r#ref, |resource| resource.downcast_ref::<R>())
+            .map_err(|_| Self::type_mismatch::<R>(BorrowMode::Shared))?;
+
+        Ok(self.check_poison(
+            r#ref,
+            &(R::id(), None),
+            TypeNameLit::of::<R>(),
+            BorrowMode::Shared,
+        ))
     }
 
     /// Returns a mutable reference to `R` if it exists, `None` otherwise.
@@ -175,157 +611,1752 @@ impl<'a> Resources<'a> {
     ///
     /// Panics if the resource doesn't exist.
     /// Panics if the resource is already accessed.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
     pub fn borrow_mut<R>(&self) -> RefMut<'_, 'a, R>
     where
         R: Resource<'a>,
     {
         self.try_borrow_mut::<R>()
-            .unwrap_or_else(Self::borrow_panic::<R, _>)
+            .unwrap_or_else(Self::borrow_panic)
     }
 
     /// Returns a mutable reference to `R` if it exists, `None` otherwise.
-    pub fn try_borrow_mut<R>(&self) -> Result<RefMut<'_, 'a, R>, BorrowFail>
+    ///
+    /// Returns [`BorrowError::Poisoned`] if a panic occurred while `R` was
+    /// borrowed mutably, see [`try_borrow_mut_poisoned`].
+    ///
+    /// [`try_borrow_mut_poisoned`]: Self::try_borrow_mut_poisoned
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_mut<R>(&self) -> Result<RefMut<'_, 'a, R>, BorrowError>
     where
         R: Resource<'a>,
     {
-        self.0.try_borrow_mut(&R::id()).map(RefMut::new)
+        self.try_borrow_mut_poisoned::<R>()?
+            .map_err(BorrowError::from)
     }
 
-    /// Retrieves a resource without fetching, which is cheaper, but only
-    /// available with `&mut self`.
-    pub fn get_mut<R>(&mut self) -> Option<&mut R>
+    /// Returns a mutable reference to `R` like [`try_borrow_mut`], which is
+    /// wrapped in a [PoisonError][crate::PoisonError] if `R` is
+    /// poisoned.
+    ///
+    /// The resource stays poisoned until [`clear_poison`] is called.
+    ///
+    /// [`try_borrow_mut`]: Self::try_borrow_mut
+    /// [`clear_poison`]: Self::clear_poison
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_mut_poisoned<R>(&self) -> Result<PoisonResult<RefMut<'_, 'a, R>>, BorrowError>
     where
         R: Resource<'a>,
     {
-        self.get_resource_mut(R::id())
-            .map(|res| res.downcast_mut().unwrap())
+        let ref_mut = self.try_borrow_mut_erased(
+            R::id(),
+            TypeNameLit::of::<R>(),
+            #[cfg(feature = "track_borrows")]
+            Location::caller(),
+        )?;
+        let ref_mut = RefMut::filter_map(ref_mut, |resource| resource.downcast_mut::<R>())
+            .map_err(|_| Self::type_mismatch::<R>(BorrowMode::Exclusive))?;
+
+        Ok(self.check_poison(
+            ref_mut,
+            &(R::id(), None),
+            TypeNameLit::of::<R>(),
+            BorrowMode::Exclusive,
+        ))
     }
 
-    /// Retrieves a resource without fetching, which is cheaper, but only
-    /// available with `&mut self`.
-    pub fn get_resource_mut(&mut self, id: TypeId) -> Option<&mut dyn Resource<'a>> {
-        self.0.get_resource_mut(&id).map(|resource| &mut **resource)
+    /// Returns true if a panic occurred while `R` was borrowed mutably.
+    ///
+    /// Like a poisoned [`Mutex`], a poisoned resource may have been left in
+    /// an inconsistent state. Inserting a new value clears the poison.
+    pub fn is_poisoned<R>(&self) -> bool
+    where
+        R: Resource<'a>,
+    {
+        matches!(self.ticks.get(&(R::id(), None)), Some(ticks) if ticks.poison().is_poisoned())
     }
 
-    /// Get raw access to the underlying cell.
-    pub fn get_raw(&self, id: &TypeId) -> Option<&Cell<Box<dyn Resource<'a>>>> {
-        self.0.get_raw(id)
+    /// Clears the poison of `R`, so it can be borrowed normally again.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::panic::{self, AssertUnwindSafe};
+    ///
+    /// use better_any::Tid;
+    /// use stateman::{BorrowError, PoisonError, Resources};
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Balance(i32);
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Balance(10));
+    ///
+    /// let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+    ///     let mut balance = resources.borrow_mut::<Balance>();
+    ///     balance.0 -= 20;
+    ///     panic!("Insufficient funds.");
+    /// }));
+    ///
+    /// assert!(matches!(
+    ///     resources.try_borrow::<Balance>(),
+    ///     Err(BorrowError::Poisoned { .. })
+    /// ));
+    /// resources
+    ///     .try_borrow_mut_poisoned::<Balance>()
+    ///     .unwrap()
+    ///     .unwrap_or_else(PoisonError::into_inner)
+    ///     .0 = 10;
+    /// resources.clear_poison::<Balance>();
+    /// assert_eq!(10, resources.borrow::<Balance>().0);
+    /// ```
+    pub fn clear_poison<R>(&self)
+    where
+        R: Resource<'a>,
+    {
+        if let Some(ticks) = self.ticks.get(&(R::id(), None)) {
+            ticks.poison().clear();
+        }
     }
 
-    fn borrow_panic<R, Ret>(borrow_fail: BorrowFail) -> Ret {
-        let type_name = std::any::type_name::<R>();
-        match borrow_fail {
-            BorrowFail::ValueNotFound => {
-                panic!("Expected to borrow `{type_name}`, but it does not exist.")
-            }
-            BorrowFail::BorrowConflictImm => panic!(
-                "Expected to borrow `{type_name}` immutably, but it was already borrowed mutably."
-            ),
-            BorrowFail::BorrowConflictMut => panic!(
-                "Expected to borrow `{type_name}` mutably, but it was already borrowed mutably."
-            ),
+    /// Inserts a resource of type `Out`, which is computed by `f` from the
+    /// resources `I`.
+    ///
+    /// The output is recomputed on [`borrow`] whenever any input has changed
+    /// since the last computation. It can't be borrowed mutably or accessed
+    /// through [`entry`], [`get_mut`] or [`get_raw`], so it only changes with
+    /// its inputs, until it is overwritten by [`insert`] or removed.
+    ///
+    /// The output can't be recomputed while it is borrowed, so if an input
+    /// changed in the meantime, further borrows return a conflict until the
+    /// output is released.
+    ///
+    /// Inputs may be derived resources themselves, which are recomputed
+    /// before the output.
+    ///
+    /// Returns an error if the inputs can't be borrowed to compute the
+    /// initial value, or [`BorrowError::DerivedCycle`] if `Out` is one of its
+    /// own inputs, directly or through other derived resources.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::Resources;
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Position(f32);
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Size(f32);
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Bounds(f32, f32);
+    ///
+    /// fn bounds(position: &Position, size: &Size) -> Bounds {
+    ///     Bounds(position.0, position.0 + size.0)
+    /// }
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Position(1.0));
+    /// resources.insert(Size(2.0));
+    /// resources
+    ///     .insert_derived::<Bounds, (Position, Size)>(bounds)
+    ///     .unwrap();
+    ///
+    /// resources.borrow_mut::<Position>().0 = 2.0;
+    /// assert_eq!(4.0, resources.borrow::<Bounds>().1);
+    /// assert!(resources.try_borrow_mut::<Bounds>().is_err());
+    /// ```
+    ///
+    /// [`borrow`]: Self::borrow
+    /// [`entry`]: Self::entry
+    /// [`get_mut`]: Self::get_mut
+    /// [`get_raw`]: Self::get_raw
+    /// [`insert`]: Self::insert
+    pub fn insert_derived<Out, I>(&mut self, f: impl Derive<'a, Out, I>) -> Result<(), BorrowError>
+    where
+        Out: Resource<'a>,
+    {
+        let derived = Derived::new(f);
+        if self.derives_from(derived.inputs(), Out::id()) {
+            return Err(BorrowError::DerivedCycle {
+                type_name: TypeNameLit::of::<Out>(),
+                label: None,
+            });
         }
+        let out = derived.derive(self, self.input_versions(derived.inputs())?)?;
+        self.insert_raw(Out::id(), out);
+        self.derived.insert(Out::id(), derived);
+
+        Ok(())
     }
-}
 
-#[cfg(not(feature = "debug"))]
-impl<'a> fmt::Debug for Resources<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut debug_map = f.debug_map();
+    /// Inserts a resource of type `R` under `label`. If a resource of the same
+    /// type and label existed before, it will be overwritten.
+    ///
+    /// Named resources are stored separately from the unnamed resource of the
+    /// same type, so multiple instances of `R` can be held at once.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::Resources;
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Camera(u32);
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert_named("left", Camera(0));
+    /// resources.insert_named("right", Camera(1));
+    ///
+    /// let mut left = resources.borrow_mut_named::<Camera>("left");
+    /// let right = resources.borrow_named::<Camera>("right");
+    /// left.0 = right.0;
+    /// ```
+    pub fn insert_named<R>(&mut self, label: impl Into<Label>, r: R)
+    where
+        R: Resource<'a>,
+    {
+        let label = label.into();
+        self.ticks.insert(
+            (R::id(), Some(label.clone())),
+            Ticks::new(self.tick, TypeNameLit::of::<R>()),
+        );
+        self.named.insert((R::id(), label), Box::new(r));
+    }
+
+    /// Removes the resource of type `R` with `label` from this container and
+    /// returns its ownership to the caller.
+    ///
+    /// If the value stored for `R` and `label` has a different type, it is
+    /// kept and `None` is returned.
+    pub fn remove_named<R>(&mut self, label: impl Into<Label>) -> Option<R>
+    where
+        R: Resource<'a>,
+    {
+        let key = (R::id(), label.into());
+        if (**self.named.get_resource_mut(&key)?).self_id() != R::id() {
+            return None;
+        }
+
+        self.ticks.remove(&(key.0, Some(key.1.clone())));
+        self.named
+            .remove(&key)
+            .and_then(|x| x.downcast_box().ok())
+            .map(|x: Box<R>| *x)
+    }
+
+    /// Returns true if a resource of type `R` with `label` exists in `self`.
+    pub fn contains_named<R>(&self, label: impl Into<Label>) -> bool
+    where
+        R: Resource<'a>,
+    {
+        self.named.contains_key(&(R::id(), label.into()))
+    }
+
+    /// Returns the `R` resource with `label` in the resource map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist.
+    /// Panics if the resource is being accessed mutably.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_named<R>(&self, label: impl Into<Label>) -> Ref<'_, 'a, R>
+    where
+        R: Resource<'a>,
+    {
+        self.try_borrow_named::<R>(label)
+            .unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Returns an immutable reference to the `R` resource with `label`.
+    ///
+    /// Returns [`BorrowError::Poisoned`] if a panic occurred while the
+    /// resource was borrowed mutably, see [`try_borrow_named_poisoned`].
+    ///
+    /// [`try_borrow_named_poisoned`]: Self::try_borrow_named_poisoned
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_named<R>(
+        &self,
+        label: impl Into<Label>,
+    ) -> Result<Ref<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        self.try_borrow_named_poisoned::<R>(label)?
+            .map_err(BorrowError::from)
+    }
+
+    /// Returns an immutable reference to the `R` resource with `label` like
+    /// [`try_borrow_named`], which is wrapped in a
+    /// [PoisonError][crate::PoisonError] if the resource is poisoned.
+    ///
+    /// [`try_borrow_named`]: Self::try_borrow_named
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_named_poisoned<R>(
+        &self,
+        label: impl Into<Label>,
+    ) -> Result<PoisonResult<Ref<'_, 'a, R>>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        self.try_borrow_named_at::<R>(
+            label.into(),
+            #[cfg(feature = "track_borrows")]
+            Location::caller(),
+        )
+    }
+
+    /// Returns a mutable reference to the `R` resource with `label`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist.
+    /// Panics if the resource is already accessed.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_mut_named<R>(&self, label: impl Into<Label>) -> RefMut<'_, 'a, R>
+    where
+        R: Resource<'a>,
+    {
+        self.try_borrow_mut_named::<R>(label)
+            .unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Returns a mutable reference to the `R` resource with `label`.
+    ///
+    /// Returns [`BorrowError::Poisoned`] if a panic occurred while the
+    /// resource was borrowed mutably, see [`try_borrow_mut_named_poisoned`].
+    ///
+    /// [`try_borrow_mut_named_poisoned`]: Self::try_borrow_mut_named_poisoned
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_mut_named<R>(
+        &self,
+        label: impl Into<Label>,
+    ) -> Result<RefMut<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        self.try_borrow_mut_named_poisoned::<R>(label)?
+            .map_err(BorrowError::from)
+    }
+
+    /// Returns a mutable reference to the `R` resource with `label` like
+    /// [`try_borrow_mut_named`], which is wrapped in a
+    /// [PoisonError][crate::PoisonError] if the resource is poisoned.
+    ///
+    /// The resource stays poisoned until [`clear_poison_named`] is called.
+    ///
+    /// [`try_borrow_mut_named`]: Self::try_borrow_mut_named
+    /// [`clear_poison_named`]: Self::clear_poison_named
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_mut_named_poisoned<R>(
+        &self,
+        label: impl Into<Label>,
+    ) -> Result<PoisonResult<RefMut<'_, 'a, R>>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        let key = (R::id(), label.into());
+        let ref_mut = self
+            .named
+            .try_borrow_mut(&key)
+            .map(RefMut::<R>::new)
+            .map_err(|borrow_fail| {
+                self.borrow_error(
+                    R::id(),
+                    TypeNameLit::of::<R>(),
+                    borrow_fail,
+                    BorrowMode::Exclusive,
+                    Some(&key.1),
+                )
+            })?;
+        let ref_mut = self.track_ref_mut(ref_mut, &(key.0, Some(key.1.clone())));
+
+        let key = (key.0, Some(key.1));
+
+        #[cfg(feature = "track_borrows")]
+        let ref_mut = ref_mut.with_record(self.borrows.record(key.clone(), BorrowMode::Exclusive));
+
+        Ok(self.check_poison(ref_mut, &key, TypeNameLit::of::<R>(), BorrowMode::Exclusive))
+    }
+
+    /// Returns true if a panic occurred while the `R` resource with `label`
+    /// was borrowed mutably, see [`is_poisoned`].
+    ///
+    /// [`is_poisoned`]: Self::is_poisoned
+    pub fn is_poisoned_named<R>(&self, label: impl Into<Label>) -> bool
+    where
+        R: Resource<'a>,
+    {
+        matches!(
+            self.ticks.get(&(R::id(), Some(label.into()))),
+            Some(ticks) if ticks.poison().is_poisoned()
+        )
+    }
+
+    /// Clears the poison of the `R` resource with `label`, so it can be
+    /// borrowed normally again.
+    pub fn clear_poison_named<R>(&self, label: impl Into<Label>)
+    where
+        R: Resource<'a>,
+    {
+        if let Some(ticks) = self.ticks.get(&(R::id(), Some(label.into()))) {
+            ticks.poison().clear();
+        }
+    }
+
+    /// Borrows all named resources of type `R` immutably, together with
+    /// their labels.
+    ///
+    /// The resources are borrowed lazily while iterating. Resources which
+    /// can't be borrowed yield an error, see [`try_borrow_named`].
+    ///
+    /// [`try_borrow_named`]: Self::try_borrow_named
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn iter_named<R>(
+        &self,
+    ) -> impl Iterator<Item = (&Label, Result<Ref<'_, 'a, R>, BorrowError>)>
+    where
+        R: Resource<'a>,
+    {
+        #[cfg(feature = "track_borrows")]
+        let location = Location::caller();
+
+        self.named
+            .keys()
+            .filter(|(type_id, _)| *type_id == R::id())
+            .map(move |(_, label)| {
+                let r#ref = self
+                    .try_borrow_named_at::<R>(
+                        label.clone(),
+                        #[cfg(feature = "track_borrows")]
+                        location,
+                    )
+                    .and_then(|r#ref| r#ref.map_err(BorrowError::from));
+
+                (label, r#ref)
+            })
+    }
+
+    /// Borrows all resources of the [SystemData] `S` at once.
+    ///
+    /// See [`try_fetch`] for a non-panicking version of this function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the resources can't be borrowed.
+    ///
+    /// [`try_fetch`]: Self::try_fetch
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn fetch<'b, S>(&'b self) -> S
+    where
+        S: SystemData<'b, 'a>,
+    {
+        self.try_fetch::<S>().unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Borrows all resources of the [SystemData] `S` at once.
+    ///
+    /// If any of the borrows fails, the already borrowed resources are
+    /// released and the error of the failed borrow is returned.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_fetch<'b, S>(&'b self) -> Result<S, BorrowError>
+    where
+        S: SystemData<'b, 'a>,
+    {
+        S::try_fetch(self)
+    }
+
+    /// Retrieves a resource without fetching, which is cheaper, but only
+    /// available with `&mut self`.
+    ///
+    /// The resource is marked as changed. Returns `None` for derived
+    /// resources, see [`insert_derived`].
+    ///
+    /// [`insert_derived`]: Self::insert_derived
+    pub fn get_mut<R>(&mut self) -> Option<&mut R>
+    where
+        R: Resource<'a>,
+    {
+        self.get_resource_mut(R::id())
+            .and_then(|res| res.downcast_mut())
+    }
+
+    /// Retrieves a resource without fetching, which is cheaper, but only
+    /// available with `&mut self`.
+    ///
+    /// The resource is marked as changed. Returns `None` for derived
+    /// resources, see [`insert_derived`].
+    ///
+    /// [`insert_derived`]: Self::insert_derived
+    pub fn get_resource_mut(&mut self, id: TypeId) -> Option<&mut dyn Resource<'a>> {
+        if self.derived.contains_key(&id) {
+            return None;
+        }
+
+        if let Some(ticks) = self.ticks.get(&(id, None)) {
+            ticks.set_changed(self.tick);
+        }
+
+        self.map
+            .get_resource_mut(&id)
+            .map(|resource| &mut **resource)
+    }
+
+    /// Returns the current world tick.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Advances the world tick and returns the new tick.
+    ///
+    /// Resources added or changed from now on are recorded with the new tick,
+    /// so they are reported as changed since all earlier ticks.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::Resources;
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Score(u32);
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Score(0));
+    ///
+    /// let last_run = resources.tick();
+    /// resources.increment_tick();
+    /// assert!(!resources.is_changed::<Score>(last_run));
+    ///
+    /// resources.borrow_mut::<Score>().0 += 1;
+    /// assert!(resources.is_changed::<Score>(last_run));
+    /// ```
+    pub fn increment_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Returns true if the resource `R` was added after tick `since`.
+    pub fn is_added<R>(&self, since: u64) -> bool
+    where
+        R: Resource<'a>,
+    {
+        matches!(self.ticks.get(&(R::id(), None)), Some(ticks) if ticks.added() > since)
+    }
+
+    /// Returns true if the resource `R` was added or changed after tick
+    /// `since`.
+    pub fn is_changed<R>(&self, since: u64) -> bool
+    where
+        R: Resource<'a>,
+    {
+        matches!(self.ticks.get(&(R::id(), None)), Some(ticks) if ticks.changed() > since)
+    }
+
+    /// Serializes the resources as a map from their names in `registry` to
+    /// their values.
+    ///
+    /// Resources which are not registered are skipped or reported according
+    /// to the registry's [UnregisteredPolicy][crate::UnregisteredPolicy].
+    /// Named resources are not supported, so they are treated like
+    /// unregistered ones. [Derived][Self::insert_derived] resources are left
+    /// out, as they are recomputed from their inputs.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a resource is borrowed mutably, a value of another type is
+    /// stored under the id of a registered one, or if serializing fails.
+    #[cfg(feature = "serde")]
+    pub fn snapshot<S>(
+        &self,
+        registry: &ResourceRegistry<'a>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let cells = self
+            .map
+            .iter()
+            .filter(|(type_id, _)| !self.derived.contains_key(type_id));
+        let named = self.ticks.iter().filter_map(|((_, label), ticks)| {
+            label.as_ref().map(|label| (ticks.type_name(), label))
+        });
+
+        resource_registry::snapshot(cells, named, registry, serializer)
+    }
+
+    /// Inserts the resources of a snapshot taken with [`snapshot`],
+    /// overwriting existing resources of the same types.
+    ///
+    /// Nothing is inserted if deserializing fails.
+    ///
+    /// [`snapshot`]: Self::snapshot
+    #[cfg(feature = "serde")]
+    pub fn restore<'de, D>(
+        &mut self,
+        registry: &ResourceRegistry<'a>,
+        deserializer: D,
+    ) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        resource_registry::restore(registry, deserializer)?
+            .into_iter()
+            .for_each(|(type_id, resource)| {
+                self.insert_raw(type_id, resource);
+            });
+
+        Ok(())
+    }
+
+    /// Registers `f` to be called after a resource of type `R` is inserted
+    /// where none existed before.
+    ///
+    /// Observers are called with the type name and the value of the resource
+    /// by [`insert`], [`insert_raw`] and [`entry`], once the resource is
+    /// stored in the map. Only unnamed resources are observed.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use better_any::Tid;
+    /// use stateman::Resources;
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Panel(&'static str);
+    ///
+    /// let titles = Arc::new(Mutex::new(Vec::new()));
+    /// let mut resources = Resources::default();
+    ///
+    /// let observed = Arc::clone(&titles);
+    /// let observer = resources.on_insert::<Panel>(move |_, panel| {
+    ///     observed.lock().unwrap().push(panel.0);
+    /// });
+    /// resources.insert(Panel("inventory"));
+    ///
+    /// resources.remove_observer(observer);
+    /// resources.remove::<Panel>();
+    /// resources.insert(Panel("map"));
+    ///
+    /// assert_eq!(vec!["inventory"], *titles.lock().unwrap());
+    /// ```
+    ///
+    /// [`insert`]: Self::insert
+    /// [`insert_raw`]: Self::insert_raw
+    /// [`entry`]: Self::entry
+    pub fn on_insert<R>(&mut self, f: impl Fn(TypeNameLit, &R) + Send + 'a) -> ObserverId
+    where
+        R: Resource<'a>,
+    {
+        self.observers.add(Some(R::id()), Observer::insert(f))
+    }
+
+    /// Registers `f` to be called when a resource of type `R` is removed,
+    /// before it is returned from [`remove`].
+    ///
+    /// [`remove`]: Self::remove
+    pub fn on_remove<R>(&mut self, f: impl Fn(TypeNameLit, &R) + Send + 'a) -> ObserverId
+    where
+        R: Resource<'a>,
+    {
+        self.observers.add(Some(R::id()), Observer::remove(f))
+    }
+
+    /// Registers `f` to be called with the old and the new value after a
+    /// resource of type `R` is overwritten by [`insert`] or [`insert_raw`].
+    ///
+    /// [`insert`]: Self::insert
+    /// [`insert_raw`]: Self::insert_raw
+    pub fn on_replace<R>(&mut self, f: impl Fn(TypeNameLit, &R, &R) + Send + 'a) -> ObserverId
+    where
+        R: Resource<'a>,
+    {
+        self.observers.add(Some(R::id()), Observer::replace(f))
+    }
+
+    /// Registers `f` to be called when a [RefMut] of the resource `R` which
+    /// was mutably dereferenced is dropped.
+    ///
+    /// The callback runs after the mutable borrow is released, so it may
+    /// borrow the resource immutably itself. It runs on the thread dropping
+    /// the reference, and is skipped if the resource is borrowed mutably
+    /// again in the meantime. Changes through
+    /// [`RefMut::bypass_change_detection`] and named resources are not
+    /// observed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::sync::{
+    ///     atomic::{AtomicU32, Ordering},
+    ///     Arc,
+    /// };
+    ///
+    /// use better_any::Tid;
+    /// use stateman::Resources;
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Health(u32);
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Health(10));
+    ///
+    /// let last_seen = Arc::new(AtomicU32::new(0));
+    /// let observed = Arc::clone(&last_seen);
+    /// resources.on_change::<Health>(move |health| observed.store(health.0, Ordering::SeqCst));
+    ///
+    /// resources.borrow_mut::<Health>().0 -= 3;
+    /// assert_eq!(7, last_seen.load(Ordering::SeqCst));
+    /// ```
+    pub fn on_change<R>(&mut self, f: impl Fn(&R) + Send + Sync + 'a) -> ObserverId
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.observers.add_change::<R, _>(f)
+    }
+
+    /// Registers `f` to be called after any resource is inserted where none
+    /// existed before, see [`on_insert`].
+    ///
+    /// [`on_insert`]: Self::on_insert
+    pub fn on_insert_any<F>(&mut self, f: F) -> ObserverId
+    where
+        F: Fn(TypeNameLit, &dyn Resource<'a>) + Send + 'a,
+    {
+        self.observers.add(None, Observer::Insert(Box::new(f)))
+    }
+
+    /// Registers `f` to be called when any resource is removed, see
+    /// [`on_remove`].
+    ///
+    /// [`on_remove`]: Self::on_remove
+    pub fn on_remove_any<F>(&mut self, f: F) -> ObserverId
+    where
+        F: Fn(TypeNameLit, &dyn Resource<'a>) + Send + 'a,
+    {
+        self.observers.add(None, Observer::Remove(Box::new(f)))
+    }
+
+    /// Registers `f` to be called after any resource is overwritten, see
+    /// [`on_replace`].
+    ///
+    /// [`on_replace`]: Self::on_replace
+    pub fn on_replace_any<F>(&mut self, f: F) -> ObserverId
+    where
+        F: Fn(TypeNameLit, &dyn Resource<'a>, &dyn Resource<'a>) + Send + 'a,
+    {
+        self.observers.add(None, Observer::Replace(Box::new(f)))
+    }
+
+    /// Unregisters the observer with `id`. Returns false if it was already
+    /// unregistered.
+    pub fn remove_observer(&mut self, id: ObserverId) -> bool {
+        self.observers.remove(id)
+    }
+
+    /// Queues `commands` to be applied by the next call to
+    /// [`apply_commands`], after the commands queued before.
+    ///
+    /// [`apply_commands`]: Self::apply_commands
+    pub fn push_commands(&self, mut commands: Commands<'a>) {
+        self.commands
+            .lock()
+            .unwrap_or_else(sync::PoisonError::into_inner)
+            .append(&mut commands);
+    }
+
+    /// Applies the queued [Commands] in FIFO order.
+    ///
+    /// Commands queued while applying are kept for the next call.
+    pub fn apply_commands(&mut self) {
+        let commands = std::mem::take(
+            self.commands
+                .get_mut()
+                .unwrap_or_else(sync::PoisonError::into_inner),
+        );
+        commands.apply(self);
+    }
+
+    /// Returns a new scope with `self` as its parent, see [ScopedResources].
+    pub fn child(&self) -> ScopedResources<'_, 'a> {
+        ScopedResources::new(self)
+    }
+
+    /// Registers `R` to be printed with its [`Debug`] implementation in the
+    /// `Debug` output of `self`. Resources of other types are printed as
+    /// `".."`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::Resources;
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Score(u32);
+    ///
+    /// #[derive(Tid)]
+    /// struct Opaque;
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Score(3));
+    /// resources.insert(Opaque);
+    /// resources.register_debug::<Score>();
+    ///
+    /// let debug = format!("{resources:?}");
+    /// assert!(debug.contains("Score: Score(3)"));
+    /// assert!(debug.contains(r#"Opaque: "..""#));
+    /// ```
+    ///
+    /// [`Debug`]: fmt::Debug
+    pub fn register_debug<R>(&mut self)
+    where
+        R: Resource<'a> + fmt::Debug,
+    {
+        self.debugs.insert(R::id(), resource::debug_resource::<R>);
+    }
+
+    fn debug_resource<'r>(
+        &self,
+        type_id: TypeId,
+        resource: &'r dyn Resource<'a>,
+    ) -> DebugResource<'r, 'a> {
+        DebugResource {
+            resource,
+            debug_fn: self.debugs.get(&type_id).copied(),
+        }
+    }
+
+    /// Registers how to cast the resource type `R` to the trait object `T`,
+    /// which makes it available through [`iter_dyn`] and [`iter_dyn_mut`].
+    ///
+    /// The casts are usually just the identity, which coerces `R` to `T`.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::Resources;
+    ///
+    /// trait Tickable {
+    ///     fn tick(&mut self);
+    ///     fn ticks(&self) -> u32;
+    /// }
+    ///
+    /// #[derive(Tid)]
+    /// struct Timer(u32);
+    ///
+    /// impl Tickable for Timer {
+    ///     fn tick(&mut self) {
+    ///         self.0 += 1;
+    ///     }
+    ///
+    ///     fn ticks(&self) -> u32 {
+    ///         self.0
+    ///     }
+    /// }
+    ///
+    /// #[derive(Tid)]
+    /// struct Frames(u32);
+    ///
+    /// impl Tickable for Frames {
+    ///     fn tick(&mut self) {
+    ///         self.0 += 2;
+    ///     }
+    ///
+    ///     fn ticks(&self) -> u32 {
+    ///         self.0 / 2
+    ///     }
+    /// }
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Timer(0));
+    /// resources.insert(Frames(0));
+    /// resources.register_trait::<Timer, dyn Tickable>(|r| r, |r| r);
+    /// resources.register_trait::<Frames, dyn Tickable>(|r| r, |r| r);
+    ///
+    /// for tickable in resources.iter_dyn_mut::<dyn Tickable>() {
+    ///     tickable.unwrap().tick();
+    /// }
+    ///
+    /// let ticks = resources
+    ///     .iter_dyn::<dyn Tickable>()
+    ///     .map(|tickable| tickable.unwrap().ticks())
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(vec![1, 1], ticks);
+    /// ```
+    ///
+    /// [`iter_dyn`]: Self::iter_dyn
+    /// [`iter_dyn_mut`]: Self::iter_dyn_mut
+    pub fn register_trait<R, T>(&mut self, cast_ref: fn(&R) -> &T, cast_mut: fn(&mut R) -> &mut T)
+    where
+        R: Resource<'a>,
+        T: ?Sized + 'static,
+    {
+        self.traits.insert(cast_ref, cast_mut);
+    }
+
+    /// Borrows all resources registered for the trait object `T` immutably,
+    /// in the order of their registration.
+    ///
+    /// The resources are borrowed lazily while iterating. Resources which
+    /// don't exist are skipped, and resources which can't be borrowed yield
+    /// an error, see [`try_borrow`].
+    ///
+    /// [`try_borrow`]: Self::try_borrow
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn iter_dyn<T>(&self) -> impl Iterator<Item = Result<Ref<'_, 'a, T>, BorrowError>>
+    where
+        T: ?Sized + 'static,
+    {
+        #[cfg(feature = "track_borrows")]
+        let location = Location::caller();
+
+        self.traits
+            .iter::<T>()
+            .filter(move |cast| self.map.contains_key(&cast.type_id))
+            .map(move |cast| {
+                let r#ref = self.try_borrow_erased(
+                    cast.type_id,
+                    cast.type_name,
+                    #[cfg(feature = "track_borrows")]
+                    location,
+                )?;
+                let r#ref = self
+                    .check_poison(
+                        r#ref,
+                        &(cast.type_id, None),
+                        cast.type_name,
+                        BorrowMode::Shared,
+                    )
+                    .map_err(BorrowError::from)?;
+
+                Ok(Ref::map(r#ref, |resource| (cast.cast_ref)(resource)))
+            })
+    }
+
+    /// Borrows all resources registered for the trait object `T` mutably, in
+    /// the order of their registration.
+    ///
+    /// The resources are borrowed lazily while iterating. Resources which
+    /// don't exist are skipped, and resources which can't be borrowed yield
+    /// an error, see [`try_borrow_mut`].
+    ///
+    /// [`try_borrow_mut`]: Self::try_borrow_mut
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn iter_dyn_mut<T>(&self) -> impl Iterator<Item = Result<RefMut<'_, 'a, T>, BorrowError>>
+    where
+        T: ?Sized + 'static,
+    {
+        #[cfg(feature = "track_borrows")]
+        let location = Location::caller();
+
+        self.traits
+            .iter::<T>()
+            .filter(move |cast| self.map.contains_key(&cast.type_id))
+            .map(move |cast| {
+                let ref_mut = self.try_borrow_mut_erased(
+                    cast.type_id,
+                    cast.type_name,
+                    #[cfg(feature = "track_borrows")]
+                    location,
+                )?;
+                let ref_mut = self
+                    .check_poison(
+                        ref_mut,
+                        &(cast.type_id, None),
+                        cast.type_name,
+                        BorrowMode::Exclusive,
+                    )
+                    .map_err(BorrowError::from)?;
+
+                Ok(RefMut::map(ref_mut, |resource| (cast.cast_mut)(resource)))
+            })
+    }
+
+    /// Registers how to clone the resource type `R`, which allows it to be
+    /// changed within a [`transaction`].
+    ///
+    /// [`transaction`]: Self::transaction
+    pub fn register_clone<R>(&mut self)
+    where
+        R: Resource<'a> + Clone,
+    {
+        self.clones
+            .insert(R::id(), transaction::clone_resource::<R>);
+    }
+
+    /// Runs `f` as a transaction, whose changes to the resources are only
+    /// kept if it returns `Ok`.
+    ///
+    /// Resources borrowed mutably through the [Transaction] are cloned
+    /// beforehand, so they need to be registered with [`register_clone`].
+    /// The transaction changes the clones, which are written back once `f`
+    /// returns `Ok`. Written back resources are marked as changed and
+    /// reported to the [`on_change`] observers, while clones which were not
+    /// mutably dereferenced are dropped. If `f` returns `Err` or panics, the
+    /// clones are dropped and the resources stay untouched. Use
+    /// [`Transaction::savepoint`] to nest transactions.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::Resources;
+    ///
+    /// #[derive(Clone, Debug, Tid)]
+    /// struct Gold(u32);
+    ///
+    /// #[derive(Clone, Debug, Tid)]
+    /// struct Items(Vec<&'static str>);
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Gold(3));
+    /// resources.insert(Items(Vec::new()));
+    /// resources.register_clone::<Gold>();
+    /// resources.register_clone::<Items>();
+    ///
+    /// let result = resources.transaction(|tx| {
+    ///     tx.borrow_mut::<Items>().0.push("sword");
+    ///
+    ///     let mut gold = tx.borrow_mut::<Gold>();
+    ///     gold.0 = gold.0.checked_sub(5).ok_or("not enough gold")?;
+    ///     Ok(())
+    /// });
+    ///
+    /// assert_eq!(Err("not enough gold"), result);
+    /// assert!(resources.borrow::<Items>().0.is_empty());
+    /// ```
+    ///
+    /// [`register_clone`]: Self::register_clone
+    /// [`on_change`]: Self::on_change
+    pub fn transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Transaction<'_, 'a>) -> Result<T, E>,
+    {
+        let mut transaction = Transaction::new(self);
+        let result = f(&mut transaction);
+        if result.is_ok() {
+            transaction.commit();
+        }
+
+        result
+    }
+
+    pub(crate) fn clone_fn(&self, type_id: TypeId) -> Option<CloneFn<'a>> {
+        self.clones.get(&type_id).copied()
+    }
+
+    /// Returns the ids of the resources which can be changed within a
+    /// [Transaction], i.e. which are registered with [`register_clone`] and
+    /// not derived.
+    ///
+    /// [`register_clone`]: Self::register_clone
+    pub(crate) fn cloneable_ids(&self) -> Vec<TypeId> {
+        self.clones
+            .keys()
+            .filter(|type_id| self.map.contains_key(type_id) && !self.derived.contains_key(type_id))
+            .copied()
+            .collect()
+    }
+
+    pub(crate) fn ticks_of(&self, type_id: TypeId) -> Option<&Ticks> {
+        self.ticks.get(&(type_id, None))
+    }
+
+    /// Writes back the changed working copy of a committed [Transaction] in
+    /// place, marks the resource as changed and calls the change observers.
+    pub(crate) fn write_back(&mut self, type_id: TypeId, resource: Box<dyn Resource<'a>>) {
+        let Some(value) = self.map.get_resource_mut(&type_id) else {
+            return;
+        };
+        *value = resource;
+        if let Some(ticks) = self.ticks.get(&(type_id, None)) {
+            ticks.set_changed(self.tick);
+        }
+        self.observers.changed(type_id, &**value);
+    }
+
+    /// Returns the versions of `inputs`, recomputing derived inputs first.
+    fn input_versions(&self, inputs: &[TypeId]) -> Result<Vec<u64>, BorrowError> {
+        inputs
+            .iter()
+            .filter_map(|type_id| {
+                let ticks = self.ticks.get(&(*type_id, None))?;
+                // Derived inputs only get a new version when they are
+                // recomputed, so they are brought up to date first.
+                let updated = match self.derived.get(type_id) {
+                    Some(derived) => self.update_derived(*type_id, ticks.type_name(), derived),
+                    None => Ok(()),
+                };
+
+                Some(updated.map(|()| ticks.version()))
+            })
+            .collect()
+    }
+
+    /// Returns true if `type_id` is one of `inputs`, or an input of a derived
+    /// resource reachable from them.
+    fn derives_from(&self, inputs: &[TypeId], type_id: TypeId) -> bool {
+        inputs.iter().any(|input| {
+            *input == type_id
+                || matches!(
+                    self.derived.get(input),
+                    Some(derived) if self.derives_from(derived.inputs(), type_id)
+                )
+        })
+    }
+
+    /// Recomputes the derived resource `type_id` if its inputs changed.
+    ///
+    /// Returns a conflict if the resource needs to be recomputed while it is
+    /// borrowed, instead of handing out a stale value.
+    fn update_derived(
+        &self,
+        type_id: TypeId,
+        type_name: TypeNameLit,
+        derived: &Derived<'a>,
+    ) -> Result<(), BorrowError> {
+        let versions = self.input_versions(derived.inputs())?;
+        if !derived.is_stale(&versions) {
+            return Ok(());
+        }
+
+        let mut out = self.map.try_borrow_mut(&type_id).map_err(|borrow_fail| {
+            self.borrow_error(type_id, type_name, borrow_fail, BorrowMode::Exclusive, None)
+        })?;
+        *out = derived.derive(self, versions)?;
+        if let Some(ticks) = self.ticks.get(&(type_id, None)) {
+            ticks.set_changed(self.tick);
+        }
+
+        Ok(())
+    }
+
+    /// Get raw access to the underlying cell.
+    ///
+    /// Returns `None` for derived resources, which can only be borrowed, see
+    /// [`insert_derived`]. Borrows of the cell itself are not counted in
+    /// [`BorrowState::Shared`].
+    ///
+    /// [`insert_derived`]: Self::insert_derived
+    pub fn get_raw(&self, id: &TypeId) -> Option<&Cell<Box<dyn Resource<'a>>>> {
+        if self.derived.contains_key(id) {
+            return None;
+        }
+
+        self.map.get_raw(id)
+    }
+
+    /// Converts the `rt_map` failure into a [BorrowError] for `type_id`,
+    /// which is named `label` if given.
+    fn borrow_error(
+        &self,
+        type_id: TypeId,
+        type_name: TypeNameLit,
+        borrow_fail: BorrowFail,
+        mode: BorrowMode,
+        label: Option<&Label>,
+    ) -> BorrowError {
+        let cell = match label {
+            Some(label) => self.named.get_raw(&(type_id, label.clone())),
+            None => self.map.get_raw(&type_id),
+        };
+
+        BorrowError::from_fail(
+            borrow_fail,
+            cell,
+            (type_id, label.cloned()),
+            type_name,
+            mode,
+            #[cfg(feature = "track_borrows")]
+            Some(&self.borrows),
+        )
+    }
+
+    /// Borrows the `R` resource with `label` immutably, recording the borrow
+    /// at `location`.
+    fn try_borrow_named_at<R>(
+        &self,
+        label: Label,
+        #[cfg(feature = "track_borrows")] location: &'static Location<'static>,
+    ) -> Result<PoisonResult<Ref<'_, 'a, R>>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        let key = (R::id(), label);
+        let r#ref = self
+            .named
+            .try_borrow(&key)
+            .map(Ref::<R>::new)
+            .map_err(|borrow_fail| {
+                self.borrow_error(
+                    R::id(),
+                    TypeNameLit::of::<R>(),
+                    borrow_fail,
+                    BorrowMode::Shared,
+                    Some(&key.1),
+                )
+            })?;
+        let r#ref = self.track_ref(r#ref, &(key.0, Some(key.1.clone())));
+
+        let key = (key.0, Some(key.1));
+
+        #[cfg(feature = "track_borrows")]
+        let r#ref = r#ref.with_record(self.borrows.record_at(
+            key.clone(),
+            BorrowMode::Shared,
+            location,
+        ));
+
+        Ok(self.check_poison(r#ref, &key, TypeNameLit::of::<R>(), BorrowMode::Shared))
+    }
+
+    /// Borrows the resource `type_id` immutably without downcasting it.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn try_borrow_erased(
+        &self,
+        type_id: TypeId,
+        type_name: TypeNameLit,
+        #[cfg(feature = "track_borrows")] location: &'static Location<'static>,
+    ) -> Result<Ref<'_, 'a, dyn Resource<'a>>, BorrowError> {
+        if let Some(derived) = self.derived.get(&type_id) {
+            self.update_derived(type_id, type_name, derived)?;
+        }
+
+        let r#ref = self
+            .map
+            .try_borrow(&type_id)
+            .map(Ref::erased)
+            .map_err(|borrow_fail| {
+                self.borrow_error(type_id, type_name, borrow_fail, BorrowMode::Shared, None)
+            })?;
+        let r#ref = self.track_ref(r#ref, &(type_id, None));
+
+        #[cfg(feature = "track_borrows")]
+        let r#ref = r#ref.with_record(self.borrows.record_at(
+            (type_id, None),
+            BorrowMode::Shared,
+            location,
+        ));
+
+        Ok(r#ref)
+    }
+
+    /// Borrows the resource `type_id` mutably without downcasting it.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn try_borrow_mut_erased(
+        &self,
+        type_id: TypeId,
+        type_name: TypeNameLit,
+        #[cfg(feature = "track_borrows")] location: &'static Location<'static>,
+    ) -> Result<RefMut<'_, 'a, dyn Resource<'a>>, BorrowError> {
+        if self.derived.contains_key(&type_id) {
+            return Err(BorrowError::ReadOnly {
+                type_name,
+                label: None,
+            });
+        }
+
+        let ref_mut = self
+            .map
+            .try_borrow_mut(&type_id)
+            .map(RefMut::erased)
+            .map_err(|borrow_fail| {
+                self.borrow_error(type_id, type_name, borrow_fail, BorrowMode::Exclusive, None)
+            })?;
+        let mut ref_mut = self.track_ref_mut(ref_mut, &(type_id, None));
+
+        let changes = self.observers.changes();
+        if changes.observes(type_id) {
+            if let Some(cell) = self.map.get_raw(&type_id) {
+                ref_mut = ref_mut.with_change_hook(ChangeHook::new(type_id, cell, changes));
+            }
+        }
+
+        #[cfg(feature = "track_borrows")]
+        let ref_mut = ref_mut.with_record(self.borrows.record_at(
+            (type_id, None),
+            BorrowMode::Exclusive,
+            location,
+        ));
+
+        Ok(ref_mut)
+    }
+
+    /// Marks the resource `type_id` as poisoned.
+    pub(crate) fn poison_raw(&self, type_id: TypeId) {
+        if let Some(ticks) = self.ticks.get(&(type_id, None)) {
+            ticks.poison().poison();
+        }
+    }
+
+    /// Removes the resource `type_id` together with its bookkeeping.
+    fn remove_raw(&mut self, type_id: TypeId) -> Option<Box<dyn Resource<'a>>> {
+        self.derived.remove(&type_id);
+        self.ticks.remove(&(type_id, None));
+        let resource = self.map.remove(&type_id)?;
+        self.observers.removed(type_id, &*resource);
+
+        Some(resource)
+    }
+
+    /// Takes the resource `type_id` out of the map together with its ticks
+    /// and derived registration, without calling observers.
+    fn detach(&mut self, type_id: TypeId) -> Option<Detached<'a>> {
+        let resource = self.map.remove(&type_id)?;
+        let ticks = self
+            .ticks
+            .remove(&(type_id, None))
+            .expect("Expected ticks of resource to exist.");
+
+        Some(Detached {
+            resource,
+            ticks,
+            derived: self.derived.remove(&type_id),
+        })
+    }
+
+    /// Puts a resource taken out with [`detach`] into the map, without
+    /// calling observers.
+    ///
+    /// [`detach`]: Self::detach
+    fn attach(&mut self, type_id: TypeId, detached: Detached<'a>) {
+        let Detached {
+            resource,
+            ticks,
+            derived,
+        } = detached;
+        self.map.insert(type_id, resource);
+        self.ticks.insert((type_id, None), ticks);
+        if let Some(derived) = derived {
+            self.derived.insert(type_id, derived);
+        }
+    }
+
+    /// Calls the observers of `type_id` after its value was swapped with the
+    /// one in `other`.
+    fn swapped(&mut self, type_id: TypeId, other: &mut Resources<'a>) {
+        match (
+            self.map.get_resource_mut(&type_id),
+            other.map.get_resource_mut(&type_id),
+        ) {
+            (Some(new), Some(old)) => self.observers.replaced(type_id, &**old, &**new),
+            (Some(new), None) => self.observers.inserted(type_id, &**new),
+            (None, Some(old)) => self.observers.removed(type_id, &**old),
+            (None, None) => {}
+        }
+    }
+
+    fn meta(&self, type_id: TypeId) -> Option<(TypeId, TypeNameLit, BorrowState)> {
+        let type_name = self.ticks.get(&(type_id, None))?.type_name();
+
+        Some((type_id, type_name, self.borrow_state_of(type_id)?))
+    }
+
+    /// Probes the cell of `type_id` for its borrow state.
+    fn borrow_state_of(&self, type_id: TypeId) -> Option<BorrowState> {
+        let cell = self.map.get_raw(&type_id)?;
+        if cell.try_borrow_mut().is_ok() {
+            return Some(BorrowState::Unborrowed);
+        }
+        if cell.try_borrow().is_err() {
+            return Some(BorrowState::Exclusive);
+        }
+
+        // Every `Ref` into the map counts its borrow in the ticks, including
+        // the ones of derived resources and their inputs.
+        let shared = self.ticks.get(&(type_id, None)).map_or(0, Ticks::shared);
+        Some(BorrowState::Shared(shared))
+    }
+
+    /// Returns an error if `R` doesn't exist or the value stored for it has a
+    /// different type.
+    fn check_type<R>(&self, mode: BorrowMode) -> Result<(), BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        let cell = self.map.get_raw(&R::id()).ok_or(BorrowError::NotFound {
+            type_name: TypeNameLit::of::<R>(),
+            label: None,
+            mode,
+        })?;
+        match cell.try_borrow() {
+            Ok(resource) if (**resource).self_id() != R::id() => {
+                Err(Self::type_mismatch::<R>(mode))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn type_mismatch<R>(mode: BorrowMode) -> BorrowError
+    where
+        R: Resource<'a>,
+    {
+        BorrowError::TypeMismatch {
+            type_name: TypeNameLit::of::<R>(),
+            label: None,
+            mode,
+        }
+    }
+
+    /// Returns `guard` to the resource with `key`, or an error carrying it if
+    /// the resource is poisoned.
+    fn check_poison<G>(
+        &self,
+        guard: G,
+        key: &SlotKey,
+        type_name: TypeNameLit,
+        mode: BorrowMode,
+    ) -> PoisonResult<G> {
+        match self.ticks.get(key) {
+            Some(ticks) => ticks.poison().check(guard, type_name, key.1.as_ref(), mode),
+            None => Ok(guard),
+        }
+    }
+
+    fn track_ref<'b, R: ?Sized>(&'b self, r#ref: Ref<'b, 'a, R>, key: &SlotKey) -> Ref<'b, 'a, R> {
+        match self.ticks.get(key) {
+            Some(ticks) => r#ref.with_ticks(ticks),
+            None => r#ref,
+        }
+    }
+
+    fn track_ref_mut<'b, R: ?Sized>(
+        &'b self,
+        ref_mut: RefMut<'b, 'a, R>,
+        key: &SlotKey,
+    ) -> RefMut<'b, 'a, R> {
+        match self.ticks.get(key) {
+            Some(ticks) => ref_mut.with_ticks(TickRef {
+                ticks,
+                tick: self.tick,
+            }),
+            None => ref_mut,
+        }
+    }
+
+    fn borrow_panic<Ret>(borrow_error: BorrowError) -> Ret {
+        panic!("{borrow_error}")
+    }
+}
+
+impl<'a> fmt::Debug for Resources<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug_map = f.debug_map();
+
+        self.map.keys().for_each(|type_id| {
+            let resource = &*self.map.borrow(type_id);
+            let type_name = resource.as_ref().type_name();
+
+            debug_map.entry(&type_name, &self.debug_resource(*type_id, &**resource));
+        });
+
+        self.named.keys().for_each(|key| {
+            let resource = &*self.named.borrow(key);
+            let type_name = resource.as_ref().type_name();
+
+            debug_map.entry(
+                &NamedKey(type_name, &key.1),
+                &self.debug_resource(key.0, &**resource),
+            );
+        });
+
+        debug_map.finish()
+    }
+}
+
+/// A resource taken out of [Resources] with its bookkeeping, see
+/// [`Resources::swap`].
+struct Detached<'a> {
+    resource: Box<dyn Resource<'a>>,
+    ticks: Ticks,
+    derived: Option<Derived<'a>>,
+}
+
+/// Iterator over the id, type name and borrow state of each resource, see
+/// [`Resources::iter`].
+pub struct ResourcesIter<'r, 'a> {
+    resources: &'r Resources<'a>,
+    type_ids: hash_map::Keys<'r, TypeId, Cell<Box<dyn Resource<'a>>>>,
+}
+
+impl<'r, 'a> Iterator for ResourcesIter<'r, 'a> {
+    type Item = (TypeId, TypeNameLit, BorrowState);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let resources = self.resources;
+        self.type_ids.find_map(|type_id| resources.meta(*type_id))
+    }
+}
+
+/// Iterator which removes all resources, see [`Resources::drain`].
+pub struct ResourcesDrain<'r, 'a> {
+    resources: hash_map::Drain<'r, TypeId, Cell<Box<dyn Resource<'a>>>>,
+    named: hash_map::Drain<'r, (TypeId, Label), Cell<Box<dyn Resource<'a>>>>,
+    observers: &'r Observers<'a>,
+}
+
+impl<'r, 'a> Iterator for ResourcesDrain<'r, 'a> {
+    type Item = Box<dyn Resource<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((type_id, cell)) = self.resources.next() {
+            let resource = cell.into_inner();
+            self.observers.removed(type_id, &*resource);
+
+            return Some(resource);
+        }
+
+        self.named.next().map(|(_, cell)| cell.into_inner())
+    }
+}
+
+impl<'r, 'a> Drop for ResourcesDrain<'r, 'a> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
+/// Debug key of a named resource, formatted as `Type["label"]`.
+struct NamedKey<'l>(TypeNameLit, &'l Label);
+
+impl<'l> fmt::Debug for NamedKey<'l> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}[{:?}]", self.0, self.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use better_any::{Tid, TidExt};
+    use std::{
+        any::TypeId,
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use crate::{BorrowError, BorrowMode, BorrowState, Entry, Label, Ref, RefMut, TypeNameLit};
+
+    use super::Resources;
+
+    #[test]
+    fn entry_or_insert_inserts_value() {
+        let mut resources = Resources::new();
+        let mut a_ref = resources.entry::<A>().or_insert(A(1));
+
+        assert_eq!(&A(1), &*a_ref);
+
+        *a_ref = A(2);
+
+        drop(a_ref);
+
+        assert_eq!(&A(2), &*resources.borrow::<A>());
+    }
+
+    #[test]
+    fn iter_dyn_skips_missing_and_reports_borrowed_resources() {
+        let mut resources = Resources::new();
+        resources.insert(A(1));
+        resources.register_trait::<A, dyn Value>(|a| a, |a| a);
+        resources.register_trait::<B, dyn Value>(|b| b, |b| b);
+
+        let a_ref = resources.borrow_mut::<A>();
+        let values = resources.iter_dyn::<dyn Value>().collect::<Vec<_>>();
+        assert_eq!(1, values.len());
+        assert!(matches!(
+            values[0],
+            Err(BorrowError::ConflictExclusive {
+                mode: BorrowMode::Shared,
+                ..
+            })
+        ));
+        drop(values);
+        drop(a_ref);
+
+        resources.insert(B(2));
+        let values = resources
+            .iter_dyn::<dyn Value>()
+            .map(|value| value.unwrap().value())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![1, 2], values);
+    }
+
+    #[test]
+    fn iter_dyn_mut_marks_resources_changed() {
+        let mut resources = Resources::new();
+        resources.insert(A(1));
+        resources.register_trait::<A, dyn Value>(|a| a, |a| a);
+        resources.increment_tick();
+
+        for value in resources.iter_dyn_mut::<dyn Value>() {
+            value.unwrap().set_value(3);
+        }
+
+        assert!(resources.is_changed::<A>(0));
+        assert_eq!(&A(3), &*resources.borrow::<A>());
+    }
+
+    #[test]
+    fn borrow_state_counts_shared_borrows() {
+        let mut resources = Resources::new();
+        resources.insert(A(1));
 
-        self.0.keys().for_each(|type_id| {
-            let resource = &*self.0.borrow(type_id);
-            let type_name = resource.as_ref().type_name();
+        assert_eq!(Some(BorrowState::Unborrowed), resources.borrow_state::<A>());
+        assert_eq!(None, resources.borrow_state::<B>());
 
-            // At runtime, we are unable to determine if the resource is `Debug`.
-            debug_map.entry(&type_name, &"..");
-        });
+        let a_ref = resources.borrow::<A>();
+        let a_clone = Ref::map(Ref::clone(&a_ref), |a| &a.0);
+        assert_eq!(Some(BorrowState::Shared(2)), resources.borrow_state::<A>());
+        drop((a_ref, a_clone));
 
-        debug_map.finish()
-    }
-}
+        let a_mut = resources.borrow_mut::<A>();
+        assert_eq!(Some(BorrowState::Exclusive), resources.borrow_state::<A>());
+        drop(a_mut);
 
-#[cfg(feature = "debug")]
-impl<'a> fmt::Debug for Resources<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut debug_map = f.debug_map();
+        assert_eq!(Some(BorrowState::Unborrowed), resources.borrow_state::<A>());
+    }
 
-        self.0.keys().for_each(|type_id| {
-            let resource = &*self.0.borrow(type_id);
-            let type_name = resource.as_ref().type_name();
+    #[test]
+    fn retain_returns_removed_resources() {
+        let mut resources = Resources::new();
+        resources.insert(A(1));
+        resources.insert(B(2));
+        resources.insert_named("named", A(3));
 
-            debug_map.entry(&type_name, resource);
-        });
+        assert_eq!(3, resources.len());
 
-        debug_map.finish()
-    }
-}
+        let removed = resources.retain(|(type_id, _, _)| type_id != TypeId::of::<A>());
 
-impl<'a> Deref for Resources<'a> {
-    type Target = RtMap<TypeId, Box<dyn Resource<'a>>>;
+        assert_eq!(1, removed.len());
+        assert_eq!(TypeId::of::<A>(), removed[0].self_id());
+        assert!(!resources.contains::<A>());
+        assert_eq!(
+            vec![TypeNameLit::of::<B>()],
+            resources.type_names().collect::<Vec<_>>()
+        );
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
+        assert!(resources.contains_named::<A>("named"));
+        assert_eq!(2, resources.len());
 
-impl<'a> DerefMut for Resources<'a> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        assert_eq!(2, resources.clear().len());
+        assert_eq!(0, resources.iter().count());
+        assert!(!resources.contains_named::<A>("named"));
+        assert!(resources.is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use better_any::Tid;
-    use std::any::TypeId;
+    #[test]
+    fn drain_removes_resources_while_iterating() {
+        let removed = Arc::new(AtomicUsize::new(0));
+        let mut resources = Resources::new();
+        resources.insert(A(1));
+        resources.insert(B(2));
+        resources.insert_named("named", A(3));
+        let counter = removed.clone();
+        resources.on_remove_any(move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
 
-    use crate::BorrowFail;
+        let mut drain = resources.drain();
+        assert!(drain.next().is_some());
+        assert_eq!(1, removed.load(Ordering::SeqCst));
 
-    use super::Resources;
+        drop(drain);
+        assert_eq!(2, removed.load(Ordering::SeqCst));
+        assert!(!resources.contains_named::<A>("named"));
+        assert!(resources.is_empty());
+    }
 
     #[test]
-    fn entry_or_insert_inserts_value() {
-        let mut resources = Resources::new();
-        let mut a_ref = resources.entry::<A>().or_insert(A(1));
+    fn ref_mut_released_during_unwind_does_not_poison_resource() {
+        struct BorrowOnDrop<'r>(&'r Resources<'static>);
 
-        assert_eq!(&A(1), &*a_ref);
+        impl Drop for BorrowOnDrop<'_> {
+            fn drop(&mut self) {
+                self.0.borrow_mut::<A>().0 += 1;
+            }
+        }
 
-        *a_ref = A(2);
+        let mut resources = Resources::new();
+        resources.insert(A(1));
 
-        drop(a_ref);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _borrow_on_drop = BorrowOnDrop(&resources);
+            panic!("failed");
+        }));
+        assert!(result.is_err());
 
+        assert!(!resources.is_poisoned::<A>());
         assert_eq!(&A(2), &*resources.borrow::<A>());
     }
 
-    #[cfg(not(feature = "debug"))]
     #[test]
-    fn debug_uses_placeholder_for_values() {
+    fn entry_of_poisoned_resource_is_reported() {
         let mut resources = Resources::new();
+        resources.insert(A(1));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _a = resources.borrow_mut::<A>();
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+
+        assert!(matches!(
+            resources.try_entry::<A>(),
+            Err(BorrowError::Poisoned {
+                mode: BorrowMode::Exclusive,
+                ..
+            })
+        ));
+    }
 
-        resources.insert(A(0));
-        resources.insert(Res);
+    #[test]
+    fn entry_or_insert_with_key_receives_type_name() {
+        let mut resources = Resources::new();
+        let mut type_name = None;
 
-        let resources_dbg = format!("{:?}", resources);
-        assert!(
-            resources_dbg.contains(r#"A: "..""#),
-            r#"Expected `{}` to contain `A: ".."`"#,
-            resources_dbg
-        );
-        assert!(
-            resources_dbg.contains(r#"Res: "..""#),
-            r#"Expected `{}` to contain `Res: ".."`"#,
-            resources_dbg
-        );
+        resources.entry::<A>().or_insert_with_key(|key| {
+            type_name = Some(key);
+            A(1)
+        });
+
+        assert_eq!(Some(TypeNameLit::of::<A>()), type_name);
+    }
+
+    #[test]
+    fn occupied_entry_insert_and_remove() {
+        let mut resources = Resources::new();
+        resources.insert(A(1));
+
+        let Entry::Occupied(mut entry) = resources.entry::<A>() else {
+            panic!("Expected entry of A to be occupied.");
+        };
+        assert_eq!(&A(1), &*entry.get());
+        assert_eq!(A(1), entry.insert(A(2)));
+        entry.get_mut().0 += 1;
+        assert_eq!(A(3), entry.remove());
+
+        assert!(!resources.contains::<A>());
+        assert!(matches!(resources.entry::<A>(), Entry::Vacant(_)));
     }
 
-    #[cfg(feature = "debug")]
     #[test]
-    fn debug_uses_debug_implementation_for_values() {
+    fn debug_uses_placeholder_for_unregistered_values() {
         let mut resources = Resources::new();
 
         resources.insert(A(0));
         resources.insert(Res);
+        resources.insert_named("named", A(1));
+        resources.register_debug::<A>();
 
         let resources_dbg = format!("{:?}", resources);
         assert!(
@@ -334,8 +2365,13 @@ mod tests {
             resources_dbg
         );
         assert!(
-            resources_dbg.contains(r#"Res: Res"#),
-            r#"Expected `{}` to contain `Res: Res`"#,
+            resources_dbg.contains(r#"A["named"]: A(1)"#),
+            r#"Expected `{}` to contain `A["named"]: A(1)`"#,
+            resources_dbg
+        );
+        assert!(
+            resources_dbg.contains(r#"Res: "..""#),
+            r#"Expected `{}` to contain `Res: ".."`"#,
             resources_dbg
         );
     }
@@ -366,7 +2402,7 @@ mod tests {
 
     #[test]
     #[should_panic(
-        expected = "Expected to borrow `stateman::resources::tests::Res` mutably, but it was already borrowed mutably."
+        expected = "Expected to borrow `stateman::resources::tests::Res` mutably, but it was already borrowed immutably."
     )]
     fn read_write_fails() {
         let mut resources = Resources::default();
@@ -429,10 +2465,15 @@ mod tests {
 
         let _res = resources.borrow_mut::<Res>();
 
-        assert_eq!(
-            Err(BorrowFail::BorrowConflictImm),
-            resources.try_borrow::<Res>()
-        );
+        assert!(matches!(
+            resources.try_borrow::<Res>(),
+            Err(BorrowError::ConflictExclusive {
+                type_name,
+                label: None,
+                mode: BorrowMode::Shared,
+                ..
+            }) if type_name == TypeNameLit::of::<Res>()
+        ));
     }
 
     #[test]
@@ -442,25 +2483,307 @@ mod tests {
 
         let _res = resources.borrow::<Res>();
 
+        assert!(matches!(
+            resources.try_borrow_mut::<Res>(),
+            Err(BorrowError::ConflictShared {
+                type_name,
+                label: None,
+                mode: BorrowMode::Exclusive,
+                ..
+            }) if type_name == TypeNameLit::of::<Res>()
+        ));
+    }
+
+    #[test]
+    fn borrow_mut_borrow_mut_returns_err() {
+        let mut resources = Resources::default();
+        resources.insert(Res);
+
+        let _res = resources.borrow_mut::<Res>();
+
+        assert!(matches!(
+            resources.try_borrow_mut::<Res>(),
+            Err(BorrowError::ConflictExclusive {
+                type_name,
+                label: None,
+                mode: BorrowMode::Exclusive,
+                ..
+            }) if type_name == TypeNameLit::of::<Res>()
+        ));
+    }
+
+    #[test]
+    fn try_borrow_before_insert_returns_not_found() {
+        let resources = Resources::default();
+
         assert_eq!(
-            Err(BorrowFail::BorrowConflictMut),
-            resources.try_borrow_mut::<Res>()
+            Err(BorrowError::NotFound {
+                type_name: TypeNameLit::of::<Res>(),
+                label: None,
+                mode: BorrowMode::Shared,
+            }),
+            resources.try_borrow::<Res>()
         );
     }
 
+    #[cfg(feature = "track_borrows")]
     #[test]
-    fn borrow_mut_borrow_mut_returns_err() {
+    fn conflict_reports_location_of_held_borrow() {
         let mut resources = Resources::default();
         resources.insert(Res);
 
         let _res = resources.borrow_mut::<Res>();
+        let line = line!() - 1;
+
+        let borrow_error = resources.try_borrow::<Res>().map(|_| ()).unwrap_err();
+        let held_at = borrow_error
+            .held_at()
+            .expect("Expected location to be recorded.");
+
+        assert_eq!(BorrowMode::Exclusive, held_at.mode());
+        assert_eq!(file!(), held_at.location().file());
+        assert_eq!(line, held_at.location().line());
+        assert!(borrow_error.to_string().ends_with(&format!(
+            "It is currently held mutably at {}.",
+            held_at.location()
+        )));
+    }
+
+    #[cfg(feature = "track_borrows")]
+    #[test]
+    fn dropping_borrow_removes_location() {
+        let mut resources = Resources::default();
+        resources.insert(Res);
+
+        let res = resources.borrow::<Res>();
+        drop(res);
+        let _res = resources.borrow::<Res>();
+        let line = line!() - 1;
+
+        let borrow_error = resources.try_borrow_mut::<Res>().map(|_| ()).unwrap_err();
+        let held_at = borrow_error
+            .held_at()
+            .expect("Expected location to be recorded.");
+
+        assert_eq!(line, held_at.location().line());
+    }
+
+    #[cfg(feature = "track_borrows")]
+    #[test]
+    fn entry_borrow_is_recorded() {
+        let mut resources = Resources::default();
+
+        let res = resources.entry::<Res>().or_insert(Res);
+        let line = line!() - 1;
+        // Leaks the borrow, so the map can be accessed again.
+        std::mem::forget(res);
+
+        let borrow_error = resources.try_borrow::<Res>().map(|_| ()).unwrap_err();
+        assert_eq!(
+            Some(line),
+            borrow_error
+                .held_at()
+                .map(|held_at| held_at.location().line())
+        );
+    }
+
+    #[test]
+    fn named_resources_are_separate_from_unnamed() {
+        let mut resources = Resources::default();
+        resources.insert(A(0));
+        resources.insert_named("left", A(1));
+        resources.insert_named(String::from("right"), A(2));
+
+        let mut left = resources.borrow_mut_named::<A>("left");
+        let right = resources.borrow_named::<A>("right");
+        left.0 += right.0;
+        drop(left);
+
+        assert_eq!(&A(0), &*resources.borrow::<A>());
+        assert_eq!(&A(3), &*resources.borrow_named::<A>("left"));
+        assert!(resources.contains_named::<A>("right"));
+        assert!(!resources.contains_named::<Res>("right"));
+    }
+
+    #[test]
+    fn remove_named_returns_resource() {
+        let mut resources = Resources::default();
+        resources.insert_named("left", A(1));
+
+        assert_eq!(Some(A(1)), resources.remove_named::<A>("left"));
+        assert!(!resources.contains_named::<A>("left"));
+        assert_eq!(None, resources.remove_named::<A>("left"));
+
+        resources
+            .named
+            .insert((A::id(), Label::from("left")), Box::new(B(1)));
+        assert_eq!(None, resources.remove_named::<A>("left"));
+        assert!(resources.contains_named::<A>("left"));
+    }
+
+    #[test]
+    fn borrow_named_conflict_returns_err() {
+        let mut resources = Resources::default();
+        resources.insert_named("left", A(1));
+        resources.insert_named("right", A(2));
+
+        let _left = resources.borrow_named::<A>("left");
+
+        let borrow_error = resources.try_borrow_mut_named::<A>("left").unwrap_err();
+        assert!(matches!(
+            borrow_error,
+            BorrowError::ConflictShared {
+                mode: BorrowMode::Exclusive,
+                ..
+            }
+        ));
+        assert_eq!(Some(&Label::from("left")), borrow_error.label());
+        assert!(borrow_error.to_string().contains(r#"A["left"]"#));
+        assert!(resources.try_borrow_mut_named::<A>("right").is_ok());
+        assert!(matches!(
+            resources.try_borrow_named::<A>("middle"),
+            Err(BorrowError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn iter_named_yields_resources_of_type() {
+        let mut resources = Resources::default();
+        resources.insert(A(0));
+        resources.insert_named("left", A(1));
+        resources.insert_named("right", A(2));
+        resources.insert_named("left", Res);
+
+        let mut named = resources
+            .iter_named::<A>()
+            .map(|(label, a)| (label.as_str().to_owned(), a.unwrap().0))
+            .collect::<Vec<_>>();
+        named.sort();
 
         assert_eq!(
-            Err(BorrowFail::BorrowConflictMut),
-            resources.try_borrow_mut::<Res>()
+            vec![(String::from("left"), 1), (String::from("right"), 2)],
+            named
+        );
+    }
+
+    #[test]
+    fn iter_named_yields_err_for_borrowed_resource() {
+        let mut resources = Resources::default();
+        resources.insert_named("left", A(1));
+
+        let _left = resources.borrow_mut_named::<A>("left");
+
+        let named = resources.iter_named::<A>().collect::<Vec<_>>();
+        assert_eq!(1, named.len());
+        assert!(matches!(
+            named[0].1,
+            Err(BorrowError::ConflictExclusive { label: Some(_), .. })
+        ));
+    }
+
+    #[test]
+    fn iter_named_borrows_resources_lazily() {
+        let mut resources = Resources::default();
+        resources.insert_named("left", A(1));
+        resources.insert_named("right", A(2));
+
+        let mut named = resources.iter_named::<A>();
+        let (label, first) = named.next().unwrap();
+        let first = first.unwrap();
+
+        assert!(resources.try_borrow_mut_named::<A>(label.clone()).is_err());
+        let other = if label.as_str() == "left" {
+            "right"
+        } else {
+            "left"
+        };
+        assert!(resources.try_borrow_mut_named::<A>(other).is_ok());
+
+        drop(first);
+        let (_, second) = named.next().unwrap();
+        assert!(second.is_ok());
+        assert!(named.next().is_none());
+    }
+
+    #[test]
+    fn debug_shows_type_and_label_of_named_resources() {
+        let mut resources = Resources::default();
+        resources.insert_named("left", Res);
+
+        let resources_dbg = format!("{:?}", resources);
+        assert!(
+            resources_dbg.contains(r#"Res["left"]"#),
+            r#"Expected `{}` to contain `Res["left"]`"#,
+            resources_dbg
         );
     }
 
+    #[test]
+    fn deref_mut_marks_resource_changed() {
+        let mut resources = Resources::default();
+        resources.insert(A(0));
+        let since = resources.tick();
+        resources.increment_tick();
+
+        let a = resources.borrow_mut::<A>();
+        assert_eq!(0, a.0);
+        drop(a);
+        assert!(!resources.is_changed::<A>(since));
+
+        resources.borrow_mut::<A>().0 = 1;
+        assert!(resources.is_changed::<A>(since));
+        assert!(!resources.is_added::<A>(since));
+        assert!(!resources.is_changed::<A>(resources.tick()));
+    }
+
+    #[test]
+    fn bypass_change_detection_does_not_mark_resource_changed() {
+        let mut resources = Resources::default();
+        resources.insert(A(0));
+        let since = resources.increment_tick();
+        resources.increment_tick();
+
+        let mut a = resources.borrow_mut::<A>();
+        RefMut::bypass_change_detection(&mut a).0 = 1;
+        drop(a);
+
+        assert_eq!(&A(1), &*resources.borrow::<A>());
+        assert!(!resources.is_changed::<A>(since));
+    }
+
+    #[test]
+    fn insert_marks_resource_added() {
+        let mut resources = Resources::default();
+        let since = resources.tick();
+        resources.increment_tick();
+
+        assert!(!resources.is_added::<A>(since));
+
+        resources.insert(A(0));
+        resources.entry::<Res>().or_insert(Res);
+
+        assert!(resources.is_added::<A>(since));
+        assert!(resources.is_changed::<A>(since));
+        assert!(resources.is_added::<Res>(since));
+    }
+
+    #[test]
+    fn ref_reports_change_ticks() {
+        let mut resources = Resources::default();
+        resources.insert(A(0));
+        resources.increment_tick();
+        resources.get_mut::<A>().unwrap().0 = 1;
+
+        let a = resources.borrow::<A>();
+        assert_eq!(Some(0), Ref::added_tick(&a));
+        assert_eq!(Some(1), Ref::changed_tick(&a));
+        assert!(Ref::is_changed(&a, 0));
+        assert!(!Ref::is_added(&a, 0));
+
+        let field = Ref::map(a, |a| &a.0);
+        assert!(Ref::is_changed(&field, 0));
+    }
+
     #[test]
     fn get_mut_returns_ok() {
         let mut resources = Resources::default();
@@ -487,13 +2810,270 @@ mod tests {
         assert!(resources.get_raw(&Res::id()).is_some());
     }
 
+    #[test]
+    fn ref_mut_dropped_during_panic_poisons_resource() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        resources.insert(Res);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _a = resources.borrow::<A>();
+            let mut b = resources.borrow_mut::<Res>();
+            *b = Res;
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+        assert!(!resources.is_poisoned::<A>());
+        assert!(resources.is_poisoned::<Res>());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _a = resources.borrow_mut::<A>();
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+
+        assert!(resources.is_poisoned::<A>());
+        assert_eq!(
+            Err(BorrowError::Poisoned {
+                type_name: TypeNameLit::of::<A>(),
+                label: None,
+                mode: BorrowMode::Exclusive,
+            }),
+            resources.try_borrow_mut::<A>().map(|_| ())
+        );
+        assert_eq!(
+            &A(1),
+            &*resources
+                .try_borrow_poisoned::<A>()
+                .unwrap()
+                .unwrap_err()
+                .into_inner()
+        );
+
+        resources.clear_poison::<A>();
+        assert!(resources.try_borrow::<A>().is_ok());
+    }
+
+    #[test]
+    fn named_and_trait_object_borrows_report_poison() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        resources.insert_named("left", A(2));
+        resources.register_trait::<A, dyn Value>(|a| a, |a| a);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _left = resources.borrow_mut_named::<A>("left");
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+
+        assert!(resources.is_poisoned_named::<A>("left"));
+        assert!(!resources.is_poisoned::<A>());
+        assert_eq!(
+            Err(BorrowError::Poisoned {
+                type_name: TypeNameLit::of::<A>(),
+                label: Some(Label::from("left")),
+                mode: BorrowMode::Shared,
+            }),
+            resources.try_borrow_named::<A>("left").map(|_| ())
+        );
+        assert_eq!(
+            &A(2),
+            &*resources
+                .try_borrow_mut_named_poisoned::<A>("left")
+                .unwrap()
+                .unwrap_err()
+                .into_inner()
+        );
+        resources.clear_poison_named::<A>("left");
+        assert!(resources.try_borrow_named::<A>("left").is_ok());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _values = resources.iter_dyn_mut::<dyn Value>().collect::<Vec<_>>();
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+
+        assert!(resources.is_poisoned::<A>());
+        assert!(matches!(
+            resources.iter_dyn::<dyn Value>().next().unwrap(),
+            Err(BorrowError::Poisoned {
+                mode: BorrowMode::Shared,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn insert_clears_poison() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _a = resources.borrow_mut::<A>();
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+        resources.insert(A(2));
+
+        assert!(!resources.is_poisoned::<A>());
+        assert_eq!(&A(2), &*resources.borrow::<A>());
+    }
+
+    #[test]
+    fn insert_returns_previous_value() {
+        let mut resources = Resources::default();
+
+        assert_eq!(None, resources.insert(A(1)));
+        assert_eq!(Some(A(1)), resources.insert(A(2)));
+    }
+
+    #[test]
+    fn replace_take_return_previous_value() {
+        let mut resources = Resources::default();
+
+        assert_eq!(
+            Err(BorrowError::NotFound {
+                type_name: TypeNameLit::of::<A>(),
+                label: None,
+                mode: BorrowMode::Exclusive,
+            }),
+            resources.replace(A(1))
+        );
+        assert!(!resources.contains::<A>());
+
+        resources.insert(A(1));
+        resources.insert(Res);
+
+        assert_eq!(Ok(A(1)), resources.replace(A(2)));
+        assert_eq!(Ok(Res), resources.take::<Res>());
+        assert_eq!(&A(2), &*resources.borrow::<A>());
+    }
+
+    #[test]
+    fn swap_exchanges_or_moves_values() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        resources.insert(B(1));
+        let mut other = Resources::default();
+        other.insert(A(2));
+
+        resources.swap::<A>(&mut other).unwrap();
+        resources.swap::<B>(&mut other).unwrap();
+
+        assert_eq!(&A(2), &*resources.borrow::<A>());
+        assert_eq!(&A(1), &*other.borrow::<A>());
+        assert!(!resources.contains::<B>());
+        assert_eq!(&B(1), &*other.borrow::<B>());
+    }
+
+    #[test]
+    fn swap_keeps_bookkeeping_and_calls_replace_observers() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _a = resources.borrow_mut::<A>();
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+        let replaced = Arc::new(AtomicUsize::new(0));
+        let observed = Arc::clone(&replaced);
+        resources.on_replace::<A>(move |_, old, new| {
+            observed.fetch_add(old.0 * 10 + new.0, Ordering::SeqCst);
+        });
+
+        let mut other = Resources::default();
+        other.increment_tick();
+        other.insert(A(2));
+        other.on_remove::<A>(|_, _| panic!("Expected A to be replaced."));
+
+        resources.swap::<A>(&mut other).unwrap();
+
+        assert_eq!(12, replaced.load(Ordering::SeqCst));
+        assert!(other.is_poisoned::<A>());
+        assert!(!resources.is_poisoned::<A>());
+        assert!(resources.is_added::<A>(0));
+        assert!(!other.is_added::<A>(0));
+    }
+
+    #[test]
+    fn mismatched_insert_raw_returns_type_mismatch() {
+        let mut resources = Resources::default();
+        resources.insert_raw(A::id(), Box::new(B(1)));
+        let mut other = Resources::default();
+        other.insert(A(2));
+
+        let type_mismatch = |mode| BorrowError::TypeMismatch {
+            type_name: TypeNameLit::of::<A>(),
+            label: None,
+            mode,
+        };
+        assert_eq!(
+            Some(type_mismatch(BorrowMode::Shared)),
+            resources.try_borrow::<A>().err()
+        );
+        assert_eq!(
+            Some(type_mismatch(BorrowMode::Exclusive)),
+            resources.try_borrow_mut::<A>().err()
+        );
+        assert_eq!(
+            Err(type_mismatch(BorrowMode::Exclusive)),
+            resources.replace(A(3))
+        );
+        assert_eq!(
+            Err(type_mismatch(BorrowMode::Exclusive)),
+            resources.swap::<A>(&mut other)
+        );
+        assert_eq!(None, resources.get_mut::<A>());
+        assert_eq!(None, resources.remove::<A>());
+
+        assert_eq!(
+            Err(type_mismatch(BorrowMode::Exclusive)),
+            resources.try_insert(A(4))
+        );
+
+        assert!(resources.contains::<A>());
+        assert_eq!(&A(2), &*other.borrow::<A>());
+        let old = resources.insert_raw(A::id(), Box::new(A(4))).unwrap();
+        assert_eq!(Some(&B(1)), old.downcast_ref::<B>());
+        assert_eq!(&A(4), &*resources.borrow::<A>());
+    }
+
     #[derive(Debug, Default, PartialEq, Tid)]
     struct Res;
 
-    #[cfg_attr(feature = "debug", derive(Debug))]
     #[derive(Tid)]
     struct Foo;
 
     #[derive(Debug, PartialEq, Tid)]
     struct A(usize);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct B(usize);
+
+    trait Value {
+        fn value(&self) -> usize;
+
+        fn set_value(&mut self, value: usize);
+    }
+
+    impl Value for A {
+        fn value(&self) -> usize {
+            self.0
+        }
+
+        fn set_value(&mut self, value: usize) {
+            self.0 = value;
+        }
+    }
+
+    impl Value for B {
+        fn value(&self) -> usize {
+            self.0
+        }
+
+        fn set_value(&mut self, value: usize) {
+            self.0 = value;
+        }
+    }
 }
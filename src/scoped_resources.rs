@@ -0,0 +1,315 @@
+use std::{any::TypeId, fmt};
+
+use crate::{BorrowError, Ref, RefMut, Resource, Resources};
+
+/// [Resources] layered on top of parent resources.
+///
+/// Borrows look in the local layer first and then walk up the chain of
+/// parents, while inserts and removes only affect the local layer. This allows
+/// scopes to see the resources of their parents, but override some of them
+/// locally.
+///
+/// A resource which exists in a layer is always borrowed from that layer, so
+/// a conflicting borrow is reported instead of falling back to a parent.
+///
+/// ## Examples
+///
+/// ```rust
+/// use better_any::Tid;
+/// use stateman::Resources;
+///
+/// #[derive(Debug, Tid)]
+/// struct Gravity(f32);
+///
+/// #[derive(Debug, Tid)]
+/// struct Score(u32);
+///
+/// let mut global = Resources::default();
+/// global.insert(Gravity(9.81));
+/// global.insert(Score(0));
+///
+/// let mut level = global.child();
+/// level.insert(Gravity(1.62));
+///
+/// assert_eq!(1.62, level.borrow::<Gravity>().0);
+/// assert_eq!(Some(0), level.layer_of::<Gravity>());
+///
+/// level.borrow_mut::<Score>().0 += 1;
+/// assert_eq!(Some(1), level.layer_of::<Score>());
+///
+/// drop(level);
+/// assert_eq!(9.81, global.borrow::<Gravity>().0);
+/// assert_eq!(1, global.borrow::<Score>().0);
+/// ```
+pub struct ScopedResources<'p, 'a> {
+    local: Resources<'a>,
+    parent: Parent<'p, 'a>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Parent<'p, 'a> {
+    Root(&'p Resources<'a>),
+    Scope(&'p ScopedResources<'p, 'a>),
+}
+
+impl<'p, 'a> ScopedResources<'p, 'a> {
+    pub(crate) fn new(parent: &'p Resources<'a>) -> Self {
+        Self {
+            local: Resources::default(),
+            parent: Parent::Root(parent),
+        }
+    }
+
+    /// Returns a new scope with `self` as its parent.
+    pub fn child(&self) -> ScopedResources<'_, 'a> {
+        ScopedResources {
+            local: Resources::default(),
+            parent: Parent::Scope(self),
+        }
+    }
+
+    /// Returns the resources of the local layer.
+    pub fn local(&self) -> &Resources<'a> {
+        &self.local
+    }
+
+    /// Returns the resources of the local layer.
+    pub fn local_mut(&mut self) -> &mut Resources<'a> {
+        &mut self.local
+    }
+
+    /// Inserts a resource into the local layer. If the resource existed in
+    /// the local layer before, it will be overwritten.
+    pub fn insert<R>(&mut self, r: R)
+    where
+        R: Resource<'a>,
+    {
+        self.local.insert(r);
+    }
+
+    /// Inserts an already boxed resource into the local layer.
+    pub fn insert_raw(&mut self, type_id: TypeId, resource: Box<dyn Resource<'a>>) {
+        self.local.insert_raw(type_id, resource);
+    }
+
+    /// Removes a resource of type `R` from the local layer and returns its
+    /// ownership to the caller.
+    ///
+    /// Resources of the parents are not affected, so the resource may still
+    /// be borrowed from a parent afterwards.
+    pub fn remove<R>(&mut self) -> Option<R>
+    where
+        R: Resource<'a>,
+    {
+        self.local.remove::<R>()
+    }
+
+    /// Returns true if the resource type `R` exists in any layer.
+    pub fn contains<R>(&self) -> bool
+    where
+        R: Resource<'a>,
+    {
+        self.layer_of::<R>().is_some()
+    }
+
+    /// Returns the layer the resource `R` is borrowed from, where `0` is the
+    /// local layer, `1` its parent and so on, or `None` if it doesn't exist.
+    pub fn layer_of<R>(&self) -> Option<usize>
+    where
+        R: Resource<'a>,
+    {
+        if self.local.contains::<R>() {
+            return Some(0);
+        }
+
+        match self.parent {
+            Parent::Root(resources) => resources.contains::<R>().then_some(1),
+            Parent::Scope(scope) => scope.layer_of::<R>().map(|layer| layer + 1),
+        }
+    }
+
+    /// Returns the `R` resource of the nearest layer containing it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist.
+    /// Panics if the resource is being accessed mutably.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow<R>(&self) -> Ref<'_, 'a, R>
+    where
+        R: Resource<'a>,
+    {
+        self.try_borrow::<R>()
+            .unwrap_or_else(|borrow_error| panic!("{borrow_error}"))
+    }
+
+    /// Returns an immutable reference to the `R` resource of the nearest
+    /// layer containing it.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow<R>(&self) -> Result<Ref<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        match self.local.try_borrow::<R>() {
+            Err(BorrowError::NotFound { .. }) => match self.parent {
+                Parent::Root(resources) => resources.try_borrow::<R>(),
+                Parent::Scope(scope) => scope.try_borrow::<R>(),
+            },
+            result => result,
+        }
+    }
+
+    /// Returns a mutable reference to the `R` resource of the nearest layer
+    /// containing it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist.
+    /// Panics if the resource is already accessed.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_mut<R>(&self) -> RefMut<'_, 'a, R>
+    where
+        R: Resource<'a>,
+    {
+        self.try_borrow_mut::<R>()
+            .unwrap_or_else(|borrow_error| panic!("{borrow_error}"))
+    }
+
+    /// Returns a mutable reference to the `R` resource of the nearest layer
+    /// containing it.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_mut<R>(&self) -> Result<RefMut<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        match self.local.try_borrow_mut::<R>() {
+            Err(BorrowError::NotFound { .. }) => match self.parent {
+                Parent::Root(resources) => resources.try_borrow_mut::<R>(),
+                Parent::Scope(scope) => scope.try_borrow_mut::<R>(),
+            },
+            result => result,
+        }
+    }
+}
+
+impl<'p, 'a> fmt::Debug for ScopedResources<'p, 'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScopedResources")
+            .field("local", &self.local)
+            .field("parent", &self.parent)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use better_any::Tid;
+
+    use crate::{BorrowError, BorrowMode, Resources, TypeNameLit};
+
+    #[test]
+    fn borrow_prefers_local_layer() {
+        let mut root = Resources::default();
+        root.insert(A(0));
+        root.insert(B(0));
+
+        let mut child = root.child();
+        child.insert(A(1));
+
+        assert_eq!(&A(1), &*child.borrow::<A>());
+        assert_eq!(&B(0), &*child.borrow::<B>());
+        assert_eq!(Some(0), child.layer_of::<A>());
+        assert_eq!(Some(1), child.layer_of::<B>());
+        assert_eq!(None, child.layer_of::<C>());
+        assert_eq!(&A(0), &*root.borrow::<A>());
+    }
+
+    #[test]
+    fn borrow_walks_up_the_chain() {
+        let mut root = Resources::default();
+        root.insert(A(0));
+
+        let mut child = root.child();
+        child.insert(B(1));
+
+        let mut grandchild = child.child();
+        grandchild.insert(C(2));
+        grandchild.borrow_mut::<A>().0 = 3;
+
+        assert_eq!(Some(2), grandchild.layer_of::<A>());
+        assert_eq!(Some(1), grandchild.layer_of::<B>());
+        assert_eq!(Some(0), grandchild.layer_of::<C>());
+        assert_eq!(&A(3), &*grandchild.borrow::<A>());
+        assert!(!child.contains::<C>());
+    }
+
+    #[test]
+    fn remove_only_affects_local_layer() {
+        let mut root = Resources::default();
+        root.insert(A(0));
+
+        let mut child = root.child();
+        child.insert(A(1));
+
+        assert_eq!(Some(A(1)), child.remove::<A>());
+        assert_eq!(None, child.remove::<A>());
+        assert_eq!(&A(0), &*child.borrow::<A>());
+    }
+
+    #[test]
+    fn borrow_conflict_in_parent_is_reported() {
+        let mut root = Resources::default();
+        root.insert(A(0));
+
+        let child = root.child();
+        let _a = root.borrow::<A>();
+
+        assert!(matches!(
+            child.try_borrow_mut::<A>(),
+            Err(BorrowError::ConflictShared {
+                mode: BorrowMode::Exclusive,
+                ..
+            })
+        ));
+        assert!(child.try_borrow::<A>().is_ok());
+    }
+
+    #[test]
+    fn borrow_conflict_in_local_layer_does_not_fall_back_to_parent() {
+        let mut root = Resources::default();
+        root.insert(A(0));
+
+        let mut child = root.child();
+        child.insert(A(1));
+        let _a = child.borrow_mut::<A>();
+
+        assert!(matches!(
+            child.try_borrow::<A>(),
+            Err(BorrowError::ConflictExclusive { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_resource_returns_not_found() {
+        let root = Resources::default();
+        let child = root.child();
+
+        assert_eq!(
+            Err(BorrowError::NotFound {
+                type_name: TypeNameLit::of::<A>(),
+                label: None,
+                mode: BorrowMode::Shared,
+            }),
+            child.try_borrow::<A>()
+        );
+    }
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct A(u32);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct B(u32);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct C(u32);
+}
@@ -0,0 +1,293 @@
+use std::{
+    cell::UnsafeCell,
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+    thread::{self, ThreadId},
+    time::Instant,
+};
+
+use crate::{poison::Poison, BorrowMode, Resource, TypeNameLit};
+
+/// Thread safe storage of a single resource, see
+/// [SyncResources][crate::SyncResources].
+pub(crate) struct Slot<'a> {
+    value: UnsafeCell<Box<dyn Resource<'a> + Sync>>,
+    /// Type name of the value, which can be accessed while it is borrowed.
+    type_name: TypeNameLit,
+    poison: Poison,
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+// SAFETY: Access to `value` is guarded by `state`, and the value itself is
+// `Send + Sync`.
+unsafe impl<'a> Sync for Slot<'a> {}
+
+#[derive(Default)]
+struct State {
+    shared: usize,
+    exclusive: bool,
+    /// Threads which took the current borrows, see [`Slot::holders`].
+    holders: Vec<ThreadId>,
+    /// Pending [SlotFuture]s in the order in which they were first polled.
+    waiters: VecDeque<(u64, Waker)>,
+    next_waiter: u64,
+}
+
+impl State {
+    /// Returns the mode of the current borrow which conflicts with a borrow in
+    /// `mode`, if any.
+    fn conflict(&self, mode: BorrowMode) -> Option<BorrowMode> {
+        if self.exclusive {
+            Some(BorrowMode::Exclusive)
+        } else if mode == BorrowMode::Exclusive && self.shared > 0 {
+            Some(BorrowMode::Shared)
+        } else {
+            None
+        }
+    }
+
+    fn acquire(&mut self, mode: BorrowMode, thread: ThreadId) {
+        match mode {
+            BorrowMode::Shared => self.shared += 1,
+            BorrowMode::Exclusive => self.exclusive = true,
+        }
+        self.holders.push(thread);
+    }
+
+    fn release(&mut self, mode: BorrowMode, thread: ThreadId) {
+        match mode {
+            BorrowMode::Shared => self.shared -= 1,
+            BorrowMode::Exclusive => self.exclusive = false,
+        }
+        if let Some(index) = self.holders.iter().position(|holder| *holder == thread) {
+            self.holders.swap_remove(index);
+        }
+    }
+
+    /// Wakes the first waiting future, which is the only one that may
+    /// borrow the slot next.
+    fn wake_first(&self) {
+        if let Some((_, waker)) = self.waiters.front() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl<'a> Slot<'a> {
+    pub(crate) fn new(value: Box<dyn Resource<'a> + Sync>) -> Self {
+        Self {
+            type_name: value.type_name(),
+            value: UnsafeCell::new(value),
+            poison: Poison::default(),
+            state: Mutex::default(),
+            released: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> Box<dyn Resource<'a> + Sync> {
+        self.value.into_inner()
+    }
+
+    pub(crate) fn type_name(&self) -> TypeNameLit {
+        self.type_name
+    }
+
+    pub(crate) fn poison(&self) -> &Poison {
+        &self.poison
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut Box<dyn Resource<'a> + Sync> {
+        self.value.get_mut()
+    }
+
+    /// Borrows the value in `mode`, or returns the mode of the conflicting
+    /// borrow.
+    pub(crate) fn try_borrow(&self, mode: BorrowMode) -> Result<SlotBorrow<'_, 'a>, BorrowMode> {
+        let mut state = self.lock();
+        match state.conflict(mode) {
+            Some(held_mode) => Err(held_mode),
+            None => Ok(self.acquire(&mut state, mode)),
+        }
+    }
+
+    /// Borrows the value in `mode`, waiting until conflicting borrows are
+    /// released or `deadline` has passed. Returns the mode of the conflicting
+    /// borrow if the deadline has passed.
+    pub(crate) fn borrow_until(
+        &self,
+        mode: BorrowMode,
+        deadline: Option<Instant>,
+    ) -> Result<SlotBorrow<'_, 'a>, BorrowMode> {
+        let mut state = self.lock();
+        while let Some(held_mode) = state.conflict(mode) {
+            state = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline
+                        .checked_duration_since(Instant::now())
+                        .ok_or(held_mode)?;
+                    self.released
+                        .wait_timeout(state, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .released
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+
+        Ok(self.acquire(&mut state, mode))
+    }
+
+    /// Returns a future which borrows the value in `mode` once conflicting
+    /// borrows are released.
+    ///
+    /// Futures borrow the slot in the order in which they were first polled.
+    pub(crate) fn borrow_async(&self, mode: BorrowMode) -> SlotFuture<'_, 'a> {
+        SlotFuture {
+            slot: self,
+            mode,
+            waiter: None,
+        }
+    }
+
+    /// Returns the threads which took the current borrows of the value.
+    pub(crate) fn holders(&self) -> Vec<ThreadId> {
+        self.lock().holders.clone()
+    }
+
+    fn acquire(&self, state: &mut State, mode: BorrowMode) -> SlotBorrow<'_, 'a> {
+        let thread = thread::current().id();
+        state.acquire(mode, thread);
+
+        SlotBorrow {
+            slot: self,
+            mode,
+            thread,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // The state is updated atomically, so it stays consistent even if the
+        // mutex is poisoned.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Live borrow of a [Slot], released on drop.
+pub(crate) struct SlotBorrow<'s, 'a> {
+    slot: &'s Slot<'a>,
+    mode: BorrowMode,
+    /// Thread which took the borrow, which may differ from the thread
+    /// dropping it.
+    thread: ThreadId,
+}
+
+impl<'s, 'a> SlotBorrow<'s, 'a> {
+    pub(crate) fn poison(&self) -> &'s Poison {
+        self.slot.poison()
+    }
+
+    pub(crate) fn value(&self) -> &(dyn Resource<'a> + Sync) {
+        // SAFETY: The slot is borrowed, so there is no exclusive borrow other
+        // than possibly `self`.
+        unsafe { &**self.slot.value.get() }
+    }
+
+    pub(crate) fn value_mut(&mut self) -> &mut (dyn Resource<'a> + Sync) {
+        assert_eq!(BorrowMode::Exclusive, self.mode);
+        // SAFETY: The slot is borrowed exclusively by `self`.
+        unsafe { &mut **self.slot.value.get() }
+    }
+}
+
+impl<'s, 'a> Clone for SlotBorrow<'s, 'a> {
+    fn clone(&self) -> Self {
+        assert_eq!(BorrowMode::Shared, self.mode);
+        self.slot.acquire(&mut self.slot.lock(), BorrowMode::Shared)
+    }
+}
+
+impl<'s, 'a> Drop for SlotBorrow<'s, 'a> {
+    fn drop(&mut self) {
+        let mut state = self.slot.lock();
+        state.release(self.mode, self.thread);
+        state.wake_first();
+        drop(state);
+        self.slot.released.notify_all();
+    }
+}
+
+/// Future returned by [`Slot::borrow_async`].
+pub(crate) struct SlotFuture<'s, 'a> {
+    slot: &'s Slot<'a>,
+    mode: BorrowMode,
+    /// Id of the queued waker, once the future had to wait.
+    waiter: Option<u64>,
+}
+
+impl<'s, 'a> Future for SlotFuture<'s, 'a> {
+    type Output = SlotBorrow<'s, 'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let slot = self.slot;
+        let mut state = slot.lock();
+
+        let is_first = match self.waiter {
+            Some(id) => matches!(state.waiters.front(), Some((first, _)) if *first == id),
+            None => state.waiters.is_empty(),
+        };
+        if is_first && state.conflict(self.mode).is_none() {
+            if self.waiter.take().is_some() {
+                state.waiters.pop_front();
+            }
+            let borrow = slot.acquire(&mut state, self.mode);
+            // The next future may be able to borrow the slot as well.
+            state.wake_first();
+
+            return Poll::Ready(borrow);
+        }
+
+        match self.waiter {
+            Some(id) => {
+                if let Some((_, waker)) = state.waiters.iter_mut().find(|(waiter, _)| *waiter == id)
+                {
+                    if !waker.will_wake(cx.waker()) {
+                        *waker = cx.waker().clone();
+                    }
+                }
+            }
+            None => {
+                let id = state.next_waiter;
+                state.next_waiter += 1;
+                state.waiters.push_back((id, cx.waker().clone()));
+                self.waiter = Some(id);
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<'s, 'a> Drop for SlotFuture<'s, 'a> {
+    fn drop(&mut self) {
+        let Some(id) = self.waiter else {
+            return;
+        };
+
+        // Dropping a waiting future gives up its place in the queue, which
+        // may let the next future borrow the slot.
+        let mut state = self.slot.lock();
+        if let Some(index) = state.waiters.iter().position(|(waiter, _)| *waiter == id) {
+            state.waiters.remove(index);
+            if index == 0 {
+                state.wake_first();
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    any::{Any, TypeId},
+    ops::{Deref, DerefMut},
+};
+
+use better_any::Tid;
+
+/// Adapter which turns any `'static` type into a resource, without deriving
+/// [Tid].
+///
+/// This allows storing primitives and types of other crates, and can be mixed
+/// with resources borrowing data in the same [Resources][crate::Resources]
+/// map. `Static<T>` dereferences to `T`.
+///
+/// ## Examples
+///
+/// ```rust
+/// use better_any::Tid;
+/// use stateman::{Resources, Static};
+///
+/// #[derive(Debug, Tid)]
+/// struct Name<'a>(&'a str);
+///
+/// let owned = String::from("stateman");
+///
+/// let mut resources = Resources::default();
+/// resources.insert(Static(1u32));
+/// resources.insert(Static(vec![String::from("a")]));
+/// resources.insert(Name(&owned));
+///
+/// **resources.borrow_mut::<Static<u32>>() += 1;
+///
+/// assert_eq!(2, **resources.borrow::<Static<u32>>());
+/// assert_eq!(1, resources.borrow::<Static<Vec<String>>>().len());
+/// assert_eq!("stateman", resources.borrow::<Name>().0);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Static<T>(pub T);
+
+impl<T> Static<T> {
+    /// Returns the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+// The id is the one of `Static<T>` rather than `T`, so it never collides with
+// the id of a `T` which derives `Tid` itself.
+impl<'a, T> Tid<'a> for Static<T>
+where
+    T: Any,
+{
+    fn self_id(&self) -> TypeId {
+        TypeId::of::<Self>()
+    }
+
+    fn id() -> TypeId
+    where
+        Self: Sized,
+    {
+        TypeId::of::<Self>()
+    }
+}
+
+impl<T> From<T> for Static<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Deref for Static<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Static<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use better_any::Tid;
+
+    use crate::Resources;
+
+    use super::Static;
+
+    #[test]
+    fn static_adapter_does_not_collide_with_tid_type() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        resources.insert(Static(A(2)));
+
+        assert_eq!(&A(1), &*resources.borrow::<A>());
+        assert_eq!(A(2), **resources.borrow::<Static<A>>());
+        assert_eq!(Some(Static(A(2))), resources.remove::<Static<A>>());
+        assert!(resources.contains::<A>());
+    }
+
+    #[test]
+    fn static_adapter_is_mixed_with_lifetime_types() {
+        let owned = A(3);
+
+        let mut resources = Resources::default();
+        resources.insert(Static(String::from("a")));
+        resources.insert(B(&owned));
+
+        resources.borrow_mut::<Static<String>>().push('b');
+
+        assert_eq!("ab", resources.borrow::<Static<String>>().as_str());
+        assert_eq!(&A(3), resources.borrow::<B>().0);
+    }
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct A(u32);
+
+    #[derive(Debug, Tid)]
+    struct B<'a>(&'a A);
+}
@@ -0,0 +1,274 @@
+use std::{any::TypeId, collections::hash_map, marker::PhantomData};
+
+use better_any::TidExt;
+
+#[cfg(feature = "track_borrows")]
+use crate::borrow_location::BorrowTracker;
+use crate::{slot::Slot, BorrowMode, Ref, RefMut, Resource, TypeNameLit};
+
+/// An entry to a resource of type `R` in a
+/// [SyncResources][crate::SyncResources] container, which is either occupied
+/// or vacant.
+///
+/// This is similar to the Entry API found in the standard library.
+///
+/// ## Examples
+///
+/// ```rust
+/// use better_any::Tid;
+/// use stateman::SyncResources;
+///
+/// #[derive(Debug, Default, Tid)]
+/// struct Res(i32);
+///
+/// let mut resources = SyncResources::default();
+///
+/// let value = resources.entry::<Res>().or_insert(Res(4));
+/// println!("{:?}", value.0 * 2);
+/// drop(value);
+///
+/// resources
+///     .entry::<Res>()
+///     .and_modify(|res| res.0 += 1)
+///     .or_default();
+/// assert_eq!(5, resources.borrow::<Res>().0);
+/// ```
+pub enum SyncEntry<'a, 'b, R> {
+    /// The resource exists.
+    Occupied(SyncOccupiedEntry<'a, 'b, R>),
+    /// The resource doesn't exist.
+    Vacant(SyncVacantEntry<'a, 'b, R>),
+}
+
+/// An entry to an existing resource, see [SyncEntry].
+pub struct SyncOccupiedEntry<'a, 'b, R> {
+    inner: hash_map::OccupiedEntry<'a, TypeId, Slot<'b>>,
+    #[cfg(feature = "track_borrows")]
+    borrows: &'a BorrowTracker,
+    marker: PhantomData<R>,
+}
+
+/// An entry to a missing resource, see [SyncEntry].
+pub struct SyncVacantEntry<'a, 'b, R> {
+    inner: hash_map::VacantEntry<'a, TypeId, Slot<'b>>,
+    #[cfg(feature = "track_borrows")]
+    borrows: &'a BorrowTracker,
+    marker: PhantomData<R>,
+}
+
+/// Borrows `slot` of `R` mutably, which is poisoned on panic, and records the
+/// borrow.
+#[cfg_attr(feature = "track_borrows", track_caller)]
+fn borrow_mut<'a, 'b, R>(
+    slot: &'a Slot<'b>,
+    #[cfg(feature = "track_borrows")] borrows: &'a BorrowTracker,
+) -> RefMut<'a, 'b, R>
+where
+    R: Resource<'b> + Sync,
+{
+    // The entry borrows the map mutably, so the slot can't be borrowed.
+    let borrow = slot
+        .try_borrow(BorrowMode::Exclusive)
+        .unwrap_or_else(|_| unreachable!("Expected slot of entry to be unborrowed."));
+    let ref_mut = RefMut::filter_map(RefMut::from_slot(borrow), |resource| {
+        resource.downcast_mut::<R>()
+    })
+    .unwrap_or_else(|_| unreachable!("Expected value of entry to be an `R`."));
+
+    #[cfg(feature = "track_borrows")]
+    let ref_mut = ref_mut.with_record(borrows.record((R::id(), None), BorrowMode::Exclusive));
+
+    ref_mut
+}
+
+/// Returns the value of `slot`, which was checked to be an `R` by
+/// [`SyncResources::try_entry`][crate::SyncResources::try_entry].
+fn into_value<'b, R>(slot: Slot<'b>) -> R
+where
+    R: Resource<'b> + Sync,
+{
+    let resource: Box<R> = slot
+        .into_inner()
+        .downcast_box()
+        .unwrap_or_else(|_| unreachable!("Expected value of entry to be an `R`."));
+
+    *resource
+}
+
+impl<'a, 'b: 'a, R> SyncEntry<'a, 'b, R>
+where
+    R: Resource<'b> + Sync,
+{
+    pub(crate) fn new(
+        inner: hash_map::Entry<'a, TypeId, Slot<'b>>,
+        #[cfg(feature = "track_borrows")] borrows: &'a BorrowTracker,
+    ) -> Self {
+        match inner {
+            hash_map::Entry::Occupied(inner) => Self::Occupied(SyncOccupiedEntry {
+                inner,
+                #[cfg(feature = "track_borrows")]
+                borrows,
+                marker: PhantomData,
+            }),
+            hash_map::Entry::Vacant(inner) => Self::Vacant(SyncVacantEntry {
+                inner,
+                #[cfg(feature = "track_borrows")]
+                borrows,
+                marker: PhantomData,
+            }),
+        }
+    }
+
+    /// Returns the type name of the resource of this entry.
+    pub fn key(&self) -> TypeNameLit {
+        TypeNameLit::of::<R>()
+    }
+
+    /// Returns this entry's value, inserts and returns `v` otherwise.
+    ///
+    /// Please note that you should use `or_insert_with` in case the creation of
+    /// the value is expensive.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn or_insert(self, v: R) -> RefMut<'a, 'b, R> {
+        self.or_insert_with(move || v)
+    }
+
+    /// Returns this entry's value, inserts and returns the return value of `f`
+    /// otherwise.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn or_insert_with<F>(self, f: F) -> RefMut<'a, 'b, R>
+    where
+        F: FnOnce() -> R,
+    {
+        self.or_insert_with_key(|_| f())
+    }
+
+    /// Returns this entry's value, inserts and returns the return value of `f`
+    /// otherwise, which is called with the type name of the resource.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn or_insert_with_key<F>(self, f: F) -> RefMut<'a, 'b, R>
+    where
+        F: FnOnce(TypeNameLit) -> R,
+    {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => {
+                let value = f(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// Returns this entry's value, inserts and returns the default value
+    /// otherwise.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn or_default(self) -> RefMut<'a, 'b, R>
+    where
+        R: Default,
+    {
+        self.or_insert_with(R::default)
+    }
+
+    /// Calls `f` with the value if the entry is occupied.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut R),
+    {
+        if let Self::Occupied(entry) = &mut self {
+            f(&mut entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, 'b: 'a, R> SyncOccupiedEntry<'a, 'b, R>
+where
+    R: Resource<'b> + Sync,
+{
+    /// Returns the type name of the resource of this entry.
+    pub fn key(&self) -> TypeNameLit {
+        TypeNameLit::of::<R>()
+    }
+
+    /// Returns a reference to the value.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn get(&self) -> Ref<'_, 'b, R> {
+        let borrow = self
+            .inner
+            .get()
+            .try_borrow(BorrowMode::Shared)
+            .unwrap_or_else(|_| unreachable!("Expected slot of entry to be unborrowed."));
+        let r#ref = Ref::filter_map(Ref::from_slot(borrow), |resource| {
+            resource.downcast_ref::<R>()
+        })
+        .unwrap_or_else(|_| unreachable!("Expected value of entry to be an `R`."));
+
+        #[cfg(feature = "track_borrows")]
+        let r#ref = r#ref.with_record(self.borrows.record((R::id(), None), BorrowMode::Shared));
+
+        r#ref
+    }
+
+    /// Returns a mutable reference to the value like
+    /// [`SyncResources::borrow_mut`][crate::SyncResources::borrow_mut].
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn get_mut(&mut self) -> RefMut<'_, 'b, R> {
+        borrow_mut(
+            self.inner.get(),
+            #[cfg(feature = "track_borrows")]
+            self.borrows,
+        )
+    }
+
+    /// Converts the entry into a reference to the value, see [`get_mut`].
+    ///
+    /// [`get_mut`]: Self::get_mut
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn into_mut(self) -> RefMut<'a, 'b, R> {
+        borrow_mut(
+            self.inner.into_mut(),
+            #[cfg(feature = "track_borrows")]
+            self.borrows,
+        )
+    }
+
+    /// Replaces the value and returns the old one.
+    pub fn insert(&mut self, value: R) -> R {
+        into_value(std::mem::replace(
+            self.inner.get_mut(),
+            Slot::new(Box::new(value)),
+        ))
+    }
+
+    /// Removes the value from the map and returns it.
+    pub fn remove(self) -> R {
+        self.remove_entry().1
+    }
+
+    /// Removes the value from the map and returns it together with the type
+    /// name of the resource.
+    pub fn remove_entry(self) -> (TypeNameLit, R) {
+        (TypeNameLit::of::<R>(), into_value(self.inner.remove()))
+    }
+}
+
+impl<'a, 'b: 'a, R> SyncVacantEntry<'a, 'b, R>
+where
+    R: Resource<'b> + Sync,
+{
+    /// Returns the type name of the resource of this entry.
+    pub fn key(&self) -> TypeNameLit {
+        TypeNameLit::of::<R>()
+    }
+
+    /// Inserts the value and returns a reference to it.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn insert(self, value: R) -> RefMut<'a, 'b, R> {
+        borrow_mut(
+            self.inner.insert(Slot::new(Box::new(value))),
+            #[cfg(feature = "track_borrows")]
+            self.borrows,
+        )
+    }
+}
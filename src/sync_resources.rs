@@ -0,0 +1,1219 @@
+#[cfg(feature = "track_borrows")]
+use std::panic::Location;
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use better_any::TidExt;
+
+#[cfg(feature = "track_borrows")]
+use crate::borrow_location::BorrowTracker;
+use crate::{
+    poison::PoisonResult,
+    resource::{self, DebugFn, DebugResource},
+    slot::{Slot, SlotBorrow},
+    wait_graph::WaitGraph,
+    BorrowError, BorrowMode, Ref, RefMut, Resource, SyncEntry, TypeNameLit,
+};
+
+/// A thread safe set of types (resources), or map from `TypeId` to type.
+#[derive(Default)]
+pub struct SyncResources<'a> {
+    slots: HashMap<TypeId, Slot<'a>>,
+    waits: Option<WaitGraph>,
+    debugs: HashMap<TypeId, DebugFn<'a>>,
+    #[cfg(feature = "track_borrows")]
+    borrows: BorrowTracker,
+}
+
+/// A [Resource] container like [Resources][crate::Resources], which is `Send +
+/// Sync` and can thus be shared between threads.
+///
+/// Only resources which are `Sync` can be stored. Besides the `try_*` methods,
+/// which fail immediately on conflicting borrows, there are `*_blocking`
+/// methods which wait until conflicting borrows are released, and `*_timeout`
+/// methods which give up after a while.
+///
+/// Waiting threads may deadlock if they hold resources other threads are
+/// waiting for, which can be reported as errors with
+/// [`set_deadlock_detection`].
+///
+/// ## Examples
+///
+/// ```rust
+/// use better_any::Tid;
+/// use stateman::SyncResources;
+///
+/// #[derive(Debug, Tid)]
+/// struct Counter(u32);
+///
+/// let mut resources = SyncResources::default();
+/// resources.insert(Counter(0));
+///
+/// std::thread::scope(|scope| {
+///     for _ in 0..4 {
+///         scope.spawn(|| resources.borrow_mut_blocking::<Counter>().unwrap().0 += 1);
+///     }
+/// });
+///
+/// assert_eq!(4, resources.borrow::<Counter>().0);
+/// ```
+///
+/// [`set_deadlock_detection`]: Self::set_deadlock_detection
+impl<'a> SyncResources<'a> {
+    /// Creates an empty `SyncResources` map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty `SyncResources` map with the specified capacity.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: HashMap::with_capacity(capacity),
+            waits: None,
+            debugs: HashMap::new(),
+            #[cfg(feature = "track_borrows")]
+            borrows: BorrowTracker::default(),
+        }
+    }
+
+    /// Returns the number of elements the map can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /// Enables or disables deadlock detection for the `*_blocking` and
+    /// `*_timeout` methods.
+    ///
+    /// With deadlock detection, a thread which would wait for a resource held
+    /// by a thread which is itself (directly or indirectly) waiting for a
+    /// resource held by the first thread gets a [`BorrowError::Deadlock`]
+    /// instead. Borrows are attributed to the thread which took them, even if
+    /// the reference is sent to another thread afterwards.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::{BorrowError, SyncResources};
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Counter(u32);
+    ///
+    /// let mut resources = SyncResources::default();
+    /// resources.insert(Counter(0));
+    /// resources.set_deadlock_detection(true);
+    ///
+    /// let _counter = resources.borrow::<Counter>();
+    /// assert!(matches!(
+    ///     resources.borrow_mut_blocking::<Counter>(),
+    ///     Err(BorrowError::Deadlock { .. })
+    /// ));
+    /// ```
+    pub fn set_deadlock_detection(&mut self, enabled: bool) -> &mut Self {
+        self.waits = enabled.then(WaitGraph::default);
+        self
+    }
+
+    /// Returns true if deadlock detection is enabled.
+    pub fn deadlock_detection(&self) -> bool {
+        self.waits.is_some()
+    }
+
+    /// Returns an entry for the resource with type `R`.
+    ///
+    /// See [`try_entry`] for a non-panicking version of this function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is poisoned, see [`is_poisoned`].
+    ///
+    /// [`is_poisoned`]: Self::is_poisoned
+    /// [`try_entry`]: Self::try_entry
+    pub fn entry<'b, R>(&'b mut self) -> SyncEntry<'b, 'a, R>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.try_entry::<R>().unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Returns an entry for the resource with type `R`, or an error if `R`
+    /// is poisoned or the value stored for it has a different type.
+    pub fn try_entry<'b, R>(&'b mut self) -> Result<SyncEntry<'b, 'a, R>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        if let Some(slot) = self.slots.get_mut(&R::id()) {
+            if slot.poison().is_poisoned() {
+                return Err(BorrowError::Poisoned {
+                    type_name: TypeNameLit::of::<R>(),
+                    label: None,
+                    mode: BorrowMode::Exclusive,
+                });
+            }
+            if slot.get_mut().downcast_mut::<R>().is_none() {
+                return Err(Self::type_mismatch::<R>(BorrowMode::Exclusive));
+            }
+        }
+
+        Ok(SyncEntry::new(
+            self.slots.entry(R::id()),
+            #[cfg(feature = "track_borrows")]
+            &self.borrows,
+        ))
+    }
+
+    /// Inserts a resource into the map and returns the previous value of `R`.
+    /// If the resource existed before, it will be overwritten.
+    ///
+    /// See [`try_insert`] for a non-panicking version of this function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the previous value has a different type, see
+    /// [`BorrowError::TypeMismatch`].
+    ///
+    /// [`try_insert`]: Self::try_insert
+    pub fn insert<R>(&mut self, r: R) -> Option<R>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.try_insert(r).unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Inserts a resource into the map like [`insert`], and returns the
+    /// previous value of `R`.
+    ///
+    /// Returns [`BorrowError::TypeMismatch`] and keeps the previous value if
+    /// it has a different type, so it can still be removed with
+    /// [`insert_raw`].
+    ///
+    /// [`insert`]: Self::insert
+    /// [`insert_raw`]: Self::insert_raw
+    pub fn try_insert<R>(&mut self, r: R) -> Result<Option<R>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        if let Some(slot) = self.slots.get_mut(&R::id()) {
+            if slot.get_mut().downcast_mut::<R>().is_none() {
+                return Err(Self::type_mismatch::<R>(BorrowMode::Exclusive));
+            }
+        }
+
+        let old = self.insert_raw(R::id(), Box::new(r)).map(|old| {
+            old.downcast_box()
+                .unwrap_or_else(|_| unreachable!("Expected previous value to be an `R`."))
+        });
+
+        Ok(old.map(|old: Box<R>| *old))
+    }
+
+    /// Inserts an already boxed resource into the map and returns the
+    /// previous one.
+    pub fn insert_raw(
+        &mut self,
+        type_id: TypeId,
+        resource: Box<dyn Resource<'a> + Sync>,
+    ) -> Option<Box<dyn Resource<'a> + Sync>> {
+        self.slots
+            .insert(type_id, Slot::new(resource))
+            .map(Slot::into_inner)
+    }
+
+    /// Removes a resource of type `R` from this container and returns its
+    /// ownership to the caller. In case there is no such resource in this,
+    /// container, `None` will be returned.
+    ///
+    /// If the value stored for `R` has a different type, it is kept and
+    /// `None` is returned.
+    pub fn remove<R>(&mut self) -> Option<R>
+    where
+        R: Resource<'a> + Sync,
+    {
+        if (**self.slots.get_mut(&R::id())?.get_mut()).self_id() != R::id() {
+            return None;
+        }
+
+        self.slots
+            .remove(&R::id())
+            .map(Slot::into_inner)
+            .and_then(|resource| resource.downcast_box().ok())
+            .map(|resource: Box<R>| *resource)
+    }
+
+    /// Returns true if the specified resource type `R` exists in `self`.
+    pub fn contains<R>(&self) -> bool
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.slots.contains_key(&R::id())
+    }
+
+    /// Returns the `R` resource in the resource map.
+    ///
+    /// See [`try_borrow`] for a non-panicking version of this function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist.
+    /// Panics if the resource is being accessed mutably.
+    ///
+    /// [`try_borrow`]: Self::try_borrow
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow<R>(&self) -> Ref<'_, 'a, R>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.try_borrow::<R>().unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Returns an immutable reference to `R` if it exists and is not borrowed
+    /// mutably.
+    ///
+    /// Returns [`BorrowError::Poisoned`] if a panic occurred while the
+    /// resource was borrowed mutably, see [`try_borrow_poisoned`].
+    ///
+    /// [`try_borrow_poisoned`]: Self::try_borrow_poisoned
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow<R>(&self) -> Result<Ref<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.try_borrow_poisoned::<R>()?.map_err(BorrowError::from)
+    }
+
+    /// Returns an immutable reference to `R` like [`try_borrow`], which is
+    /// wrapped in a [PoisonError][crate::PoisonError] if the resource is
+    /// poisoned.
+    ///
+    /// [`try_borrow`]: Self::try_borrow
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_poisoned<R>(&self) -> Result<PoisonResult<Ref<'_, 'a, R>>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        let borrow = self.try_borrow_slot::<R>(BorrowMode::Shared)?;
+        let poison = borrow.poison();
+        let r#ref = Self::downcast_ref::<R>(borrow)?;
+
+        #[cfg(feature = "track_borrows")]
+        let r#ref = r#ref.with_record(self.borrows.record((R::id(), None), BorrowMode::Shared));
+
+        Ok(poison.check(r#ref, TypeNameLit::of::<R>(), None, BorrowMode::Shared))
+    }
+
+    /// Returns an immutable reference to `R`, waiting until it is no longer
+    /// borrowed mutably.
+    ///
+    /// Returns an error if the resource doesn't exist or waiting would
+    /// deadlock.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_blocking<R>(&self) -> Result<Ref<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.borrow_until::<R>(None)
+    }
+
+    /// Returns an immutable reference to `R`, waiting at most `timeout` until
+    /// it is no longer borrowed mutably.
+    ///
+    /// Returns an error if the resource doesn't exist, waiting would deadlock
+    /// or the resource is still borrowed after `timeout`.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_timeout<R>(&self, timeout: Duration) -> Result<Ref<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.borrow_until::<R>(Instant::now().checked_add(timeout))
+    }
+
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn borrow_until<R>(&self, deadline: Option<Instant>) -> Result<Ref<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        let borrow = self.borrow_slot_until::<R>(BorrowMode::Shared, deadline)?;
+        let poison = borrow.poison();
+        let r#ref = Self::downcast_ref::<R>(borrow)?;
+
+        #[cfg(feature = "track_borrows")]
+        let r#ref = r#ref.with_record(self.borrows.record((R::id(), None), BorrowMode::Shared));
+
+        poison
+            .check(r#ref, TypeNameLit::of::<R>(), None, BorrowMode::Shared)
+            .map_err(BorrowError::from)
+    }
+
+    /// Returns a mutable reference to `R` in the resource map.
+    ///
+    /// See [`try_borrow_mut`] for a non-panicking version of this function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist.
+    /// Panics if the resource is already accessed.
+    ///
+    /// [`try_borrow_mut`]: Self::try_borrow_mut
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_mut<R>(&self) -> RefMut<'_, 'a, R>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.try_borrow_mut::<R>()
+            .unwrap_or_else(Self::borrow_panic)
+    }
+
+    /// Returns a mutable reference to `R` if it exists and is not borrowed.
+    ///
+    /// Returns [`BorrowError::Poisoned`] if a panic occurred while the
+    /// resource was borrowed mutably, see [`try_borrow_mut_poisoned`].
+    ///
+    /// [`try_borrow_mut_poisoned`]: Self::try_borrow_mut_poisoned
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_mut<R>(&self) -> Result<RefMut<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.try_borrow_mut_poisoned::<R>()?
+            .map_err(BorrowError::from)
+    }
+
+    /// Returns a mutable reference to `R` like [`try_borrow_mut`], which is
+    /// wrapped in a [PoisonError][crate::PoisonError] if the resource is
+    /// poisoned.
+    ///
+    /// The resource stays poisoned until [`clear_poison`] is called.
+    ///
+    /// [`try_borrow_mut`]: Self::try_borrow_mut
+    /// [`clear_poison`]: Self::clear_poison
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_mut_poisoned<R>(&self) -> Result<PoisonResult<RefMut<'_, 'a, R>>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        let borrow = self.try_borrow_slot::<R>(BorrowMode::Exclusive)?;
+        let poison = borrow.poison();
+        let ref_mut = Self::downcast_mut::<R>(borrow)?;
+
+        #[cfg(feature = "track_borrows")]
+        let ref_mut =
+            ref_mut.with_record(self.borrows.record((R::id(), None), BorrowMode::Exclusive));
+
+        Ok(poison.check(ref_mut, TypeNameLit::of::<R>(), None, BorrowMode::Exclusive))
+    }
+
+    /// Returns a mutable reference to `R`, waiting until it is no longer
+    /// borrowed.
+    ///
+    /// Returns an error if the resource doesn't exist or waiting would
+    /// deadlock.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_mut_blocking<R>(&self) -> Result<RefMut<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.borrow_mut_until::<R>(None)
+    }
+
+    /// Returns a mutable reference to `R`, waiting at most `timeout` until it
+    /// is no longer borrowed.
+    ///
+    /// Returns an error if the resource doesn't exist, waiting would deadlock
+    /// or the resource is still borrowed after `timeout`.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use better_any::Tid;
+    /// use stateman::{BorrowError, SyncResources};
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Counter(u32);
+    ///
+    /// let mut resources = SyncResources::default();
+    /// resources.insert(Counter(0));
+    ///
+    /// let counter = resources.borrow::<Counter>();
+    /// std::thread::scope(|scope| {
+    ///     scope.spawn(|| {
+    ///         assert!(matches!(
+    ///             resources.borrow_mut_timeout::<Counter>(Duration::from_millis(10)),
+    ///             Err(BorrowError::ConflictShared { .. })
+    ///         ));
+    ///     });
+    /// });
+    /// drop(counter);
+    /// ```
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_mut_timeout<R>(&self, timeout: Duration) -> Result<RefMut<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.borrow_mut_until::<R>(Instant::now().checked_add(timeout))
+    }
+
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn borrow_mut_until<R>(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<RefMut<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a> + Sync,
+    {
+        let borrow = self.borrow_slot_until::<R>(BorrowMode::Exclusive, deadline)?;
+        let poison = borrow.poison();
+        let ref_mut = Self::downcast_mut::<R>(borrow)?;
+
+        #[cfg(feature = "track_borrows")]
+        let ref_mut =
+            ref_mut.with_record(self.borrows.record((R::id(), None), BorrowMode::Exclusive));
+
+        poison
+            .check(ref_mut, TypeNameLit::of::<R>(), None, BorrowMode::Exclusive)
+            .map_err(BorrowError::from)
+    }
+
+    /// Returns a future which resolves to an immutable reference to `R` once
+    /// it is no longer borrowed mutably.
+    ///
+    /// The future is woken when the conflicting borrow is released, and works
+    /// with any executor. Futures borrow a resource in the order in which
+    /// they were first polled, while the `try_*`, `*_blocking` and
+    /// `*_timeout` methods don't wait in line. Dropping a pending future
+    /// gives up its place.
+    ///
+    /// Resolves to an error if the resource doesn't exist.
+    // Not an `async fn`, so the caller's location is known before polling.
+    #[allow(clippy::manual_async_fn)]
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_async<R>(&self) -> impl Future<Output = Result<Ref<'_, 'a, R>, BorrowError>>
+    where
+        R: Resource<'a> + Sync,
+    {
+        #[cfg(feature = "track_borrows")]
+        let location = Location::caller();
+
+        async move {
+            let borrow = self
+                .slot::<R>(BorrowMode::Shared)?
+                .borrow_async(BorrowMode::Shared)
+                .await;
+            let poison = borrow.poison();
+            let r#ref = Self::downcast_ref::<R>(borrow)?;
+
+            #[cfg(feature = "track_borrows")]
+            let r#ref = r#ref.with_record(self.borrows.record_at(
+                (R::id(), None),
+                BorrowMode::Shared,
+                location,
+            ));
+
+            poison
+                .check(r#ref, TypeNameLit::of::<R>(), None, BorrowMode::Shared)
+                .map_err(BorrowError::from)
+        }
+    }
+
+    /// Returns a future which resolves to a mutable reference to `R` once it
+    /// is no longer borrowed, see [`borrow_async`].
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::SyncResources;
+    ///
+    /// #[derive(Debug, Tid)]
+    /// struct Counter(u32);
+    ///
+    /// async fn increment(resources: &SyncResources<'_>) {
+    ///     resources.borrow_mut_async::<Counter>().await.unwrap().0 += 1;
+    /// }
+    /// ```
+    ///
+    /// [`borrow_async`]: Self::borrow_async
+    // Not an `async fn`, so the caller's location is known before polling.
+    #[allow(clippy::manual_async_fn)]
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_mut_async<R>(
+        &self,
+    ) -> impl Future<Output = Result<RefMut<'_, 'a, R>, BorrowError>>
+    where
+        R: Resource<'a> + Sync,
+    {
+        #[cfg(feature = "track_borrows")]
+        let location = Location::caller();
+
+        async move {
+            let borrow = self
+                .slot::<R>(BorrowMode::Exclusive)?
+                .borrow_async(BorrowMode::Exclusive)
+                .await;
+            let poison = borrow.poison();
+            let ref_mut = Self::downcast_mut::<R>(borrow)?;
+
+            #[cfg(feature = "track_borrows")]
+            let ref_mut = ref_mut.with_record(self.borrows.record_at(
+                (R::id(), None),
+                BorrowMode::Exclusive,
+                location,
+            ));
+
+            poison
+                .check(ref_mut, TypeNameLit::of::<R>(), None, BorrowMode::Exclusive)
+                .map_err(BorrowError::from)
+        }
+    }
+
+    /// Returns true if a panic occurred while `R` was borrowed mutably.
+    ///
+    /// Inserting a new value clears the poison.
+    pub fn is_poisoned<R>(&self) -> bool
+    where
+        R: Resource<'a> + Sync,
+    {
+        matches!(self.slots.get(&R::id()), Some(slot) if slot.poison().is_poisoned())
+    }
+
+    /// Clears the poison of `R`, so it can be borrowed normally again.
+    pub fn clear_poison<R>(&self)
+    where
+        R: Resource<'a> + Sync,
+    {
+        if let Some(slot) = self.slots.get(&R::id()) {
+            slot.poison().clear();
+        }
+    }
+
+    /// Registers `R` to be printed with its [`Debug`] implementation in the
+    /// `Debug` output of `self`, see
+    /// [`Resources::register_debug`][crate::Resources::register_debug].
+    ///
+    /// [`Debug`]: fmt::Debug
+    pub fn register_debug<R>(&mut self)
+    where
+        R: Resource<'a> + Sync + fmt::Debug,
+    {
+        self.debugs.insert(R::id(), resource::debug_resource::<R>);
+    }
+
+    /// Retrieves a resource without fetching, which is cheaper, but only
+    /// available with `&mut self`.
+    pub fn get_mut<R>(&mut self) -> Option<&mut R>
+    where
+        R: Resource<'a> + Sync,
+    {
+        self.slots
+            .get_mut(&R::id())
+            .and_then(|slot| slot.get_mut().downcast_mut())
+    }
+
+    fn slot<R>(&self, mode: BorrowMode) -> Result<&Slot<'a>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        self.slots
+            .get(&R::id())
+            .ok_or_else(|| BorrowError::NotFound {
+                type_name: TypeNameLit::of::<R>(),
+                label: None,
+                mode,
+            })
+    }
+
+    /// Borrows the slot of `R`, waiting until `deadline` if it is borrowed.
+    fn borrow_slot_until<R>(
+        &self,
+        mode: BorrowMode,
+        deadline: Option<Instant>,
+    ) -> Result<SlotBorrow<'_, 'a>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        let slot = self.slot::<R>(mode)?;
+        if let Ok(borrow) = slot.try_borrow(mode) {
+            return Ok(borrow);
+        }
+
+        let _waiting =
+            match &self.waits {
+                Some(waits) => Some(waits.wait(R::id(), &self.slots).ok_or(
+                    BorrowError::Deadlock {
+                        type_name: TypeNameLit::of::<R>(),
+                        label: None,
+                        mode,
+                    },
+                )?),
+                None => None,
+            };
+        slot.borrow_until(mode, deadline)
+            .map_err(|held_mode| self.conflict::<R>(mode, held_mode))
+    }
+
+    fn try_borrow_slot<R>(&self, mode: BorrowMode) -> Result<SlotBorrow<'_, 'a>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        self.slot::<R>(mode)?
+            .try_borrow(mode)
+            .map_err(|held_mode| self.conflict::<R>(mode, held_mode))
+    }
+
+    /// Downcasts the value of `borrow` to `R`, or returns an error if it has
+    /// another type.
+    fn downcast_ref<'b, R>(borrow: SlotBorrow<'b, 'a>) -> Result<Ref<'b, 'a, R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        Ref::filter_map(Ref::from_slot(borrow), |resource| {
+            resource.downcast_ref::<R>()
+        })
+        .map_err(|_| Self::type_mismatch::<R>(BorrowMode::Shared))
+    }
+
+    /// Downcasts the value of `borrow` to `R` mutably, or returns an error if
+    /// it has another type.
+    fn downcast_mut<'b, R>(borrow: SlotBorrow<'b, 'a>) -> Result<RefMut<'b, 'a, R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        RefMut::filter_map(RefMut::from_slot(borrow), |resource| {
+            resource.downcast_mut::<R>()
+        })
+        .map_err(|_| Self::type_mismatch::<R>(BorrowMode::Exclusive))
+    }
+
+    fn type_mismatch<R>(mode: BorrowMode) -> BorrowError
+    where
+        R: Resource<'a>,
+    {
+        BorrowError::TypeMismatch {
+            type_name: TypeNameLit::of::<R>(),
+            label: None,
+            mode,
+        }
+    }
+
+    fn conflict<R>(&self, mode: BorrowMode, held_mode: BorrowMode) -> BorrowError
+    where
+        R: Resource<'a>,
+    {
+        #[cfg(feature = "track_borrows")]
+        let held_at = self.borrows.held_at(&(R::id(), None), held_mode);
+        #[cfg(not(feature = "track_borrows"))]
+        let held_at = None;
+
+        BorrowError::conflict(TypeNameLit::of::<R>(), None, mode, held_mode, held_at)
+    }
+
+    fn borrow_panic<Ret>(borrow_error: BorrowError) -> Ret {
+        panic!("{borrow_error}")
+    }
+}
+
+impl<'a> fmt::Debug for SyncResources<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug_map = f.debug_map();
+
+        self.slots.iter().for_each(|(type_id, slot)| {
+            match slot.try_borrow(BorrowMode::Shared) {
+                Ok(borrow) => {
+                    let resource = borrow.value();
+                    debug_map.entry(
+                        &slot.type_name(),
+                        &DebugResource {
+                            resource,
+                            debug_fn: self.debugs.get(type_id).copied(),
+                        },
+                    )
+                }
+                Err(_) => debug_map.entry(&slot.type_name(), &".."),
+            };
+        });
+
+        debug_map.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        panic::{self, AssertUnwindSafe},
+        pin::Pin,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Barrier,
+        },
+        task::{Context, Poll, Wake, Waker},
+        thread::{self, Thread},
+        time::Duration,
+    };
+
+    use better_any::{Tid, TidExt};
+
+    use crate::{BorrowError, BorrowMode, Resource, SyncEntry, TypeNameLit};
+
+    use super::SyncResources;
+
+    #[test]
+    fn sync_resources_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<SyncResources>();
+    }
+
+    #[test]
+    fn insert_borrow_remove() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+
+        assert!(resources.contains::<Res>());
+        assert_eq!(Res(1), *resources.borrow::<Res>());
+
+        resources.borrow_mut::<Res>().0 = 2;
+
+        assert_eq!(Some(Res(2)), resources.remove::<Res>());
+        assert!(!resources.contains::<Res>());
+    }
+
+    #[test]
+    fn ref_mut_dropped_during_panic_poisons_resource() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _res = resources.borrow_mut::<Res>();
+            panic!("failed");
+        }));
+        assert!(result.is_err());
+
+        assert!(resources.is_poisoned::<Res>());
+        assert_eq!(
+            Err(BorrowError::Poisoned {
+                type_name: TypeNameLit::of::<Res>(),
+                label: None,
+                mode: BorrowMode::Shared,
+            }),
+            resources.borrow_blocking::<Res>().map(|_| ())
+        );
+        assert_eq!(
+            Res(1),
+            *resources
+                .try_borrow_poisoned::<Res>()
+                .unwrap()
+                .unwrap_err()
+                .into_inner()
+        );
+
+        resources.clear_poison::<Res>();
+        assert!(resources.try_borrow_mut::<Res>().is_ok());
+    }
+
+    #[test]
+    fn entry_or_insert_inserts_value() {
+        let mut resources = SyncResources::default();
+        *resources.entry::<Res>().or_insert(Res(1)) = Res(2);
+
+        assert_eq!(Res(2), *resources.borrow::<Res>());
+    }
+
+    #[test]
+    fn occupied_entry_replaces_and_removes_value() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+
+        let SyncEntry::Occupied(mut entry) = resources.entry::<Res>() else {
+            panic!("Expected entry of Res to be occupied.");
+        };
+        assert_eq!(Res(1), *entry.get());
+        assert_eq!(Res(1), entry.insert(Res(2)));
+        assert_eq!(Res(2), entry.remove());
+
+        assert!(!resources.contains::<Res>());
+        let res = resources
+            .entry::<Res>()
+            .and_modify(|res| res.0 = 3)
+            .or_insert_with(|| Res(4));
+        assert_eq!(Res(4), *res);
+    }
+
+    #[test]
+    fn entry_of_poisoned_resource_is_reported() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            resources.entry::<Res>().and_modify(|_| panic!("failed"));
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(
+            Err(BorrowError::Poisoned {
+                type_name: TypeNameLit::of::<Res>(),
+                label: None,
+                mode: BorrowMode::Exclusive,
+            }),
+            resources.try_entry::<Res>().map(|_| ())
+        );
+    }
+
+    #[test]
+    fn mismatched_insert_raw_returns_type_mismatch() {
+        let mut resources = SyncResources::default();
+        resources.insert_raw(Res::id(), Box::new(Other(1)));
+
+        let type_mismatch = |mode| BorrowError::TypeMismatch {
+            type_name: TypeNameLit::of::<Res>(),
+            label: None,
+            mode,
+        };
+        assert_eq!(
+            Some(type_mismatch(BorrowMode::Shared)),
+            resources.try_borrow::<Res>().err()
+        );
+        assert_eq!(
+            Some(type_mismatch(BorrowMode::Exclusive)),
+            resources.borrow_mut_blocking::<Res>().err()
+        );
+        assert_eq!(
+            Err(type_mismatch(BorrowMode::Exclusive)),
+            resources.try_insert(Res(2))
+        );
+        assert_eq!(None, resources.remove::<Res>());
+
+        assert!(resources.contains::<Res>());
+        let old = resources.insert_raw(Res::id(), Box::new(Res(3))).unwrap();
+        assert_eq!(Some(&Other(1)), old.downcast_ref::<Other>());
+        assert_eq!(Some(Res(3)), resources.insert(Res(4)));
+    }
+
+    #[test]
+    fn try_borrow_returns_err_on_conflict() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+
+        let _res = resources.borrow::<Res>();
+
+        assert!(resources.try_borrow::<Res>().is_ok());
+        assert!(matches!(
+            resources.try_borrow_mut::<Res>(),
+            Err(BorrowError::ConflictShared {
+                type_name,
+                label: None,
+                mode: BorrowMode::Exclusive,
+                ..
+            }) if type_name == TypeNameLit::of::<Res>()
+        ));
+    }
+
+    #[test]
+    fn borrow_blocking_returns_err_if_missing() {
+        let resources = SyncResources::default();
+
+        assert!(matches!(
+            resources.borrow_blocking::<Res>(),
+            Err(BorrowError::NotFound { .. })
+        ));
+    }
+
+    /// Waits until another thread is blocked on `R`, which is recorded with
+    /// deadlock detection.
+    fn wait_until_blocked<R>(resources: &SyncResources)
+    where
+        R: Resource<'static>,
+    {
+        let waits = resources
+            .waits
+            .as_ref()
+            .expect("Expected deadlock detection to be enabled.");
+        while !waits.is_waited_for(R::id()) {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn borrow_mut_blocking_waits_for_release() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+        resources.set_deadlock_detection(true);
+
+        thread::scope(|scope| {
+            let res = resources.borrow::<Res>();
+
+            scope.spawn(|| {
+                resources.borrow_mut_blocking::<Res>().unwrap().0 = 2;
+            });
+
+            wait_until_blocked::<Res>(&resources);
+            assert_eq!(Res(1), *res);
+        });
+
+        assert_eq!(Res(2), *resources.borrow::<Res>());
+    }
+
+    #[test]
+    fn borrow_mut_from_multiple_threads() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(0));
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        resources.borrow_mut_blocking::<Res>().unwrap().0 += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(Res(800), *resources.borrow::<Res>());
+    }
+
+    #[test]
+    fn borrow_mut_timeout_returns_conflict_after_timeout() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+
+        let _res = resources.borrow::<Res>();
+
+        assert!(matches!(
+            resources.borrow_mut_timeout::<Res>(Duration::from_millis(10)),
+            Err(BorrowError::ConflictShared {
+                mode: BorrowMode::Exclusive,
+                ..
+            })
+        ));
+        assert!(resources
+            .borrow_timeout::<Res>(Duration::from_millis(10))
+            .is_ok());
+    }
+
+    #[test]
+    fn borrow_timeout_waits_for_release() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+        resources.set_deadlock_detection(true);
+
+        thread::scope(|scope| {
+            let mut res = resources.borrow_mut::<Res>();
+
+            scope.spawn(|| {
+                let res = resources.borrow_timeout::<Res>(Duration::from_secs(10));
+                assert_eq!(Res(2), *res.unwrap());
+            });
+
+            wait_until_blocked::<Res>(&resources);
+            res.0 = 2;
+        });
+    }
+
+    #[test]
+    fn deadlock_is_reported_to_one_thread() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(0));
+        resources.insert(Other(0));
+        resources.set_deadlock_detection(true);
+        let barrier = Barrier::new(2);
+
+        let results = thread::scope(|scope| {
+            let first = scope.spawn(|| {
+                let _res = resources.borrow_mut::<Res>();
+                barrier.wait();
+                resources.borrow_mut_blocking::<Other>().map(|_| ())
+            });
+            let second = scope.spawn(|| {
+                let _other = resources.borrow_mut::<Other>();
+                barrier.wait();
+                resources.borrow_mut_blocking::<Res>().map(|_| ())
+            });
+            [first.join().unwrap(), second.join().unwrap()]
+        });
+
+        let deadlocks = results
+            .iter()
+            .filter(|result| matches!(result, Err(BorrowError::Deadlock { .. })))
+            .count();
+        assert_eq!(1, deadlocks);
+        assert!(results.iter().any(Result::is_ok));
+    }
+
+    #[test]
+    fn waiting_for_own_borrow_is_a_deadlock() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(0));
+        resources.set_deadlock_detection(true);
+
+        let _res = resources.borrow::<Res>();
+
+        assert_eq!(
+            Err(BorrowError::Deadlock {
+                type_name: TypeNameLit::of::<Res>(),
+                label: None,
+                mode: BorrowMode::Exclusive,
+            }),
+            resources.borrow_mut_blocking::<Res>().map(|_| ())
+        );
+    }
+
+    /// Waker which records that it was woken and unparks its thread.
+    struct Flag {
+        woken: AtomicBool,
+        thread: Thread,
+    }
+
+    impl Flag {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                woken: AtomicBool::new(false),
+                thread: thread::current(),
+            })
+        }
+
+        fn take(&self) -> bool {
+            self.woken.swap(false, Ordering::SeqCst)
+        }
+    }
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.woken.store(true, Ordering::SeqCst);
+            self.thread.unpark();
+        }
+    }
+
+    /// Minimal executor which runs `future` on the current thread.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let flag = Flag::new();
+        let waker = Waker::from(Arc::clone(&flag));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            while !flag.take() {
+                thread::park();
+            }
+        }
+    }
+
+    fn poll<F: Future>(future: &mut Pin<Box<F>>, flag: &Arc<Flag>) -> Poll<F::Output> {
+        let waker = Waker::from(Arc::clone(flag));
+        future.as_mut().poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn borrow_async_resolves_when_free() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+
+        block_on(async {
+            resources.borrow_mut_async::<Res>().await.unwrap().0 = 2;
+            assert_eq!(Res(2), *resources.borrow_async::<Res>().await.unwrap());
+            assert!(matches!(
+                resources.borrow_async::<Other>().await,
+                Err(BorrowError::NotFound { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn borrow_async_is_woken_when_guard_drops() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+        let flag = Flag::new();
+
+        let res = resources.borrow_mut::<Res>();
+        let mut future = Box::pin(resources.borrow_async::<Res>());
+        assert!(poll(&mut future, &flag).is_pending());
+
+        thread::scope(|scope| {
+            scope.spawn(move || drop(res));
+        });
+
+        assert!(flag.take());
+        assert!(matches!(
+            poll(&mut future, &flag),
+            Poll::Ready(Ok(res)) if *res == Res(1)
+        ));
+    }
+
+    #[test]
+    fn debug_shows_type_name_of_borrowed_resource() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+
+        let _res = resources.borrow_mut::<Res>();
+
+        let resources_dbg = format!("{resources:?}");
+        assert!(
+            resources_dbg.contains(r#"Res: "..""#),
+            r#"Expected `{}` to contain `Res: ".."`"#,
+            resources_dbg
+        );
+    }
+
+    #[test]
+    fn borrow_async_is_fifo() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+        let (first_flag, second_flag) = (Flag::new(), Flag::new());
+
+        let res = resources.borrow_mut::<Res>();
+        let mut first = Box::pin(resources.borrow_mut_async::<Res>());
+        let mut second = Box::pin(resources.borrow_async::<Res>());
+        assert!(poll(&mut first, &first_flag).is_pending());
+        assert!(poll(&mut second, &second_flag).is_pending());
+
+        drop(res);
+        assert!(first_flag.take());
+        assert!(!second_flag.take());
+        assert!(poll(&mut second, &second_flag).is_pending());
+
+        let Poll::Ready(Ok(first)) = poll(&mut first, &first_flag) else {
+            panic!("Expected first future to borrow the resource.");
+        };
+        assert!(poll(&mut second, &second_flag).is_pending());
+
+        drop(first);
+        assert!(second_flag.take());
+        assert!(matches!(
+            poll(&mut second, &second_flag),
+            Poll::Ready(Ok(_))
+        ));
+    }
+
+    #[test]
+    fn dropped_future_gives_up_its_place() {
+        let mut resources = SyncResources::default();
+        resources.insert(Res(1));
+        let flag = Flag::new();
+
+        let res = resources.borrow_mut::<Res>();
+        let mut first = Box::pin(resources.borrow_mut_async::<Res>());
+        let mut second = Box::pin(resources.borrow_mut_async::<Res>());
+        assert!(poll(&mut first, &flag).is_pending());
+        assert!(poll(&mut second, &flag).is_pending());
+
+        drop(res);
+        flag.take();
+        drop(first);
+
+        assert!(flag.take());
+        assert!(matches!(poll(&mut second, &flag), Poll::Ready(Ok(_))));
+    }
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct Res(usize);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct Other(usize);
+}
@@ -0,0 +1,39 @@
+use crate::SystemData;
+
+/// A system which runs on the resources of a [Resources][crate::Resources]
+/// map, see [Dispatcher][crate::Dispatcher].
+///
+/// The resources a system accesses are declared through its [SystemData],
+/// which is fetched before the system is run.
+///
+/// ## Examples
+///
+/// ```rust
+/// use better_any::Tid;
+/// use stateman::{Read, System, Write};
+///
+/// #[derive(Debug, Tid)]
+/// struct Gravity(f32);
+///
+/// #[derive(Debug, Tid)]
+/// struct Velocity(f32);
+///
+/// struct Physics;
+///
+/// impl<'a> System<'a> for Physics {
+///     type SystemData<'r> = (Read<'r, 'a, Gravity>, Write<'r, 'a, Velocity>) where 'a: 'r;
+///
+///     fn run(&mut self, (gravity, mut velocity): Self::SystemData<'_>) {
+///         velocity.0 += gravity.0;
+///     }
+/// }
+/// ```
+pub trait System<'a>: Send {
+    /// The resources accessed by this system.
+    type SystemData<'r>: SystemData<'r, 'a> + Send
+    where
+        'a: 'r;
+
+    /// Runs the system on the fetched resources.
+    fn run(&mut self, data: Self::SystemData<'_>);
+}
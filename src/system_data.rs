@@ -0,0 +1,348 @@
+use std::{
+    any::TypeId,
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{BorrowError, Ref, RefMut, Resource, Resources};
+
+/// A set of resources which can be fetched from [Resources] in one go.
+///
+/// This is implemented for [Read], [Write], `Option<Read>`, `Option<Write>`
+/// and tuples of up to 16 of those.
+///
+/// ## Examples
+///
+/// ```rust
+/// use better_any::Tid;
+/// use stateman::{Read, Resources, Write};
+///
+/// #[derive(Debug, Tid)]
+/// struct A(u32);
+///
+/// #[derive(Debug, Tid)]
+/// struct B(u32);
+///
+/// let mut resources = Resources::default();
+/// resources.insert(A(1));
+/// resources.insert(B(2));
+///
+/// let (a, mut b) = resources.fetch::<(Read<A>, Write<B>)>();
+/// b.0 += a.0;
+/// ```
+pub trait SystemData<'a, 'b>: Sized {
+    /// Borrows all resources of this set from `resources`.
+    ///
+    /// If one of the borrows fails, all borrows which were already taken are
+    /// released again.
+    fn try_fetch(resources: &'a Resources<'b>) -> Result<Self, BorrowError>;
+
+    /// Returns the ids of the resources which are borrowed immutably.
+    fn reads() -> Vec<TypeId>;
+
+    /// Returns the ids of the resources which are borrowed mutably.
+    fn writes() -> Vec<TypeId>;
+}
+
+/// Immutable access to a resource, fetched as part of [SystemData].
+pub struct Read<'a, 'b, R> {
+    inner: Ref<'a, 'b, R>,
+}
+
+impl<'a, 'b, R> Read<'a, 'b, R> {
+    /// Returns the underlying [Ref].
+    pub fn into_inner(self) -> Ref<'a, 'b, R> {
+        self.inner
+    }
+}
+
+impl<'a, 'b, R> Deref for Read<'a, 'b, R>
+where
+    R: Resource<'b>,
+{
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<'a, 'b, R> fmt::Debug for Read<'a, 'b, R>
+where
+    R: Resource<'b> + fmt::Debug + 'a,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner: &R = self;
+        f.debug_struct("Read").field("inner", inner).finish()
+    }
+}
+
+/// Mutable access to a resource, fetched as part of [SystemData].
+pub struct Write<'a, 'b, R> {
+    inner: RefMut<'a, 'b, R>,
+}
+
+impl<'a, 'b, R> Write<'a, 'b, R> {
+    /// Returns the underlying [RefMut].
+    pub fn into_inner(self) -> RefMut<'a, 'b, R> {
+        self.inner
+    }
+}
+
+impl<'a, 'b, R> Deref for Write<'a, 'b, R>
+where
+    R: Resource<'b>,
+{
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<'a, 'b, R> DerefMut for Write<'a, 'b, R>
+where
+    R: Resource<'b>,
+{
+    fn deref_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<'a, 'b, R> fmt::Debug for Write<'a, 'b, R>
+where
+    R: Resource<'b> + fmt::Debug + 'a,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner: &R = self;
+        f.debug_struct("Write").field("inner", inner).finish()
+    }
+}
+
+impl<'a, 'b, R> SystemData<'a, 'b> for Read<'a, 'b, R>
+where
+    R: Resource<'b>,
+{
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn try_fetch(resources: &'a Resources<'b>) -> Result<Self, BorrowError> {
+        resources.try_borrow::<R>().map(|inner| Read { inner })
+    }
+
+    fn reads() -> Vec<TypeId> {
+        vec![R::id()]
+    }
+
+    fn writes() -> Vec<TypeId> {
+        Vec::new()
+    }
+}
+
+impl<'a, 'b, R> SystemData<'a, 'b> for Write<'a, 'b, R>
+where
+    R: Resource<'b>,
+{
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn try_fetch(resources: &'a Resources<'b>) -> Result<Self, BorrowError> {
+        resources.try_borrow_mut::<R>().map(|inner| Write { inner })
+    }
+
+    fn reads() -> Vec<TypeId> {
+        Vec::new()
+    }
+
+    fn writes() -> Vec<TypeId> {
+        vec![R::id()]
+    }
+}
+
+impl<'a, 'b, R> SystemData<'a, 'b> for Option<Read<'a, 'b, R>>
+where
+    R: Resource<'b>,
+{
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn try_fetch(resources: &'a Resources<'b>) -> Result<Self, BorrowError> {
+        match resources.try_borrow::<R>() {
+            Ok(inner) => Ok(Some(Read { inner })),
+            Err(BorrowError::NotFound { .. }) => Ok(None),
+            Err(borrow_error) => Err(borrow_error),
+        }
+    }
+
+    fn reads() -> Vec<TypeId> {
+        vec![R::id()]
+    }
+
+    fn writes() -> Vec<TypeId> {
+        Vec::new()
+    }
+}
+
+impl<'a, 'b, R> SystemData<'a, 'b> for Option<Write<'a, 'b, R>>
+where
+    R: Resource<'b>,
+{
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    fn try_fetch(resources: &'a Resources<'b>) -> Result<Self, BorrowError> {
+        match resources.try_borrow_mut::<R>() {
+            Ok(inner) => Ok(Some(Write { inner })),
+            Err(BorrowError::NotFound { .. }) => Ok(None),
+            Err(borrow_error) => Err(borrow_error),
+        }
+    }
+
+    fn reads() -> Vec<TypeId> {
+        Vec::new()
+    }
+
+    fn writes() -> Vec<TypeId> {
+        vec![R::id()]
+    }
+}
+
+impl<'a, 'b> SystemData<'a, 'b> for () {
+    fn try_fetch(_: &'a Resources<'b>) -> Result<Self, BorrowError> {
+        Ok(())
+    }
+
+    fn reads() -> Vec<TypeId> {
+        Vec::new()
+    }
+
+    fn writes() -> Vec<TypeId> {
+        Vec::new()
+    }
+}
+
+macro_rules! impl_system_data {
+    ($($ty:ident),+) => {
+        impl<'a, 'b, $($ty),+> SystemData<'a, 'b> for ($($ty,)+)
+        where
+            $($ty: SystemData<'a, 'b>),+
+        {
+            #[cfg_attr(feature = "track_borrows", track_caller)]
+            fn try_fetch(resources: &'a Resources<'b>) -> Result<Self, BorrowError> {
+                // Borrows taken so far are dropped (and thereby released) when
+                // returning early.
+                Ok(($($ty::try_fetch(resources)?,)+))
+            }
+
+            fn reads() -> Vec<TypeId> {
+                [$($ty::reads()),+].concat()
+            }
+
+            fn writes() -> Vec<TypeId> {
+                [$($ty::writes()),+].concat()
+            }
+        }
+    };
+}
+
+impl_system_data!(A);
+impl_system_data!(A, B);
+impl_system_data!(A, B, C);
+impl_system_data!(A, B, C, D);
+impl_system_data!(A, B, C, D, E);
+impl_system_data!(A, B, C, D, E, F);
+impl_system_data!(A, B, C, D, E, F, G);
+impl_system_data!(A, B, C, D, E, F, G, H);
+impl_system_data!(A, B, C, D, E, F, G, H, I);
+impl_system_data!(A, B, C, D, E, F, G, H, I, J);
+impl_system_data!(A, B, C, D, E, F, G, H, I, J, K);
+impl_system_data!(A, B, C, D, E, F, G, H, I, J, K, L);
+impl_system_data!(A, B, C, D, E, F, G, H, I, J, K, L, M);
+impl_system_data!(A, B, C, D, E, F, G, H, I, J, K, L, M, N);
+impl_system_data!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O);
+impl_system_data!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P);
+
+#[cfg(test)]
+mod tests {
+    use better_any::Tid;
+
+    use crate::{BorrowError, BorrowMode, Read, Resources, SystemData, TypeNameLit, Write};
+
+    #[test]
+    fn fetch_borrows_all_resources() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        resources.insert(B(2));
+
+        let (a, mut b) = resources.fetch::<(Read<A>, Write<B>)>();
+        b.0 += a.0;
+        drop((a, b));
+
+        assert_eq!(B(3), *resources.borrow::<B>());
+    }
+
+    #[test]
+    fn fetch_optional_resource_returns_none_if_missing() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+
+        let (a, b, c) = resources.fetch::<(Read<A>, Option<Read<B>>, Option<Write<C>>)>();
+
+        assert_eq!(A(1), *a);
+        assert!(b.is_none());
+        assert!(c.is_none());
+    }
+
+    #[test]
+    fn try_fetch_releases_borrows_on_conflict() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        resources.insert(B(2));
+
+        let b = resources.borrow::<B>();
+        let error = resources
+            .try_fetch::<(Write<A>, Write<B>)>()
+            .expect_err("Expected fetch to fail.");
+        drop(b);
+
+        assert!(matches!(
+            error,
+            BorrowError::ConflictShared {
+                type_name,
+                label: None,
+                mode: BorrowMode::Exclusive,
+                ..
+            } if type_name == TypeNameLit::of::<B>()
+        ));
+        assert!(resources.try_borrow_mut::<A>().is_ok());
+    }
+
+    #[test]
+    fn try_fetch_optional_resource_returns_err_on_conflict() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+
+        let _a = resources.borrow_mut::<A>();
+
+        assert!(resources.try_fetch::<Option<Read<A>>>().is_err());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Expected to borrow `stateman::system_data::tests::A`, but it does not exist."
+    )]
+    fn fetch_missing_resource_panics() {
+        let resources = Resources::default();
+
+        resources.fetch::<(Read<A>,)>();
+    }
+
+    #[test]
+    fn reads_and_writes_list_resource_ids() {
+        type Data<'a, 'b> = (Read<'a, 'b, A>, Option<Write<'a, 'b, B>>, Write<'a, 'b, C>);
+
+        assert_eq!(vec![A::id()], Data::reads());
+        assert_eq!(vec![B::id(), C::id()], Data::writes());
+    }
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct A(usize);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct B(usize);
+
+    #[derive(Debug, PartialEq, Tid)]
+    struct C(usize);
+}
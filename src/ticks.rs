@@ -0,0 +1,133 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::{poison::Poison, TypeNameLit};
+
+/// Source of [`Ticks::version`]s, which is shared by all maps so a slot never
+/// gets the same version twice, even if it is removed and inserted again.
+static VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Ticks at which a resource was added and last changed, together with its
+/// type name, poison flag and number of shared borrows.
+///
+/// The ticks are atomic, so they can be updated through the shared references
+/// held by [Ref][crate::Ref] and [RefMut][crate::RefMut].
+#[derive(Debug)]
+pub(crate) struct Ticks {
+    added: AtomicU64,
+    changed: AtomicU64,
+    version: AtomicU64,
+    poison: Poison,
+    type_name: TypeNameLit,
+    shared: AtomicUsize,
+}
+
+impl Ticks {
+    /// Returns ticks for a resource of type `type_name` which is added at
+    /// `tick`.
+    pub(crate) fn new(tick: u64, type_name: TypeNameLit) -> Self {
+        Self {
+            added: AtomicU64::new(tick),
+            changed: AtomicU64::new(tick),
+            version: AtomicU64::new(next_version()),
+            poison: Poison::default(),
+            type_name,
+            shared: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns ticks with the same ticks and version, which are used for a
+    /// working copy of the resource within a [Transaction][crate::Transaction].
+    pub(crate) fn duplicate(&self) -> Self {
+        Self {
+            added: AtomicU64::new(self.added()),
+            changed: AtomicU64::new(self.changed()),
+            version: AtomicU64::new(self.version()),
+            poison: Poison::default(),
+            type_name: self.type_name,
+            shared: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn type_name(&self) -> TypeNameLit {
+        self.type_name
+    }
+
+    pub(crate) fn added(&self) -> u64 {
+        self.added.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn changed(&self) -> u64 {
+        self.changed.load(Ordering::Relaxed)
+    }
+
+    /// Returns a value which differs after every change, even if the
+    /// resource is changed multiple times within the same tick.
+    pub(crate) fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_changed(&self, tick: u64) {
+        self.changed.store(tick, Ordering::Relaxed);
+        self.version.store(next_version(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn poison(&self) -> &Poison {
+        &self.poison
+    }
+
+    /// Returns the number of live shared borrows counted with
+    /// [`count_shared`].
+    ///
+    /// [`count_shared`]: Self::count_shared
+    pub(crate) fn shared(&self) -> usize {
+        self.shared.load(Ordering::Relaxed)
+    }
+
+    /// Counts a shared borrow of the resource until the returned guard is
+    /// dropped.
+    pub(crate) fn count_shared(&self) -> SharedCount<'_> {
+        SharedCount::new(&self.shared)
+    }
+}
+
+/// Shared borrow counted in [Ticks], which is released on drop.
+#[derive(Debug)]
+pub(crate) struct SharedCount<'a>(&'a AtomicUsize);
+
+impl<'a> SharedCount<'a> {
+    fn new(shared: &'a AtomicUsize) -> Self {
+        shared.fetch_add(1, Ordering::Relaxed);
+        Self(shared)
+    }
+}
+
+impl<'a> Clone for SharedCount<'a> {
+    fn clone(&self) -> Self {
+        Self::new(self.0)
+    }
+}
+
+impl<'a> Drop for SharedCount<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Change ticks of a borrowed resource together with the world tick at which
+/// it was borrowed.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TickRef<'a> {
+    pub(crate) ticks: &'a Ticks,
+    pub(crate) tick: u64,
+}
+
+impl<'a> TickRef<'a> {
+    /// Marks the resource as changed at the tick it was borrowed at.
+    pub(crate) fn set_changed(self) {
+        self.ticks.set_changed(self.tick);
+    }
+}
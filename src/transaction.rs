@@ -0,0 +1,549 @@
+use std::{any::TypeId, collections::HashMap};
+
+use better_any::{Tid, TidExt};
+use rt_map::{BorrowFail, RtMap};
+
+use crate::{
+    ticks::{TickRef, Ticks},
+    BorrowError, BorrowMode, Ref, RefMut, Resource, Resources, TypeNameLit,
+};
+
+/// Clones a type erased resource, see
+/// [`Resources::register_clone`][crate::Resources::register_clone].
+///
+/// Returns `None` if the resource is not of the registered type.
+pub(crate) type CloneFn<'a> = fn(&dyn Resource<'a>) -> Option<Box<dyn Resource<'a>>>;
+
+pub(crate) fn clone_resource<'a, R>(resource: &dyn Resource<'a>) -> Option<Box<dyn Resource<'a>>>
+where
+    R: Resource<'a> + Clone,
+{
+    let resource = resource.downcast_ref::<R>()?;
+
+    Some(Box::new(resource.clone()))
+}
+
+/// Resources changed within a [Transaction], see
+/// [`Resources::transaction`][crate::Resources::transaction].
+///
+/// Before a resource is borrowed mutably for the first time, its value is
+/// cloned, and the transaction works on the copy. The copies are only written
+/// back if the transaction succeeds and they were mutably dereferenced, so
+/// the resources stay untouched if it fails.
+pub struct Transaction<'t, 'a> {
+    resources: &'t mut Resources<'a>,
+    /// Working copies of the resources borrowed mutably within the
+    /// transaction, one layer per savepoint.
+    layers: Vec<Layer<'a>>,
+}
+
+/// Working copies of the resources within one layer of a [Transaction].
+struct Layer<'a> {
+    /// Copies of the resources, or [Unchanged] if a resource was not
+    /// borrowed mutably within the layer.
+    copies: RtMap<TypeId, Box<dyn Resource<'a>>>,
+    /// Ticks of the resources as seen within the layer, which are marked as
+    /// changed when a copy is mutably dereferenced.
+    ticks: HashMap<TypeId, Ticks>,
+}
+
+impl<'a> Layer<'a> {
+    fn new<'l>(ticks: impl Iterator<Item = (TypeId, &'l Ticks)>) -> Self {
+        let mut copies = RtMap::default();
+        let ticks = ticks
+            .map(|(type_id, ticks)| {
+                copies.insert(type_id, Box::new(Unchanged) as Box<dyn Resource<'a>>);
+                (type_id, ticks.duplicate())
+            })
+            .collect();
+
+        Self { copies, ticks }
+    }
+
+    /// Returns the copies of the layer which were changed, i.e. whose
+    /// version differs from the one in `lower`, together with their ticks.
+    fn changed_copies<'l>(
+        self,
+        lower: impl Fn(TypeId) -> Option<&'l Ticks>,
+    ) -> Vec<(TypeId, Box<dyn Resource<'a>>, Ticks)> {
+        let mut ticks = self.ticks;
+        self.copies
+            .into_inner()
+            .into_iter()
+            .filter_map(|(type_id, copy)| {
+                let copy = copy.into_inner();
+                let ticks = ticks.remove(&type_id)?;
+                let changed = match lower(type_id) {
+                    Some(lower) => lower.version() != ticks.version(),
+                    None => true,
+                };
+
+                ((*copy).downcast_ref::<Unchanged>().is_none() && changed)
+                    .then_some((type_id, copy, ticks))
+            })
+            .collect()
+    }
+}
+
+/// Working copy of a resource borrowed from a [Layer] together with its ticks.
+type CopyRef<'l, 'a> = (rt_map::Ref<'l, Box<dyn Resource<'a>>>, &'l Ticks);
+
+/// Placeholder of a resource which was not borrowed mutably within a layer.
+#[derive(Tid)]
+struct Unchanged;
+
+impl<'t, 'a> Transaction<'t, 'a> {
+    pub(crate) fn new(resources: &'t mut Resources<'a>) -> Self {
+        let layer = Layer::new(
+            resources
+                .cloneable_ids()
+                .into_iter()
+                .filter_map(|type_id| Some((type_id, resources.ticks_of(type_id)?))),
+        );
+
+        Self {
+            resources,
+            layers: vec![layer],
+        }
+    }
+
+    /// Writes the changes made within the transaction back to the resources.
+    pub(crate) fn commit(mut self) {
+        let layer = self
+            .layers
+            .pop()
+            .expect("Expected transaction to have a layer.");
+        let resources = &*self.resources;
+        let changed = layer.changed_copies(|type_id| resources.ticks_of(type_id));
+        changed.into_iter().for_each(|(type_id, copy, _)| {
+            self.resources.write_back(type_id, copy);
+        });
+    }
+
+    /// Returns the `R` resource.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist.
+    /// Panics if the resource is being accessed mutably.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow<R>(&self) -> Ref<'_, 'a, R>
+    where
+        R: Resource<'a>,
+    {
+        self.try_borrow::<R>()
+            .unwrap_or_else(|borrow_error| panic!("{borrow_error}"))
+    }
+
+    /// Returns an immutable reference to `R` if it exists, which reflects the
+    /// changes made within the transaction.
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow<R>(&self) -> Result<Ref<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        match Self::find_copy::<R>(&self.layers, BorrowMode::Shared)? {
+            Some((copy, ticks)) => Ok(Ref::<R>::new(copy).with_ticks(ticks)),
+            None => self.resources.try_borrow::<R>(),
+        }
+    }
+
+    /// Returns a mutable reference to `R`, whose changes are only kept if the
+    /// transaction succeeds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist.
+    /// Panics if the resource is already accessed.
+    /// Panics if `R` is not registered with
+    /// [`Resources::register_clone`][crate::Resources::register_clone].
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn borrow_mut<R>(&self) -> RefMut<'_, 'a, R>
+    where
+        R: Resource<'a>,
+    {
+        self.try_borrow_mut::<R>()
+            .unwrap_or_else(|borrow_error| panic!("{borrow_error}"))
+    }
+
+    /// Returns a mutable reference to `R` if it exists, whose changes are only
+    /// kept if the transaction succeeds.
+    ///
+    /// Returns [`BorrowError::NotRegistered`] if `R` is not registered with
+    /// [`Resources::register_clone`][crate::Resources::register_clone].
+    #[cfg_attr(feature = "track_borrows", track_caller)]
+    pub fn try_borrow_mut<R>(&self) -> Result<RefMut<'_, 'a, R>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        // Also checks that the resource may be borrowed mutably at all.
+        let original = self.resources.try_borrow_mut::<R>()?;
+
+        let (layer, lower) = self
+            .layers
+            .split_last()
+            .expect("Expected transaction to have a layer.");
+        let Some(ticks) = layer.ticks.get(&R::id()) else {
+            return Err(BorrowError::NotRegistered {
+                type_name: TypeNameLit::of::<R>(),
+                label: None,
+            });
+        };
+
+        let mut copy = layer
+            .copies
+            .try_borrow_mut(&R::id())
+            .map_err(|borrow_fail| {
+                Self::borrow_error::<R>(&layer.copies, borrow_fail, BorrowMode::Exclusive)
+            })?;
+        if (**copy).downcast_ref::<Unchanged>().is_some() {
+            let clone = self.resources.clone_fn(R::id()).expect(
+                "Expected copied resource to be registered with `Resources::register_clone`.",
+            );
+            let clone = match Self::find_copy::<R>(lower, BorrowMode::Exclusive)? {
+                Some((lower_copy, _)) => clone(&**lower_copy),
+                None => clone(&*original),
+            };
+            *copy = clone.ok_or(BorrowError::TypeMismatch {
+                type_name: TypeNameLit::of::<R>(),
+                label: None,
+                mode: BorrowMode::Exclusive,
+            })?;
+        }
+
+        let ticks = TickRef {
+            ticks,
+            tick: self.resources.tick(),
+        };
+        Ok(RefMut::<R>::new(copy).with_ticks(ticks))
+    }
+
+    /// Runs `f` within a savepoint.
+    ///
+    /// If `f` returns `Err`, the changes made since the savepoint are undone,
+    /// while earlier changes of the transaction are kept.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use better_any::Tid;
+    /// use stateman::Resources;
+    ///
+    /// #[derive(Clone, Debug, Tid)]
+    /// struct Gold(u32);
+    ///
+    /// let mut resources = Resources::default();
+    /// resources.insert(Gold(10));
+    /// resources.register_clone::<Gold>();
+    ///
+    /// let result: Result<(), ()> = resources.transaction(|tx| {
+    ///     tx.borrow_mut::<Gold>().0 -= 5;
+    ///
+    ///     let purchase: Result<(), ()> = tx.savepoint(|tx| {
+    ///         tx.borrow_mut::<Gold>().0 -= 5;
+    ///         Err(())
+    ///     });
+    ///     assert!(purchase.is_err());
+    ///     assert_eq!(5, tx.borrow::<Gold>().0);
+    ///
+    ///     Ok(())
+    /// });
+    ///
+    /// assert!(result.is_ok());
+    /// assert_eq!(5, resources.borrow::<Gold>().0);
+    /// ```
+    pub fn savepoint<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Self) -> Result<T, E>,
+    {
+        let parent = self
+            .layers
+            .last()
+            .expect("Expected transaction to have a layer.");
+        let layer = Layer::new(
+            parent
+                .ticks
+                .iter()
+                .map(|(type_id, ticks)| (*type_id, ticks)),
+        );
+        self.layers.push(layer);
+
+        let result = f(self);
+
+        let layer = self
+            .layers
+            .pop()
+            .expect("Expected savepoint to have a layer.");
+        if result.is_ok() {
+            let parent = self
+                .layers
+                .last_mut()
+                .expect("Expected transaction to have a layer.");
+            let changed = layer.changed_copies(|type_id| parent.ticks.get(&type_id));
+            changed.into_iter().for_each(|(type_id, copy, ticks)| {
+                parent.copies.insert(type_id, copy);
+                parent.ticks.insert(type_id, ticks);
+            });
+        }
+
+        result
+    }
+
+    /// Returns the most recent working copy of `R` in `layers` together with
+    /// its ticks, or `None` if `R` was not borrowed mutably within them.
+    fn find_copy<'l, R>(
+        layers: &'l [Layer<'a>],
+        mode: BorrowMode,
+    ) -> Result<Option<CopyRef<'l, 'a>>, BorrowError>
+    where
+        R: Resource<'a>,
+    {
+        for layer in layers.iter().rev() {
+            let Some(ticks) = layer.ticks.get(&R::id()) else {
+                break;
+            };
+
+            let copy = layer
+                .copies
+                .try_borrow(&R::id())
+                .map_err(|borrow_fail| Self::borrow_error::<R>(&layer.copies, borrow_fail, mode))?;
+            if (**copy).downcast_ref::<Unchanged>().is_none() {
+                return Ok(Some((copy, ticks)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn borrow_error<R>(
+        layer: &RtMap<TypeId, Box<dyn Resource<'a>>>,
+        borrow_fail: BorrowFail,
+        mode: BorrowMode,
+    ) -> BorrowError
+    where
+        R: Resource<'a>,
+    {
+        BorrowError::from_fail(
+            borrow_fail,
+            layer.get_raw(&R::id()),
+            (R::id(), None),
+            TypeNameLit::of::<R>(),
+            mode,
+            #[cfg(feature = "track_borrows")]
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+    };
+
+    use better_any::Tid;
+
+    use crate::{BorrowError, Ref, RefMut, Resources};
+
+    fn resources() -> Resources<'static> {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+        resources.insert(B(1));
+        resources.register_clone::<A>();
+        resources.register_clone::<B>();
+        resources
+    }
+
+    #[test]
+    fn ok_keeps_changes() {
+        let mut resources = resources();
+
+        let result: Result<u32, ()> = resources.transaction(|tx| {
+            tx.borrow_mut::<A>().0 = 2;
+            let mut b = tx.borrow_mut::<B>();
+            b.0 += tx.borrow::<A>().0;
+            Ok(b.0)
+        });
+
+        assert_eq!(Ok(3), result);
+        assert_eq!(&A(2), &*resources.borrow::<A>());
+        assert_eq!(&B(3), &*resources.borrow::<B>());
+    }
+
+    #[test]
+    fn err_restores_original_values() {
+        let mut resources = resources();
+        resources.increment_tick();
+        let changed_tick = Ref::changed_tick(&resources.borrow::<A>());
+
+        let result: Result<(), &str> = resources.transaction(|tx| {
+            tx.borrow_mut::<A>().0 = 2;
+            tx.borrow_mut::<A>().0 = 3;
+            assert_eq!(&A(3), &*tx.borrow::<A>());
+            Err("failed")
+        });
+
+        assert_eq!(Err("failed"), result);
+        let a = resources.borrow::<A>();
+        assert_eq!(&A(1), &*a);
+        assert_eq!(Some(0), changed_tick);
+        assert_eq!(changed_tick, Ref::changed_tick(&a));
+    }
+
+    #[test]
+    fn copies_report_their_changes_within_transaction() {
+        let mut resources = resources();
+        let since = resources.increment_tick();
+
+        let result: Result<(), ()> = resources.transaction(|tx| {
+            drop(tx.borrow_mut::<A>());
+            assert!(!Ref::is_changed(&tx.borrow::<A>(), since - 1));
+            assert_eq!(Some(0), Ref::changed_tick(&tx.borrow::<B>()));
+
+            tx.borrow_mut::<A>().0 = 2;
+            assert!(Ref::is_changed(&tx.borrow::<A>(), since - 1));
+            assert_eq!(Some(since), Ref::changed_tick(&tx.borrow::<A>()));
+            Ok(())
+        });
+
+        assert!(result.is_ok());
+        assert_eq!(Some(since), Ref::changed_tick(&resources.borrow::<A>()));
+    }
+
+    #[test]
+    fn untouched_copies_are_not_written_back() {
+        let mut resources = resources();
+        let changed = Arc::new(AtomicU32::new(0));
+        let observed = Arc::clone(&changed);
+        resources.on_change::<A>(move |_| {
+            observed.fetch_add(1, Ordering::SeqCst);
+        });
+        let since = resources.increment_tick();
+
+        let result: Result<(), ()> = resources.transaction(|tx| {
+            assert_eq!(1, tx.borrow_mut::<A>().0);
+            tx.savepoint(|tx| {
+                RefMut::bypass_change_detection(&mut tx.borrow_mut::<B>()).0 = 2;
+                Ok(())
+            })
+        });
+
+        assert!(result.is_ok());
+        assert!(!resources.is_changed::<A>(since - 1));
+        assert!(!resources.is_changed::<B>(since - 1));
+        assert_eq!(&B(1), &*resources.borrow::<B>());
+        assert_eq!(0, changed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn panic_restores_original_values() {
+        let mut resources = resources();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            resources.transaction(|tx| -> Result<(), ()> {
+                tx.borrow_mut::<A>().0 = 2;
+                panic!("failed");
+            })
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(&A(1), &*resources.borrow::<A>());
+    }
+
+    #[test]
+    fn failed_savepoint_restores_values_at_savepoint() {
+        let mut resources = resources();
+
+        let result: Result<(), ()> = resources.transaction(|tx| {
+            tx.borrow_mut::<A>().0 = 2;
+
+            let savepoint: Result<(), ()> = tx.savepoint(|tx| {
+                tx.borrow_mut::<A>().0 = 3;
+                tx.borrow_mut::<B>().0 = 3;
+                Err(())
+            });
+            assert!(savepoint.is_err());
+
+            assert_eq!(&A(2), &*tx.borrow::<A>());
+            assert_eq!(&B(1), &*tx.borrow::<B>());
+            Ok(())
+        });
+
+        assert!(result.is_ok());
+        assert_eq!(&A(2), &*resources.borrow::<A>());
+    }
+
+    #[test]
+    fn failed_transaction_restores_changes_of_successful_savepoint() {
+        let mut resources = resources();
+
+        let result: Result<(), ()> = resources.transaction(|tx| {
+            let savepoint: Result<(), ()> = tx.savepoint(|tx| {
+                tx.borrow_mut::<B>().0 = 3;
+                Ok(())
+            });
+            assert!(savepoint.is_ok());
+            Err(())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(&B(1), &*resources.borrow::<B>());
+    }
+
+    #[test]
+    fn borrow_mut_of_unregistered_resource_returns_err() {
+        let mut resources = Resources::default();
+        resources.insert(A(1));
+
+        let result = resources.transaction(|tx| tx.try_borrow_mut::<A>().map(|_| ()));
+
+        assert!(matches!(result, Err(BorrowError::NotRegistered { .. })));
+    }
+
+    #[test]
+    fn changes_are_not_visible_or_observed_until_commit() {
+        let mut resources = resources();
+        let replaced = Arc::new(AtomicU32::new(0));
+        let observed = Arc::clone(&replaced);
+        resources.on_replace::<A>(move |_, _, _| {
+            observed.fetch_add(1, Ordering::SeqCst);
+        });
+        let changed = Arc::new(AtomicU32::new(0));
+        let observed = Arc::clone(&changed);
+        resources.on_change::<A>(move |a| {
+            observed.fetch_add(a.0, Ordering::SeqCst);
+        });
+
+        let result: Result<(), ()> = resources.transaction(|tx| {
+            let mut a = tx.borrow_mut::<A>();
+            a.0 = 2;
+            assert!(tx.try_borrow::<A>().is_err());
+            drop(a);
+
+            assert_eq!(&A(2), &*tx.borrow::<A>());
+            assert_eq!(0, changed.load(Ordering::SeqCst));
+            Err(())
+        });
+        assert!(result.is_err());
+        assert_eq!(0, changed.load(Ordering::SeqCst));
+
+        let result: Result<(), ()> = resources.transaction(|tx| {
+            tx.borrow_mut::<A>().0 = 3;
+            Ok(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(3, changed.load(Ordering::SeqCst));
+        assert_eq!(0, replaced.load(Ordering::SeqCst));
+        assert_eq!(&A(3), &*resources.borrow::<A>());
+    }
+
+    #[derive(Clone, Debug, PartialEq, Tid)]
+    struct A(u32);
+
+    #[derive(Clone, Debug, PartialEq, Tid)]
+    struct B(u32);
+}
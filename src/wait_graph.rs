@@ -0,0 +1,78 @@
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread::{self, ThreadId},
+};
+
+use crate::slot::Slot;
+
+/// Resources the threads of a [SyncResources][crate::SyncResources] map are
+/// waiting for, used to detect deadlocks.
+#[derive(Debug, Default)]
+pub(crate) struct WaitGraph {
+    waiting: Mutex<HashMap<ThreadId, TypeId>>,
+}
+
+impl WaitGraph {
+    /// Registers the current thread as waiting for `type_id` until the
+    /// returned guard is dropped.
+    ///
+    /// Returns `None` if waiting would deadlock, i.e. if a thread holding
+    /// `type_id` is directly or indirectly waiting for a resource held by the
+    /// current thread.
+    pub(crate) fn wait<'g>(
+        &'g self,
+        type_id: TypeId,
+        slots: &HashMap<TypeId, Slot<'_>>,
+    ) -> Option<Waiting<'g>> {
+        let thread = thread::current().id();
+        let mut waiting = self.lock();
+
+        let mut visited = HashSet::from([type_id]);
+        let mut pending = vec![type_id];
+        while let Some(type_id) = pending.pop() {
+            let holders = slots.get(&type_id).map(Slot::holders).unwrap_or_default();
+            for holder in holders {
+                if holder == thread {
+                    return None;
+                }
+                if let Some(&waited_for) = waiting.get(&holder) {
+                    if visited.insert(waited_for) {
+                        pending.push(waited_for);
+                    }
+                }
+            }
+        }
+
+        waiting.insert(thread, type_id);
+        Some(Waiting {
+            graph: self,
+            thread,
+        })
+    }
+
+    /// Returns true if a thread is waiting for `type_id`.
+    #[cfg(test)]
+    pub(crate) fn is_waited_for(&self, type_id: TypeId) -> bool {
+        self.lock()
+            .values()
+            .any(|waited_for| *waited_for == type_id)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<ThreadId, TypeId>> {
+        self.waiting.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Wait of a thread registered in a [WaitGraph], removed on drop.
+pub(crate) struct Waiting<'g> {
+    graph: &'g WaitGraph,
+    thread: ThreadId,
+}
+
+impl<'g> Drop for Waiting<'g> {
+    fn drop(&mut self) {
+        self.graph.lock().remove(&self.thread);
+    }
+}